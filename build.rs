@@ -0,0 +1,18 @@
+use std::env;
+use std::path::PathBuf;
+
+/// The IDL files of the protocols, and the files in `OUT_DIR` their code is generated into.
+const PROTOCOLS: &[(&str, &str)] = &[
+    ("src/bin/chat/protocol/chat.idl", "chat_protocol.rs"),
+    ("src/bin/protocol/ping.idl", "ping_protocol.rs"),
+];
+
+fn main() {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+    for (idl, generated) in PROTOCOLS {
+        println!("cargo:rerun-if-changed={idl}");
+
+        idl::compile(idl, out_dir.join(generated)).unwrap_or_else(|e| panic!("{e}"));
+    }
+}
@@ -0,0 +1,121 @@
+//! Commands identified by a `u8` id, sent at the start of a stream before their input.
+//!
+//! The commands of a protocol are listed by an enum deriving `Command`, which is a [Payload]
+//! encoded as the id. For every variant, the derive generates a marker type implementing [Command]
+//! in a module named after the enum, like `server_command::Login` for `ServerCommand::Login`, whose
+//! methods only accept the input and output declared with `#[command(input = T, output = U)]`:
+//!
+//! ```text
+//! match ServerCommand::read_from_recv_stream(&mut recv).await? {
+//!     ServerCommand::Login => {
+//!         let input = server_command::Login::read_input(&mut recv, &limits).await?;
+//!         let output = login(input).await;
+//!         server_command::Login::write_output(&mut send, &output).await?;
+//!     }
+//!     ServerCommand::Unknown(id) => { ... }
+//! }
+//! ```
+
+use std::borrow::Borrow;
+use std::future::Future;
+
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    AsyncRead, AsyncWrite, BytesMut, DecodeContext, DecodeLimits, Encode, Encoded, Payload,
+    PayloadError,
+};
+
+/// A command of an enum deriving `Command`, with the types of its input and output.
+///
+/// Implemented by the marker types generated by the derive.
+pub trait Command {
+    /// The enum listing the command.
+    type Kind: Payload + Copy + Into<u8> + Send + Sync + 'static;
+    /// The variant of [Command::Kind] identifying the command.
+    const KIND: Self::Kind;
+
+    /// Sent after the id by the peer issuing the command.
+    type Input: Payload + Send + Sync;
+    /// Sent back by the peer handling the command.
+    type Output: Payload + Send + Sync;
+
+    /// Writes the id of the command followed by `input`, with a single `write_all`.
+    ///
+    /// `input` can be a borrowed form of [Command::Input] encoded the same way, like a slice for a
+    /// `Vec`.
+    fn write_input<W, T>(
+        send: &mut W,
+        input: &T,
+    ) -> impl Future<Output = Result<(), PayloadError>> + Send
+    where
+        W: AsyncWrite + Unpin + Send + ?Sized,
+        T: Encode + Sync + ?Sized,
+        Self::Input: Borrow<T>,
+    {
+        async move {
+            let mut buf = BytesMut::with_capacity(Self::KIND.encoded_len() + input.encoded_len());
+            Self::KIND.encode(&mut buf)?;
+            input.encode(&mut buf)?;
+            send.write_all(&buf).await?;
+
+            Ok(())
+        }
+    }
+
+    /// Writes the id of the command followed by an input encoded beforehand, to send the same
+    /// input to many peers.
+    fn write_encoded_input<W, T>(
+        send: &mut W,
+        input: &Encoded<T>,
+    ) -> impl Future<Output = Result<(), PayloadError>> + Send
+    where
+        W: AsyncWrite + Unpin + Send + ?Sized,
+        T: ?Sized,
+        Self::Input: Borrow<T>,
+    {
+        async move {
+            let mut buf = BytesMut::with_capacity(Self::KIND.encoded_len() + input.bytes().len());
+            Self::KIND.encode(&mut buf)?;
+            input.encode(&mut buf)?;
+            send.write_all(&buf).await?;
+
+            Ok(())
+        }
+    }
+
+    /// Reads the input of the command, once its id was read as a [Command::Kind].
+    fn read_input<R>(
+        recv: &mut R,
+        limits: &DecodeLimits,
+    ) -> impl Future<Output = Result<Self::Input, PayloadError>> + Send
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let limits = *limits;
+
+        async move { Self::Input::read_with(recv, &mut DecodeContext::new(limits)).await }
+    }
+
+    fn write_output<W>(
+        send: &mut W,
+        output: &Self::Output,
+    ) -> impl Future<Output = Result<(), PayloadError>> + Send
+    where
+        W: AsyncWrite + Unpin + Send + ?Sized,
+    {
+        output.write_to_send_stream(send)
+    }
+
+    fn read_output<R>(
+        recv: &mut R,
+        limits: &DecodeLimits,
+    ) -> impl Future<Output = Result<Self::Output, PayloadError>> + Send
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let limits = *limits;
+
+        async move { Self::Output::read_with(recv, &mut DecodeContext::new(limits)).await }
+    }
+}
@@ -0,0 +1,77 @@
+//! Values sent in QUIC datagrams, which are neither ordered nor retransmitted.
+//!
+//! Fits values that are stale once lost, such as presence pings, typing indicators or latency
+//! probes. A datagram holds exactly one value, which must fit in
+//! [Connection::max_datagram_size].
+
+use std::marker::PhantomData;
+
+use quinn::Connection;
+
+use crate::{DecodeContext, DecodeLimits, Encode, Payload, PayloadError};
+
+/// Sends `value` in a single datagram.
+///
+/// Fails with [PayloadError::DatagramTooLarge] before encoding the value if it doesn't fit in a
+/// datagram, and with [PayloadError::SendDatagram] if the peer doesn't accept datagrams.
+pub fn send_datagram<T>(connection: &Connection, value: &T) -> Result<(), PayloadError>
+where
+    T: Encode + ?Sized,
+{
+    let len = value.encoded_len();
+    if let Some(max) = connection.max_datagram_size() {
+        if len > max {
+            return Err(PayloadError::DatagramTooLarge { len, max });
+        }
+    }
+
+    connection.send_datagram(value.to_bytes()?)?;
+
+    Ok(())
+}
+
+/// Receives the values of `T` sent in datagrams on a connection.
+///
+/// A receive loop usually stops once [PayloadError::is_transport] and skips the other errors,
+/// which only concern a single datagram.
+#[derive(Debug)]
+pub struct DatagramRecv<T> {
+    connection: Connection,
+    limits: DecodeLimits,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> DatagramRecv<T>
+where
+    T: Payload,
+{
+    pub fn new(connection: Connection) -> DatagramRecv<T> {
+        DatagramRecv::with_limits(connection, DecodeLimits::default())
+    }
+
+    pub fn with_limits(connection: Connection, limits: DecodeLimits) -> DatagramRecv<T> {
+        DatagramRecv {
+            connection,
+            limits,
+            _type: PhantomData,
+        }
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Waits for the next datagram and decodes its value.
+    ///
+    /// A datagram that can't be decoded only fails its own call, the next call waits for the next
+    /// datagram. Once the connection is closed, every call fails with [PayloadError::Connection].
+    pub async fn recv(&mut self) -> Result<T, PayloadError> {
+        let mut datagram = self.connection.read_datagram().await?;
+        let value = T::decode_with(&mut datagram, &mut DecodeContext::new(self.limits))?;
+
+        match datagram.len() {
+            0 => Ok(value),
+            len => Err(PayloadError::TrailingBytes { len }),
+        }
+    }
+}
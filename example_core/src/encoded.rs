@@ -0,0 +1,80 @@
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+
+use bytes::BufMut;
+use tokio::io::AsyncWriteExt;
+
+use crate::{AsyncWrite, Bytes, BytesMut, Encode, PayloadError};
+
+/// A value of type `T` encoded once, to be sent many times.
+///
+/// Cloning only increments the reference count of the underlying [Bytes], and writing it to a
+/// stream writes these bytes directly, so broadcasting a value to N peers costs a single encoding.
+pub struct Encoded<T: ?Sized> {
+    bytes: Bytes,
+    _type: PhantomData<fn(&T)>,
+}
+
+impl<T> Encoded<T>
+where
+    T: Encode + ?Sized,
+{
+    pub fn new(value: &T) -> Result<Encoded<T>, PayloadError> {
+        Ok(Encoded {
+            bytes: value.to_bytes()?,
+            _type: PhantomData,
+        })
+    }
+}
+
+impl<T: ?Sized> Encoded<T> {
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.bytes
+    }
+}
+
+impl<T: ?Sized> Clone for Encoded<T> {
+    fn clone(&self) -> Self {
+        Encoded {
+            bytes: self.bytes.clone(),
+            _type: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Debug for Encoded<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Encoded").field(&self.bytes).finish()
+    }
+}
+
+/// Encoded exactly like `T`.
+impl<T: ?Sized> Encode for Encoded<T> {
+    fn encoded_len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        buf.put_slice(&self.bytes);
+
+        Ok(())
+    }
+
+    fn to_bytes(&self) -> Result<Bytes, PayloadError> {
+        Ok(self.bytes.clone())
+    }
+
+    async fn write_to_send_stream<W>(&self, send: &mut W) -> Result<(), PayloadError>
+    where
+        Self: Sync,
+        W: AsyncWrite + Unpin + Send + ?Sized,
+    {
+        send.write_all(&self.bytes).await?;
+
+        Ok(())
+    }
+}
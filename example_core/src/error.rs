@@ -0,0 +1,226 @@
+//! The error returned when a [Payload](crate::Payload) can't be encoded or decoded.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+use std::str::Utf8Error;
+
+use quinn::{ConnectionError, ReadError, SendDatagramError, WriteError};
+
+use crate::DecodeLimitError;
+
+#[derive(Debug)]
+pub enum PayloadError {
+    /// The data ended before the value was complete.
+    UnexpectedEnd,
+    /// A string isn't valid UTF-8.
+    InvalidUtf8(Utf8Error),
+    /// An enum discriminant doesn't match any variant.
+    UnknownDiscriminant { ty: &'static str, value: u8 },
+    /// A length doesn't fit in its prefix, or in a `usize` once decoded.
+    LengthOverflow { len: u64, ty: &'static str },
+    /// An integer doesn't fit in the type it is decoded to or encoded as.
+    IntegerOverflow { value: i128, ty: &'static str },
+    /// A field of a `#[payload(versioned)]` struct that isn't marked `default` wasn't sent.
+    MissingField,
+    /// The bytes don't hold a valid value of the type, like a `bool` other than 0 or 1.
+    InvalidValue { value: u64, ty: &'static str },
+    /// A frame or a datagram holds `len` more bytes than its value used.
+    TrailingBytes { len: usize },
+    /// A value of `len` bytes doesn't fit in a datagram of at most `max` bytes.
+    DatagramTooLarge { len: usize, max: usize },
+    /// A [DecodeLimits](crate::DecodeLimits) bound was exceeded.
+    Limit(DecodeLimitError),
+    /// Reading from a QUIC stream failed.
+    Read(ReadError),
+    /// Writing to a QUIC stream failed.
+    Write(WriteError),
+    /// Sending a QUIC datagram failed.
+    SendDatagram(SendDatagramError),
+    /// The QUIC connection was lost while waiting for a datagram.
+    Connection(ConnectionError),
+    /// Reading from or writing to another kind of stream failed.
+    Io(std::io::Error),
+    /// Decoding a field failed, `path` is the field prefixed by its type, like `Message.sent_by`.
+    Field {
+        path: String,
+        source: Box<PayloadError>,
+    },
+    /// An error raised by a `#[payload(with = "...")]` module or a manual impl.
+    Custom(String),
+}
+
+impl PayloadError {
+    pub fn custom(message: impl Display) -> PayloadError {
+        PayloadError::Custom(message.to_string())
+    }
+
+    /// Adds the field being decoded to the error, `field` being prefixed by its type like
+    /// `Message.sent_by`.
+    ///
+    /// The fields of nested types are joined, so that the error reads
+    /// `failed decoding Outer.inner.field: ...` rather than repeating the type of each field.
+    pub fn in_field(self, field: &str) -> PayloadError {
+        match self {
+            PayloadError::Field { path, source } => {
+                let nested = path.split_once('.').map_or(path.as_str(), |(_, rest)| rest);
+
+                PayloadError::Field {
+                    path: format!("{field}.{nested}"),
+                    source,
+                }
+            }
+            e => PayloadError::Field {
+                path: field.to_string(),
+                source: Box::new(e),
+            },
+        }
+    }
+
+    /// The error without the fields context.
+    pub fn kind(&self) -> &PayloadError {
+        match self {
+            PayloadError::Field { source, .. } => source.kind(),
+            e => e,
+        }
+    }
+
+    /// Whether the data ended early, which is expected when a peer finishes its stream.
+    pub fn is_unexpected_end(&self) -> bool {
+        matches!(self.kind(), PayloadError::UnexpectedEnd)
+    }
+
+    /// Whether the stream or the connection failed, as opposed to the peer sending invalid data.
+    pub fn is_transport(&self) -> bool {
+        matches!(
+            self.kind(),
+            PayloadError::Read(_)
+                | PayloadError::Write(_)
+                | PayloadError::SendDatagram(_)
+                | PayloadError::Connection(_)
+                | PayloadError::Io(_)
+        )
+    }
+}
+
+impl Display for PayloadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PayloadError::UnexpectedEnd => write!(f, "unexpected end of data"),
+            PayloadError::InvalidUtf8(e) => write!(f, "invalid UTF-8: {e}"),
+            PayloadError::UnknownDiscriminant { ty, value } => {
+                write!(f, "unknown discriminant {value} for enum {ty}")
+            }
+            PayloadError::LengthOverflow { len, ty } => {
+                write!(f, "length {len} does not fit in {ty}")
+            }
+            PayloadError::IntegerOverflow { value, ty } => {
+                write!(f, "integer {value} does not fit in {ty}")
+            }
+            PayloadError::MissingField => write!(f, "missing field"),
+            PayloadError::InvalidValue { value, ty } => write!(f, "invalid {ty} value {value}"),
+            PayloadError::TrailingBytes { len } => {
+                write!(f, "{len} bytes left after the value")
+            }
+            PayloadError::DatagramTooLarge { len, max } => {
+                write!(f, "value of {len} bytes exceeds the datagram size of {max}")
+            }
+            PayloadError::Limit(e) => write!(f, "{e}"),
+            PayloadError::Read(e) => write!(f, "read failed: {e}"),
+            PayloadError::Write(e) => write!(f, "write failed: {e}"),
+            PayloadError::SendDatagram(e) => write!(f, "sending datagram failed: {e}"),
+            PayloadError::Connection(e) => write!(f, "connection lost: {e}"),
+            PayloadError::Io(e) => write!(f, "{e}"),
+            PayloadError::Field { path, source } => write!(f, "failed decoding {path}: {source}"),
+            PayloadError::Custom(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl Error for PayloadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PayloadError::InvalidUtf8(e) => Some(e),
+            PayloadError::Limit(e) => Some(e),
+            PayloadError::Read(e) => Some(e),
+            PayloadError::Write(e) => Some(e),
+            PayloadError::SendDatagram(e) => Some(e),
+            PayloadError::Connection(e) => Some(e),
+            PayloadError::Io(e) => Some(e),
+            PayloadError::Field { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecodeLimitError> for PayloadError {
+    fn from(e: DecodeLimitError) -> Self {
+        PayloadError::Limit(e)
+    }
+}
+
+impl From<ReadError> for PayloadError {
+    fn from(e: ReadError) -> Self {
+        PayloadError::Read(e)
+    }
+}
+
+impl From<WriteError> for PayloadError {
+    fn from(e: WriteError) -> Self {
+        PayloadError::Write(e)
+    }
+}
+
+impl From<SendDatagramError> for PayloadError {
+    fn from(e: SendDatagramError) -> Self {
+        PayloadError::SendDatagram(e)
+    }
+}
+
+impl From<ConnectionError> for PayloadError {
+    fn from(e: ConnectionError) -> Self {
+        PayloadError::Connection(e)
+    }
+}
+
+impl From<bytes::TryGetError> for PayloadError {
+    fn from(_: bytes::TryGetError) -> Self {
+        PayloadError::UnexpectedEnd
+    }
+}
+
+impl From<Utf8Error> for PayloadError {
+    fn from(e: Utf8Error) -> Self {
+        PayloadError::InvalidUtf8(e)
+    }
+}
+
+impl From<std::string::FromUtf8Error> for PayloadError {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        PayloadError::InvalidUtf8(e.utf8_error())
+    }
+}
+
+/// Recovers the [ReadError] or [WriteError] that the quinn streams wrap in an [std::io::Error].
+impl From<std::io::Error> for PayloadError {
+    fn from(e: std::io::Error) -> Self {
+        if e.kind() == ErrorKind::UnexpectedEof {
+            return PayloadError::UnexpectedEnd;
+        }
+        if !e
+            .get_ref()
+            .is_some_and(|inner| inner.is::<ReadError>() || inner.is::<WriteError>())
+        {
+            return PayloadError::Io(e);
+        }
+
+        let inner = e.into_inner().expect("checked above");
+        match inner.downcast::<ReadError>() {
+            Ok(e) => PayloadError::Read(*e),
+            Err(inner) => match inner.downcast::<WriteError>() {
+                Ok(e) => PayloadError::Write(*e),
+                Err(_) => unreachable!("checked above"),
+            },
+        }
+    }
+}
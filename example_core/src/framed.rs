@@ -0,0 +1,383 @@
+//! A sequence of values sent on one stream, each in its own frame.
+//!
+//! A frame is the [VarInt] length of the encoded value followed by the value. Since the length is
+//! known before decoding, a reader that can't decode a value still knows where the next one
+//! starts, and values can be sent on a long-lived stream without a stream per value.
+
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Buf;
+use futures_core::Stream;
+use futures_sink::Sink;
+use quinn::{RecvStream, SendStream};
+use tokio_util::codec::{Decoder, Encoder, FramedRead, FramedWrite};
+
+use crate::{
+    AsyncRead, AsyncWrite, Bytes, BytesMut, DecodeContext, DecodeLimitError, DecodeLimits, Encode,
+    LengthPrefix, Payload, PayloadError, VarInt,
+};
+
+/// Writes values of `T` to a stream, one frame per value.
+///
+/// Closing the sink shuts the stream down, which finishes a [SendStream].
+#[derive(Debug)]
+pub struct FramedSend<T, W = SendStream> {
+    inner: FramedWrite<W, FrameCodec<T>>,
+}
+
+impl<T, W> FramedSend<T, W>
+where
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(send: W) -> FramedSend<T, W> {
+        FramedSend {
+            inner: FramedWrite::new(send, FrameCodec::new(DecodeLimits::default())),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.get_mut()
+    }
+
+    /// Returns the stream, dropping the frames that were not flushed yet.
+    pub fn into_inner(self) -> W {
+        self.inner.into_inner()
+    }
+}
+
+impl<T, W> Sink<T> for FramedSend<T, W>
+where
+    T: Encode,
+    W: AsyncWrite + Unpin,
+{
+    type Error = PayloadError;
+
+    fn poll_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), PayloadError>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), PayloadError> {
+        Pin::new(&mut self.inner).start_send(item)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), PayloadError>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), PayloadError>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// Reads values of `T` from a stream, one frame per value.
+///
+/// A frame whose value can't be decoded yields an error and is skipped, the stream goes on with
+/// the next frame. The stream ends once the underlying stream is finished, or after an error that
+/// leaves the frame boundaries unknown: a transport error, a frame larger than
+/// [DecodeLimits::max_total_bytes] or a stream ending in the middle of a frame.
+///
+/// Every frame is decoded with its own [DecodeContext], so the limits apply to each value.
+#[derive(Debug)]
+pub struct FramedRecv<T, R = RecvStream> {
+    inner: FramedRead<R, FrameCodec<T>>,
+}
+
+impl<T, R> FramedRecv<T, R>
+where
+    T: Payload,
+    R: AsyncRead + Unpin,
+{
+    pub fn new(recv: R) -> FramedRecv<T, R> {
+        FramedRecv::with_limits(recv, DecodeLimits::default())
+    }
+
+    pub fn with_limits(recv: R, limits: DecodeLimits) -> FramedRecv<T, R> {
+        FramedRecv {
+            inner: FramedRead::new(recv, FrameCodec::new(limits)),
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        self.inner.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        self.inner.get_mut()
+    }
+
+    /// Returns the stream, dropping the bytes that were read but not decoded yet.
+    pub fn into_inner(self) -> R {
+        self.inner.into_inner()
+    }
+}
+
+impl<T, R> Stream for FramedRecv<T, R>
+where
+    T: Payload,
+    R: AsyncRead + Unpin,
+{
+    type Item = Result<T, PayloadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner)
+            .poll_next(cx)
+            .map(|frame| frame.map(|frame| frame.and_then(|value| value)))
+    }
+}
+
+/// Encodes and decodes the frames.
+///
+/// Decoding yields the result of decoding the value of a frame as an item, since an error
+/// returned by the [Decoder] would end the stream.
+#[derive(Debug)]
+struct FrameCodec<T> {
+    limits: DecodeLimits,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> FrameCodec<T> {
+    fn new(limits: DecodeLimits) -> FrameCodec<T> {
+        FrameCodec {
+            limits,
+            _type: PhantomData,
+        }
+    }
+}
+
+impl<T> Encoder<T> for FrameCodec<T>
+where
+    T: Encode,
+{
+    type Error = PayloadError;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), PayloadError> {
+        let len = item.encoded_len();
+        dst.reserve(<VarInt as LengthPrefix>::encoded_len(len) + len);
+        <VarInt as LengthPrefix>::encode_len(len, dst)?;
+
+        let start = dst.len();
+        item.encode(dst)?;
+
+        let written = dst.len() - start;
+        if written != len {
+            return Err(PayloadError::custom(format!(
+                "value encoded to {written} bytes instead of the {len} announced by encoded_len"
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl<T> Decoder for FrameCodec<T>
+where
+    T: Payload,
+{
+    type Item = Result<T, PayloadError>;
+    type Error = PayloadError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, PayloadError> {
+        let Some(&first_byte) = src.first() else {
+            return Ok(None);
+        };
+        let prefix_len = VarInt::len_from_first_byte(first_byte);
+        if src.len() < prefix_len {
+            return Ok(None);
+        }
+
+        let mut prefix = Bytes::copy_from_slice(&src[..prefix_len]);
+        let len = <VarInt as LengthPrefix>::decode_len(
+            &mut prefix,
+            &mut DecodeContext::new(DecodeLimits::UNLIMITED),
+        )?;
+        if len > self.limits.max_total_bytes {
+            return Err(DecodeLimitError::TooManyBytes {
+                limit: self.limits.max_total_bytes,
+            }
+            .into());
+        }
+
+        if src.len() < prefix_len + len {
+            src.reserve(prefix_len + len - src.len());
+            return Ok(None);
+        }
+
+        src.advance(prefix_len);
+        let mut frame = src.split_to(len).freeze();
+        let value = T::decode_with(&mut frame, &mut DecodeContext::new(self.limits));
+
+        Ok(Some(value.and_then(|value| match frame.len() {
+            0 => Ok(value),
+            len => Err(PayloadError::TrailingBytes { len }),
+        })))
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, PayloadError> {
+        match self.decode(src)? {
+            Some(value) => Ok(Some(value)),
+            None if src.is_empty() => Ok(None),
+            None => Err(PayloadError::UnexpectedEnd),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::poll_fn;
+
+    use super::*;
+    use crate::DecodeLimitError;
+
+    async fn send<T: Encode, W: AsyncWrite + Unpin>(send: &mut FramedSend<T, W>, value: T) {
+        poll_fn(|cx| Pin::new(&mut *send).poll_ready(cx))
+            .await
+            .unwrap();
+        Pin::new(&mut *send).start_send(value).unwrap();
+        poll_fn(|cx| Pin::new(&mut *send).poll_flush(cx))
+            .await
+            .unwrap();
+    }
+
+    async fn close<T: Encode, W: AsyncWrite + Unpin>(send: &mut FramedSend<T, W>) {
+        poll_fn(|cx| Pin::new(&mut *send).poll_close(cx))
+            .await
+            .unwrap();
+    }
+
+    async fn next<T: Payload, R: AsyncRead + Unpin>(
+        recv: &mut FramedRecv<T, R>,
+    ) -> Option<Result<T, PayloadError>> {
+        poll_fn(|cx| Pin::new(&mut *recv).poll_next(cx)).await
+    }
+
+    /// The frames of `values`, one after the other.
+    async fn frames<T: Encode>(values: impl IntoIterator<Item = T>) -> Vec<u8> {
+        let mut framed = FramedSend::new(vec![]);
+        for value in values {
+            send(&mut framed, value).await;
+        }
+
+        framed.into_inner()
+    }
+
+    fn values() -> Vec<String> {
+        vec![
+            "hello".to_string(),
+            String::new(),
+            "é".repeat(100),
+            "bye".to_string(),
+        ]
+    }
+
+    #[tokio::test]
+    async fn frames_split_across_reads() {
+        // the stream holds at most 3 bytes, so that the frames and their prefixes are split
+        let (send_half, recv_half) = tokio::io::duplex(3);
+        let mut framed_send = FramedSend::new(send_half);
+        let mut framed_recv = FramedRecv::<String, _>::new(recv_half);
+
+        let sending = async {
+            for value in values() {
+                send(&mut framed_send, value).await;
+            }
+            close(&mut framed_send).await;
+        };
+        let receiving = async {
+            let mut received = vec![];
+            while let Some(value) = next(&mut framed_recv).await {
+                received.push(value.unwrap());
+            }
+            received
+        };
+        let ((), received) = tokio::join!(sending, receiving);
+
+        assert_eq!(received, values());
+    }
+
+    #[tokio::test]
+    async fn frames_read_at_once() {
+        let bytes = frames(values()).await;
+        let mut framed = FramedRecv::<String, _>::new(&bytes[..]);
+
+        for value in values() {
+            assert_eq!(next(&mut framed).await.unwrap().unwrap(), value);
+        }
+        assert!(next(&mut framed).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn invalid_frames_are_skipped() {
+        let mut bytes = frames(["before"]).await;
+        // a string of invalid UTF-8, then a frame with a byte the value doesn't use
+        bytes.extend_from_slice(&[2, 1, 0xff]);
+        bytes.extend_from_slice(&[3, 1, b'a', 0]);
+        bytes.extend(frames(["after"]).await);
+        let mut framed = FramedRecv::<String, _>::new(&bytes[..]);
+
+        assert_eq!(next(&mut framed).await.unwrap().unwrap(), "before");
+        assert!(matches!(
+            next(&mut framed).await.unwrap(),
+            Err(PayloadError::InvalidUtf8(_))
+        ));
+        assert!(matches!(
+            next(&mut framed).await.unwrap(),
+            Err(PayloadError::TrailingBytes { len: 1 })
+        ));
+        assert_eq!(next(&mut framed).await.unwrap().unwrap(), "after");
+        assert!(next(&mut framed).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn frames_over_the_limit_end_the_stream() {
+        let bytes = frames(["short", "much longer than the limit", "short"]).await;
+        let limits = DecodeLimits {
+            max_total_bytes: 8,
+            ..DecodeLimits::default()
+        };
+        let mut framed = FramedRecv::<String, _>::with_limits(&bytes[..], limits);
+
+        assert_eq!(next(&mut framed).await.unwrap().unwrap(), "short");
+        assert!(matches!(
+            next(&mut framed).await.unwrap(),
+            Err(PayloadError::Limit(DecodeLimitError::TooManyBytes {
+                limit: 8
+            }))
+        ));
+        assert!(next(&mut framed).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn truncated_frames_fail() {
+        let bytes = frames(["complete", "truncated"]).await;
+        let mut framed = FramedRecv::<String, _>::new(&bytes[..bytes.len() - 1]);
+
+        assert_eq!(next(&mut framed).await.unwrap().unwrap(), "complete");
+        assert!(matches!(
+            next(&mut framed).await.unwrap(),
+            Err(PayloadError::UnexpectedEnd)
+        ));
+        assert!(next(&mut framed).await.is_none());
+
+        // a stream ending in the middle of a length prefix
+        let mut framed = FramedRecv::<String, _>::new(&[0x40][..]);
+        assert!(matches!(
+            next(&mut framed).await.unwrap(),
+            Err(PayloadError::UnexpectedEnd)
+        ));
+    }
+}
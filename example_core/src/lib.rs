@@ -1,199 +1,557 @@
-use anyhow::anyhow;
-use async_trait::async_trait;
-use quinn::{Chunk, RecvStream, SendStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use bytes::{Buf, BufMut};
+use std::borrow::Cow;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadBuf};
 use uuid::Uuid;
 
-#[async_trait]
-pub trait Payload {
-    async fn read_from_recv_stream(recv: &mut RecvStream) -> anyhow::Result<Self>
+pub mod command;
+pub mod datagram;
+mod encoded;
+pub mod error;
+mod framed;
+pub mod limits;
+pub mod middleware;
+pub mod response;
+pub mod rpc;
+pub mod schema;
+pub mod serde;
+mod std_types;
+pub mod varint;
+pub mod versioned;
+
+pub use self::serde::Serde;
+pub use bytes::{Bytes, BytesMut};
+pub use command::Command;
+pub use datagram::{send_datagram, DatagramRecv};
+pub use encoded::Encoded;
+pub use error::PayloadError;
+pub use framed::{FramedRecv, FramedSend};
+pub use limits::{DecodeContext, DecodeLimitError, DecodeLimits, Nested};
+pub use response::{ErrorCode, Response, ResponseError};
+pub use rpc::RpcError;
+use schema::{Definitions, LengthEncoding, Schema};
+pub use tokio::io::{AsyncRead, AsyncWrite};
+pub use varint::VarInt;
+
+/// A value that can be encoded to bytes.
+///
+/// Values are encoded synchronously into a [BytesMut] buffer, or written to a byte stream. The
+/// streams can be anything implementing tokio's [AsyncWrite], such as [quinn::SendStream],
+/// `Vec<u8>` or a [tokio::io::duplex] pipe.
+///
+/// Every [Payload] is [Encode]. Borrowed types such as `&T`, `[T]`, `str` and [Cow] are only
+/// [Encode], so that a value can be sent without being cloned into an owned type first.
+pub trait Encode {
+    /// Number of bytes appended to the buffer by [Encode::encode].
+    fn encoded_len(&self) -> usize;
+
+    /// Appends the encoded value to `buf`.
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError>;
+
+    /// Encodes the value into a new buffer.
+    fn to_bytes(&self) -> Result<Bytes, PayloadError> {
+        let mut buf = BytesMut::with_capacity(self.encoded_len());
+        self.encode(&mut buf)?;
+
+        Ok(buf.freeze())
+    }
+
+    /// Encodes the whole value before writing it to `send` with a single `write_all`.
+    fn write_to_send_stream<W>(
+        &self,
+        send: &mut W,
+    ) -> impl Future<Output = Result<(), PayloadError>> + Send
     where
-        Self: Sized;
+        Self: Sync,
+        W: AsyncWrite + Unpin + Send + ?Sized,
+    {
+        async move {
+            send.write_all(&self.to_bytes()?).await?;
 
-    async fn write_to_send_stream(&self, send: &mut SendStream) -> anyhow::Result<()>;
+            Ok(())
+        }
+    }
 }
 
-#[async_trait]
-impl Payload for u8 {
-    async fn read_from_recv_stream(recv: &mut RecvStream) -> anyhow::Result<u8> {
-        Ok(recv.read_u8().await?)
+/// A value that can be encoded to and decoded from bytes.
+///
+/// Values are either decoded synchronously from a [Bytes] buffer, or read from a byte stream. The
+/// streams can be anything implementing tokio's [AsyncRead], such as [quinn::RecvStream], `&[u8]`
+/// or a [tokio::io::duplex] pipe.
+///
+/// Decoding is bounded by a [DecodeContext], [Payload::decode] and
+/// [Payload::read_from_recv_stream] use the default [DecodeLimits].
+///
+/// The async methods return unboxed futures, so that reading a value doesn't allocate a future per
+/// field. Implementations can use `async fn`, the returned futures must be [Send] so that values
+/// can be read from spawned tasks.
+pub trait Payload: Encode {
+    /// Decodes a value from the start of `buf`, advancing it past the bytes that were used.
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<Self, PayloadError>
+    where
+        Self: Sized;
+
+    fn read_with<R>(
+        recv: &mut R,
+        ctx: &mut DecodeContext,
+    ) -> impl Future<Output = Result<Self, PayloadError>> + Send
+    where
+        Self: Sized,
+        R: AsyncRead + Unpin + Send + ?Sized;
+
+    /// Describes the encoding of the type, adding the types deriving `Payload` it uses to `defs`.
+    fn schema(defs: &mut Definitions) -> Schema
+    where
+        Self: Sized,
+    {
+        let _ = defs;
+
+        Schema::Opaque {
+            ty: schema::type_name::<Self>(),
+        }
     }
 
-    async fn write_to_send_stream(&self, send: &mut SendStream) -> anyhow::Result<()> {
-        send.write_u8(*self).await?;
+    fn decode(buf: &mut Bytes) -> Result<Self, PayloadError>
+    where
+        Self: Sized,
+    {
+        Self::decode_with(buf, &mut DecodeContext::default())
+    }
 
-        Ok(())
+    fn read_from_recv_stream<R>(
+        recv: &mut R,
+    ) -> impl Future<Output = Result<Self, PayloadError>> + Send
+    where
+        Self: Sized,
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        async move { Self::read_with(recv, &mut DecodeContext::default()).await }
+    }
+
+    fn read_with_limits<R>(
+        recv: &mut R,
+        limits: &DecodeLimits,
+    ) -> impl Future<Output = Result<Self, PayloadError>> + Send
+    where
+        Self: Sized,
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let limits = *limits;
+
+        async move { Self::read_with(recv, &mut DecodeContext::new(limits)).await }
     }
 }
 
-#[async_trait]
-impl Payload for u16 {
-    async fn read_from_recv_stream(recv: &mut RecvStream) -> anyhow::Result<u16> {
-        Ok(recv.read_u16().await?)
+/// Implements [Payload] for a number, encoded in big-endian.
+macro_rules! impl_payload_for_number {
+    ($ty:ty, $read:ident, $try_get:ident, $put:ident, $schema:ident) => {
+        impl Encode for $ty {
+            fn encoded_len(&self) -> usize {
+                std::mem::size_of::<$ty>()
+            }
+
+            fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+                buf.$put(*self);
+
+                Ok(())
+            }
+        }
+
+        impl Payload for $ty {
+            fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<$ty, PayloadError> {
+                ctx.consume(std::mem::size_of::<$ty>())?;
+
+                Ok(buf.$try_get()?)
+            }
+
+            async fn read_with<R>(
+                recv: &mut R,
+                ctx: &mut DecodeContext,
+            ) -> Result<$ty, PayloadError>
+            where
+                R: AsyncRead + Unpin + Send + ?Sized,
+            {
+                ctx.consume(std::mem::size_of::<$ty>())?;
+
+                Ok(recv.$read().await?)
+            }
+
+            fn schema(_defs: &mut Definitions) -> Schema {
+                Schema::$schema
+            }
+        }
+    };
+}
+
+impl_payload_for_number!(u8, read_u8, try_get_u8, put_u8, U8);
+impl_payload_for_number!(u16, read_u16, try_get_u16, put_u16, U16);
+impl_payload_for_number!(u32, read_u32, try_get_u32, put_u32, U32);
+impl_payload_for_number!(u64, read_u64, try_get_u64, put_u64, U64);
+impl_payload_for_number!(u128, read_u128, try_get_u128, put_u128, U128);
+impl_payload_for_number!(i8, read_i8, try_get_i8, put_i8, I8);
+impl_payload_for_number!(i16, read_i16, try_get_i16, put_i16, I16);
+impl_payload_for_number!(i32, read_i32, try_get_i32, put_i32, I32);
+impl_payload_for_number!(i64, read_i64, try_get_i64, put_i64, I64);
+impl_payload_for_number!(i128, read_i128, try_get_i128, put_i128, I128);
+impl_payload_for_number!(f32, read_f32, try_get_f32, put_f32, F32);
+impl_payload_for_number!(f64, read_f64, try_get_f64, put_f64, F64);
+
+/// Integer type written before a [LengthPrefixed] value to hold its length.
+pub trait LengthPrefix {
+    const ENCODING: LengthEncoding;
+
+    fn encoded_len(len: usize) -> usize;
+
+    fn encode_len(len: usize, buf: &mut BytesMut) -> Result<(), PayloadError>;
+
+    fn decode_len(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<usize, PayloadError>;
+
+    fn read_len<R>(
+        recv: &mut R,
+        ctx: &mut DecodeContext,
+    ) -> impl Future<Output = Result<usize, PayloadError>> + Send
+    where
+        R: AsyncRead + Unpin + Send + ?Sized;
+}
+
+macro_rules! impl_length_prefix {
+    ($($ty:ty => $encoding:ident),*) => {
+        $(
+            impl LengthPrefix for $ty {
+                const ENCODING: LengthEncoding = LengthEncoding::$encoding;
+
+                fn encoded_len(_len: usize) -> usize {
+                    std::mem::size_of::<$ty>()
+                }
+
+                fn encode_len(len: usize, buf: &mut BytesMut) -> Result<(), PayloadError> {
+                    let len = <$ty>::try_from(len).map_err(|_| PayloadError::LengthOverflow {
+                        len: len as u64,
+                        ty: stringify!($ty),
+                    })?;
+
+                    len.encode(buf)
+                }
+
+                fn decode_len(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<usize, PayloadError> {
+                    let len = <$ty>::decode_with(buf, ctx)?;
+
+                    usize::try_from(len).map_err(|_| PayloadError::LengthOverflow {
+                        len: len as u64,
+                        ty: "usize",
+                    })
+                }
+
+                async fn read_len<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<usize, PayloadError>
+                where
+                    R: AsyncRead + Unpin + Send + ?Sized,
+                {
+                    let len = <$ty>::read_with(recv, ctx).await?;
+
+                    usize::try_from(len).map_err(|_| PayloadError::LengthOverflow {
+                        len: len as u64,
+                        ty: "usize",
+                    })
+                }
+            }
+        )*
+    };
+}
+
+impl_length_prefix!(u8 => U8, u16 => U16, u32 => U32, u64 => U64);
+
+/// A value whose encoding starts with its length, like [String], [Vec] and [Bytes].
+///
+/// Its [Payload] impl uses a [VarInt] prefix, these methods allow picking another
+/// [LengthPrefix].
+pub trait LengthPrefixed: Sized {
+    fn encoded_len_with_prefix<L: LengthPrefix>(&self) -> usize;
+
+    fn encode_with_prefix<L: LengthPrefix>(&self, buf: &mut BytesMut) -> Result<(), PayloadError>;
+
+    fn decode_with_prefix<L: LengthPrefix>(
+        buf: &mut Bytes,
+        ctx: &mut DecodeContext,
+    ) -> Result<Self, PayloadError>;
+
+    fn read_with_prefix<L, R>(
+        recv: &mut R,
+        ctx: &mut DecodeContext,
+    ) -> impl Future<Output = Result<Self, PayloadError>> + Send
+    where
+        L: LengthPrefix,
+        R: AsyncRead + Unpin + Send + ?Sized;
+}
+
+impl LengthPrefixed for String {
+    fn encoded_len_with_prefix<L: LengthPrefix>(&self) -> usize {
+        L::encoded_len(self.len()) + self.len()
     }
 
-    async fn write_to_send_stream(&self, send: &mut SendStream) -> anyhow::Result<()> {
-        send.write_u16(*self).await?;
+    fn encode_with_prefix<L: LengthPrefix>(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        let bytes: &[u8] = self.as_bytes();
+
+        // write the length of the string
+        L::encode_len(bytes.len(), buf)?;
+        // write the string
+        buf.put_slice(bytes);
 
         Ok(())
     }
-}
 
-#[async_trait]
-impl Payload for u32 {
-    async fn read_from_recv_stream(recv: &mut RecvStream) -> anyhow::Result<u32> {
-        Ok(recv.read_u32().await?)
+    fn decode_with_prefix<L: LengthPrefix>(
+        buf: &mut Bytes,
+        ctx: &mut DecodeContext,
+    ) -> Result<String, PayloadError> {
+        let bytes = Bytes::decode_with_prefix::<L>(buf, ctx)?;
+        let string: String = String::from_utf8(bytes.to_vec())?;
+
+        Ok(string)
     }
 
-    async fn write_to_send_stream(&self, send: &mut SendStream) -> anyhow::Result<()> {
-        send.write_u32(*self).await?;
+    async fn read_with_prefix<L, R>(
+        recv: &mut R,
+        ctx: &mut DecodeContext,
+    ) -> Result<String, PayloadError>
+    where
+        L: LengthPrefix,
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let string_size = L::read_len(recv, ctx).await?;
 
-        Ok(())
+        let bytes = read_exact_bytes(recv, string_size, ctx).await?;
+        let string: String = String::from_utf8(bytes)?;
+
+        Ok(string)
     }
 }
 
-#[async_trait]
-impl Payload for u64 {
-    async fn read_from_recv_stream(recv: &mut RecvStream) -> anyhow::Result<u64> {
-        Ok(recv.read_u64().await?)
+impl Encode for String {
+    fn encoded_len(&self) -> usize {
+        self.encoded_len_with_prefix::<VarInt>()
     }
 
-    async fn write_to_send_stream(&self, send: &mut SendStream) -> anyhow::Result<()> {
-        send.write_u64(*self).await?;
-
-        Ok(())
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        self.encode_with_prefix::<VarInt>(buf)
     }
 }
 
-#[async_trait]
-impl Payload for i8 {
-    async fn read_from_recv_stream(recv: &mut RecvStream) -> anyhow::Result<i8> {
-        Ok(recv.read_i8().await?)
+/// Encoded like a [String].
+impl Encode for str {
+    fn encoded_len(&self) -> usize {
+        <VarInt as LengthPrefix>::encoded_len(self.len()) + self.len()
     }
 
-    async fn write_to_send_stream(&self, send: &mut SendStream) -> anyhow::Result<()> {
-        send.write_i8(*self).await?;
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        <VarInt as LengthPrefix>::encode_len(self.len(), buf)?;
+        buf.put_slice(self.as_bytes());
 
         Ok(())
     }
 }
 
-#[async_trait]
-impl Payload for i16 {
-    async fn read_from_recv_stream(recv: &mut RecvStream) -> anyhow::Result<i16> {
-        Ok(recv.read_i16().await?)
+impl Payload for String {
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<String, PayloadError> {
+        Self::decode_with_prefix::<VarInt>(buf, ctx)
     }
 
-    async fn write_to_send_stream(&self, send: &mut SendStream) -> anyhow::Result<()> {
-        send.write_i16(*self).await?;
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<String, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        Self::read_with_prefix::<VarInt, R>(recv, ctx).await
+    }
 
-        Ok(())
+    fn schema(_defs: &mut Definitions) -> Schema {
+        Schema::String {
+            length: LengthEncoding::VarInt,
+        }
     }
 }
 
-#[async_trait]
-impl Payload for i32 {
-    async fn read_from_recv_stream(recv: &mut RecvStream) -> anyhow::Result<i32> {
-        Ok(recv.read_i32().await?)
+/// Raw bytes, encoded like a `Vec<u8>` but copied as a whole instead of byte per byte.
+impl LengthPrefixed for Bytes {
+    fn encoded_len_with_prefix<L: LengthPrefix>(&self) -> usize {
+        L::encoded_len(self.len()) + self.len()
     }
 
-    async fn write_to_send_stream(&self, send: &mut SendStream) -> anyhow::Result<()> {
-        send.write_i32(*self).await?;
+    fn encode_with_prefix<L: LengthPrefix>(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        L::encode_len(self.len(), buf)?;
+        buf.put_slice(self);
 
         Ok(())
     }
-}
 
-#[async_trait]
-impl Payload for i64 {
-    async fn read_from_recv_stream(recv: &mut RecvStream) -> anyhow::Result<i64> {
-        Ok(recv.read_i64().await?)
+    fn decode_with_prefix<L: LengthPrefix>(
+        buf: &mut Bytes,
+        ctx: &mut DecodeContext,
+    ) -> Result<Bytes, PayloadError> {
+        let len = L::decode_len(buf, ctx)?;
+        ctx.check_string_len(len)?;
+        ctx.consume(len)?;
+
+        if buf.remaining() < len {
+            return Err(PayloadError::UnexpectedEnd);
+        }
+
+        Ok(buf.split_to(len))
     }
 
-    async fn write_to_send_stream(&self, send: &mut SendStream) -> anyhow::Result<()> {
-        send.write_i64(*self).await?;
+    async fn read_with_prefix<L, R>(
+        recv: &mut R,
+        ctx: &mut DecodeContext,
+    ) -> Result<Bytes, PayloadError>
+    where
+        L: LengthPrefix,
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let len = L::read_len(recv, ctx).await?;
 
-        Ok(())
+        Ok(Bytes::from(read_exact_bytes(recv, len, ctx).await?))
     }
 }
 
-#[async_trait]
-impl Payload for f32 {
-    async fn read_from_recv_stream(recv: &mut RecvStream) -> anyhow::Result<f32> {
-        Ok(recv.read_f32().await?)
+impl Encode for Bytes {
+    fn encoded_len(&self) -> usize {
+        self.encoded_len_with_prefix::<VarInt>()
     }
 
-    async fn write_to_send_stream(&self, send: &mut SendStream) -> anyhow::Result<()> {
-        send.write_f32(*self).await?;
-
-        Ok(())
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        self.encode_with_prefix::<VarInt>(buf)
     }
 }
 
-#[async_trait]
-impl Payload for f64 {
-    async fn read_from_recv_stream(recv: &mut RecvStream) -> anyhow::Result<f64> {
-        Ok(recv.read_f64().await?)
+impl Payload for Bytes {
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<Bytes, PayloadError> {
+        Self::decode_with_prefix::<VarInt>(buf, ctx)
     }
 
-    async fn write_to_send_stream(&self, send: &mut SendStream) -> anyhow::Result<()> {
-        send.write_f64(*self).await?;
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<Bytes, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        Self::read_with_prefix::<VarInt, R>(recv, ctx).await
+    }
 
-        Ok(())
+    fn schema(_defs: &mut Definitions) -> Schema {
+        Schema::Bytes {
+            length: LengthEncoding::VarInt,
+        }
     }
 }
 
-#[async_trait]
-impl Payload for String {
-    async fn read_from_recv_stream(recv: &mut RecvStream) -> anyhow::Result<String> {
-        let string_size: u16 = recv.read_u16().await?;
+/// Reads exactly `len` bytes of a string or of [Bytes] from `recv`, checking `len` against the
+/// limits of `ctx` first.
+async fn read_exact_bytes<R>(
+    recv: &mut R,
+    len: usize,
+    ctx: &mut DecodeContext,
+) -> Result<Vec<u8>, PayloadError>
+where
+    R: AsyncRead + Unpin + Send + ?Sized,
+{
+    ctx.check_string_len(len)?;
+    ctx.consume(len)?;
 
-        let chunk: Chunk = recv.read_chunk(string_size.into(), true).await?.unwrap();
-        let string: String = String::from_utf8(chunk.bytes.to_vec())?;
+    read_exact(recv, len).await
+}
 
-        Ok(string)
+/// Reads exactly `len` bytes from `recv`, however they are split across reads.
+///
+/// The buffer grows with the data that is actually received rather than being allocated from the
+/// peer provided length up front. Fails with [PayloadError::UnexpectedEnd] if the stream ends early.
+pub(crate) async fn read_exact<R>(recv: &mut R, len: usize) -> Result<Vec<u8>, PayloadError>
+where
+    R: AsyncRead + Unpin + Send + ?Sized,
+{
+    let mut bytes: Vec<u8> = Vec::with_capacity(len.min(READ_CHUNK_SIZE));
+
+    while bytes.len() < len {
+        let start = bytes.len();
+        bytes.resize(start + (len - start).min(READ_CHUNK_SIZE), 0);
+        recv.read_exact(&mut bytes[start..]).await?;
     }
 
-    async fn write_to_send_stream(&self, send: &mut SendStream) -> anyhow::Result<()> {
-        let bytes: &[u8] = self.as_bytes();
-        let string_length = u16::try_from(bytes.len()).expect("Unable to parse usize to u16");
+    Ok(bytes)
+}
 
-        // write the length of the string
-        send.write_u16(string_length).await?;
-        // write the string
-        send.write(bytes).await?;
+pub(crate) const READ_CHUNK_SIZE: usize = 8 * 1024;
 
-        Ok(())
+/// Counts the bytes read from a stream, so that a stream ending before a value can be told apart
+/// from one ending inside it.
+#[derive(Debug)]
+pub struct CountingRead<'a, R: ?Sized> {
+    recv: &'a mut R,
+    count: usize,
+}
+
+impl<'a, R: ?Sized> CountingRead<'a, R> {
+    pub fn new(recv: &'a mut R) -> CountingRead<'a, R> {
+        CountingRead { recv, count: 0 }
+    }
+
+    /// The number of bytes read so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl<R> AsyncRead for CountingRead<'_, R>
+where
+    R: AsyncRead + Unpin + ?Sized,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut *self.recv).poll_read(cx, buf);
+        self.count += buf.filled().len() - filled;
+
+        poll
+    }
+}
+
+/// Fills a trailing `#[payload(default)]` field of a struct that isn't versioned with its default
+/// value when the data ended right before it, `available` being the number of bytes of the field
+/// that were received.
+///
+/// Only the outermost value ends where its buffer or stream ends, so the field of a nested value
+/// still fails with [PayloadError::UnexpectedEnd], as does a field that ended partway.
+pub fn default_if_missing<T: Default>(
+    result: Result<T, PayloadError>,
+    available: usize,
+    ctx: &DecodeContext,
+) -> Result<T, PayloadError> {
+    match result {
+        Err(e) if available == 0 && ctx.depth() == 1 && e.is_unexpected_end() => Ok(T::default()),
+        result => result,
     }
 }
 
 /// If the first byte is:
 /// 0b1 => Some
 /// _ => None
-#[async_trait]
-impl<T> Payload for Option<T>
+impl<T> Encode for Option<T>
 where
-    T: Payload + Sync + Send,
+    T: Encode,
 {
-    async fn read_from_recv_stream(recv: &mut RecvStream) -> anyhow::Result<Option<T>> {
-        let first_byte = recv.read_u8().await?;
-        let result: Option<T> = match first_byte {
-            0b1 => Some(T::read_from_recv_stream(recv).await?),
-            _ => None,
-        };
-
-        Ok(result)
+    fn encoded_len(&self) -> usize {
+        1 + self.as_ref().map_or(0, T::encoded_len)
     }
 
-    async fn write_to_send_stream(&self, send: &mut SendStream) -> anyhow::Result<()> {
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
         match self {
             Some(x) => {
-                send.write_u8(0b1).await?;
+                buf.put_u8(0b1);
 
-                x.write_to_send_stream(send).await?;
+                x.encode(buf)?;
             }
             None => {
-                send.write_u8(0b0).await?;
+                buf.put_u8(0b0);
             }
         };
 
@@ -201,45 +559,387 @@ where
     }
 }
 
-#[async_trait]
-impl Payload for Uuid {
-    async fn read_from_recv_stream(recv: &mut RecvStream) -> anyhow::Result<Uuid> {
-        Ok(Uuid::from_u128(recv.read_u128().await?))
+fn unknown_option_tag(value: u8) -> PayloadError {
+    PayloadError::UnknownDiscriminant {
+        ty: "Option",
+        value,
     }
+}
 
-    async fn write_to_send_stream(&self, send: &mut SendStream) -> anyhow::Result<()> {
-        send.write_u128(self.as_u128()).await?;
+impl<T> Payload for Option<T>
+where
+    T: Payload + Sync + Send,
+{
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<Option<T>, PayloadError> {
+        let first_byte = u8::decode_with(buf, ctx)?;
+
+        let mut nested = ctx.enter()?;
+        let ctx = &mut *nested;
+        match first_byte {
+            0b0 => Ok(None),
+            0b1 => T::decode_with(buf, ctx).map(Some),
+            value => Err(unknown_option_tag(value)),
+        }
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<Option<T>, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let first_byte = u8::read_with(recv, ctx).await?;
+
+        let mut nested = ctx.enter()?;
+        let ctx = &mut *nested;
+        match first_byte {
+            0b0 => Ok(None),
+            0b1 => T::read_with(recv, ctx).await.map(Some),
+            value => Err(unknown_option_tag(value)),
+        }
+    }
+
+    fn schema(defs: &mut Definitions) -> Schema {
+        Schema::Option {
+            value: Box::new(T::schema(defs)),
+        }
+    }
+}
+
+impl Encode for Uuid {
+    fn encoded_len(&self) -> usize {
+        16
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        buf.put_u128(self.as_u128());
 
         Ok(())
     }
 }
 
-#[async_trait]
-impl<T> Payload for Vec<T>
+impl Payload for Uuid {
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<Uuid, PayloadError> {
+        ctx.consume(16)?;
+
+        Ok(Uuid::from_u128(buf.try_get_u128()?))
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<Uuid, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        ctx.consume(16)?;
+
+        Ok(Uuid::from_u128(recv.read_u128().await?))
+    }
+
+    fn schema(_defs: &mut Definitions) -> Schema {
+        Schema::Uuid
+    }
+}
+
+impl<T> LengthPrefixed for Vec<T>
 where
     T: Payload + Sync + Send,
 {
-    async fn read_from_recv_stream(recv: &mut RecvStream) -> anyhow::Result<Vec<T>> {
-        let vec_len = recv.read_u16().await?;
+    fn encoded_len_with_prefix<L: LengthPrefix>(&self) -> usize {
+        L::encoded_len(self.len()) + self.iter().map(T::encoded_len).sum::<usize>()
+    }
+
+    fn encode_with_prefix<L: LengthPrefix>(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        // write length of the vec
+        L::encode_len(self.len(), buf)?;
+
+        for item in self.iter() {
+            item.encode(buf)?;
+        }
+
+        Ok(())
+    }
 
+    fn decode_with_prefix<L: LengthPrefix>(
+        buf: &mut Bytes,
+        ctx: &mut DecodeContext,
+    ) -> Result<Vec<T>, PayloadError> {
+        let vec_len = L::decode_len(buf, ctx)?;
+        ctx.check_collection_len(vec_len)?;
+
+        let mut nested = ctx.enter()?;
+        let ctx = &mut *nested;
         let mut vec: Vec<T> = vec![];
         for _ in 0..vec_len {
-            vec.push(T::read_from_recv_stream(recv).await?);
+            vec.push(T::decode_with(buf, ctx)?);
         }
 
         Ok(vec)
     }
 
-    async fn write_to_send_stream(&self, send: &mut SendStream) -> anyhow::Result<()> {
-        // write length of the vec
-        let len = u16::try_from(self.len())
-            .map_err(|e| anyhow!("Unable to cast usize {} to u16: {}", self.len(), e))?;
-        send.write_u16(len).await?;
+    async fn read_with_prefix<L, R>(
+        recv: &mut R,
+        ctx: &mut DecodeContext,
+    ) -> Result<Vec<T>, PayloadError>
+    where
+        L: LengthPrefix,
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let vec_len = L::read_len(recv, ctx).await?;
+        ctx.check_collection_len(vec_len)?;
+
+        let mut nested = ctx.enter()?;
+        let ctx = &mut *nested;
+        let mut vec: Vec<T> = vec![];
+        for _ in 0..vec_len {
+            vec.push(T::read_with(recv, ctx).await?);
+        }
+
+        Ok(vec)
+    }
+}
+
+impl<T> Encode for Vec<T>
+where
+    T: Encode,
+{
+    fn encoded_len(&self) -> usize {
+        self.as_slice().encoded_len()
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        self.as_slice().encode(buf)
+    }
+}
+
+/// Encoded like a [Vec].
+impl<T> Encode for [T]
+where
+    T: Encode,
+{
+    fn encoded_len(&self) -> usize {
+        <VarInt as LengthPrefix>::encoded_len(self.len())
+            + self.iter().map(T::encoded_len).sum::<usize>()
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        <VarInt as LengthPrefix>::encode_len(self.len(), buf)?;
 
         for item in self.iter() {
-            item.write_to_send_stream(send).await?;
+            item.encode(buf)?;
         }
 
         Ok(())
     }
 }
+
+impl<T> Encode for &T
+where
+    T: Encode + ?Sized,
+{
+    fn encoded_len(&self) -> usize {
+        T::encoded_len(self)
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        T::encode(self, buf)
+    }
+}
+
+/// Encoded like the borrowed or owned value, which are expected to be encoded the same way.
+impl<T> Encode for Cow<'_, T>
+where
+    T: ToOwned + Encode + ?Sized,
+{
+    fn encoded_len(&self) -> usize {
+        T::encoded_len(self)
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        T::encode(self, buf)
+    }
+}
+
+impl<T> Payload for Vec<T>
+where
+    T: Payload + Sync + Send,
+{
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<Vec<T>, PayloadError> {
+        Self::decode_with_prefix::<VarInt>(buf, ctx)
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<Vec<T>, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        Self::read_with_prefix::<VarInt, R>(recv, ctx).await
+    }
+
+    fn schema(defs: &mut Definitions) -> Schema {
+        Schema::Seq {
+            length: LengthEncoding::VarInt,
+            items: Box::new(T::schema(defs)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out the bytes of `data` at most `chunk` at a time, returning [Poll::Pending] before
+    /// each chunk like a stream waiting for its next frame.
+    struct Chunked {
+        data: Bytes,
+        chunk: usize,
+        ready: bool,
+    }
+
+    impl Chunked {
+        fn new(data: Bytes, chunk: usize) -> Chunked {
+            Chunked {
+                data,
+                chunk,
+                ready: false,
+            }
+        }
+    }
+
+    impl AsyncRead for Chunked {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            if !self.ready {
+                self.ready = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            self.ready = false;
+
+            let len = self.chunk.min(self.data.len()).min(buf.remaining());
+            let chunk = self.data.split_to(len);
+            buf.put_slice(&chunk);
+
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// A string long enough to take a 4 byte length and several read chunks.
+    fn long_string() -> String {
+        (0..READ_CHUNK_SIZE * 3 + 7)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn read_string_split_across_reads() {
+        let string = long_string();
+        let bytes = string.to_bytes().unwrap();
+
+        // the smaller chunks split the length prefix, the others split the body at various offsets
+        for chunk in [1, 2, 3, 1000, READ_CHUNK_SIZE - 1, READ_CHUNK_SIZE + 1] {
+            let mut recv = Chunked::new(bytes.clone(), chunk);
+
+            assert_eq!(
+                String::read_from_recv_stream(&mut recv).await.unwrap(),
+                string
+            );
+            assert!(recv.data.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn read_string_from_duplex() {
+        let string = long_string();
+        let bytes = string.to_bytes().unwrap();
+        let (mut send, mut recv) = tokio::io::duplex(64);
+
+        let writer = tokio::spawn(async move {
+            // the first write ends in the middle of the length prefix
+            for part in [&bytes[..1], &bytes[1..5], &bytes[5..]] {
+                send.write_all(part).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        });
+
+        let read = String::read_from_recv_stream(&mut recv).await.unwrap();
+        writer.await.unwrap();
+
+        assert_eq!(read, string);
+    }
+
+    #[tokio::test]
+    async fn read_bytes_split_across_reads() {
+        let value = Bytes::from(long_string().into_bytes());
+        let encoded = value.to_bytes().unwrap();
+        let mut recv = Chunked::new(encoded, 5);
+
+        assert_eq!(
+            Bytes::read_from_recv_stream(&mut recv).await.unwrap(),
+            value
+        );
+    }
+
+    #[tokio::test]
+    async fn truncated_string_is_unexpected_end() {
+        let bytes = long_string().to_bytes().unwrap();
+
+        // inside the length prefix, right after it and inside the body
+        for len in [1, 2, 3, bytes.len() - 1] {
+            let mut recv = Chunked::new(bytes.slice(..len), 3);
+            let error = String::read_from_recv_stream(&mut recv).await.unwrap_err();
+            assert!(error.is_unexpected_end(), "{len} bytes: {error}");
+
+            let error = String::decode(&mut bytes.slice(..len)).unwrap_err();
+            assert!(error.is_unexpected_end(), "{len} bytes: {error}");
+        }
+    }
+
+    #[tokio::test]
+    async fn read_exact_grows_with_the_data() {
+        let data = Bytes::from(vec![7u8; 10]);
+        let mut recv = Chunked::new(data, 4);
+
+        // the announced length is way larger than what the stream holds
+        let error = read_exact(&mut recv, usize::MAX / 2).await.unwrap_err();
+        assert!(error.is_unexpected_end(), "{error}");
+    }
+
+    #[tokio::test]
+    async fn unknown_option_tags_fail() {
+        for tag in [2u8, 255] {
+            let bytes = Bytes::from(vec![tag, 7]);
+
+            let error = Option::<u8>::decode(&mut bytes.clone()).unwrap_err();
+            assert!(
+                matches!(
+                    error,
+                    PayloadError::UnknownDiscriminant {
+                        ty: "Option",
+                        value,
+                    } if value == tag
+                ),
+                "{error}"
+            );
+
+            let mut recv: &[u8] = &bytes;
+            let error = Option::<u8>::read_from_recv_stream(&mut recv)
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(
+                    error,
+                    PayloadError::UnknownDiscriminant { ty: "Option", .. }
+                ),
+                "{error}"
+            );
+        }
+
+        assert_eq!(
+            Option::<u8>::decode(&mut Bytes::from_static(&[0])).unwrap(),
+            None
+        );
+        assert_eq!(
+            Option::<u8>::decode(&mut Bytes::from_static(&[1, 7])).unwrap(),
+            Some(7)
+        );
+    }
+}
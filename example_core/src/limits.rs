@@ -0,0 +1,315 @@
+//! Limits protecting a decoder from peers announcing huge or deeply nested values.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::ops::{Deref, DerefMut};
+
+/// Bounds checked while a single value is decoded.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DecodeLimits {
+    /// Maximum number of bytes making up the value.
+    pub max_total_bytes: usize,
+    /// Maximum number of elements of a collection such as a [Vec].
+    pub max_collection_len: usize,
+    /// Maximum nesting of options, collections and derived types.
+    pub max_depth: usize,
+    /// Maximum length in bytes of a [String] or of [Bytes](crate::Bytes).
+    pub max_string_len: usize,
+}
+
+impl DecodeLimits {
+    /// Limits that never trigger, for trusted peers only.
+    pub const UNLIMITED: DecodeLimits = DecodeLimits {
+        max_total_bytes: usize::MAX,
+        max_collection_len: usize::MAX,
+        max_depth: usize::MAX,
+        max_string_len: usize::MAX,
+    };
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_total_bytes: 16 * 1024 * 1024,
+            max_collection_len: 1024 * 1024,
+            max_depth: 64,
+            max_string_len: 1024 * 1024,
+        }
+    }
+}
+
+/// A [DecodeLimits] bound that was exceeded.
+#[derive(Debug, Eq, PartialEq)]
+pub enum DecodeLimitError {
+    TooManyBytes { limit: usize },
+    CollectionTooLong { len: usize, limit: usize },
+    TooDeep { limit: usize },
+    StringTooLong { len: usize, limit: usize },
+}
+
+impl Display for DecodeLimitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeLimitError::TooManyBytes { limit } => {
+                write!(f, "value is larger than {limit} bytes")
+            }
+            DecodeLimitError::CollectionTooLong { len, limit } => {
+                write!(
+                    f,
+                    "collection of {len} elements exceeds the limit of {limit}"
+                )
+            }
+            DecodeLimitError::TooDeep { limit } => {
+                write!(f, "value is nested more than {limit} levels deep")
+            }
+            DecodeLimitError::StringTooLong { len, limit } => {
+                write!(f, "string of {len} bytes exceeds the limit of {limit}")
+            }
+        }
+    }
+}
+
+impl Error for DecodeLimitError {}
+
+/// State of the decoding of one value, checked against its [DecodeLimits].
+///
+/// A context is passed down to every nested value, so that the limits apply to the whole value
+/// rather than to each of its fields.
+#[derive(Clone, Debug)]
+pub struct DecodeContext {
+    limits: DecodeLimits,
+    bytes: usize,
+    depth: usize,
+}
+
+impl DecodeContext {
+    pub fn new(limits: DecodeLimits) -> Self {
+        Self {
+            limits,
+            bytes: 0,
+            depth: 0,
+        }
+    }
+
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    /// Records that `len` more bytes are part of the value.
+    pub fn consume(&mut self, len: usize) -> Result<(), DecodeLimitError> {
+        match self.bytes.checked_add(len) {
+            Some(bytes) if bytes <= self.limits.max_total_bytes => {
+                self.bytes = bytes;
+                Ok(())
+            }
+            _ => Err(DecodeLimitError::TooManyBytes {
+                limit: self.limits.max_total_bytes,
+            }),
+        }
+    }
+
+    /// Checks that `len` more bytes would fit in the limit, without recording them.
+    pub fn check_available(&self, len: usize) -> Result<(), DecodeLimitError> {
+        match self.bytes.checked_add(len) {
+            Some(bytes) if bytes <= self.limits.max_total_bytes => Ok(()),
+            _ => Err(DecodeLimitError::TooManyBytes {
+                limit: self.limits.max_total_bytes,
+            }),
+        }
+    }
+
+    /// Records that a nested value starts, until the returned guard is dropped.
+    ///
+    /// The nested value is decoded through the guard, so that the depth is restored however its
+    /// decoding ends, including through `?`.
+    pub fn enter(&mut self) -> Result<Nested<'_>, DecodeLimitError> {
+        if self.depth >= self.limits.max_depth {
+            return Err(DecodeLimitError::TooDeep {
+                limit: self.limits.max_depth,
+            });
+        }
+        self.depth += 1;
+
+        Ok(Nested { ctx: self })
+    }
+
+    /// The number of values entered and not left yet, 1 while decoding the fields of the
+    /// outermost value.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn check_collection_len(&self, len: usize) -> Result<(), DecodeLimitError> {
+        if len > self.limits.max_collection_len {
+            return Err(DecodeLimitError::CollectionTooLong {
+                len,
+                limit: self.limits.max_collection_len,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn check_string_len(&self, len: usize) -> Result<(), DecodeLimitError> {
+        if len > self.limits.max_string_len {
+            return Err(DecodeLimitError::StringTooLong {
+                len,
+                limit: self.limits.max_string_len,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for DecodeContext {
+    fn default() -> Self {
+        Self::new(DecodeLimits::default())
+    }
+}
+
+/// The context of a nested value, returned by [DecodeContext::enter], which leaves the nested
+/// value when dropped.
+#[derive(Debug)]
+pub struct Nested<'a> {
+    ctx: &'a mut DecodeContext,
+}
+
+impl Deref for Nested<'_> {
+    type Target = DecodeContext;
+
+    fn deref(&self) -> &DecodeContext {
+        self.ctx
+    }
+}
+
+impl DerefMut for Nested<'_> {
+    fn deref_mut(&mut self) -> &mut DecodeContext {
+        self.ctx
+    }
+}
+
+impl Drop for Nested<'_> {
+    fn drop(&mut self) {
+        self.ctx.depth -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{Bytes, Encode, Payload, PayloadError};
+
+    /// The largest varint, announcing a length that can't be allocated.
+    const HUGE_LENGTH: [u8; 8] = [0xff; 8];
+
+    fn limit_error(error: &PayloadError) -> Option<&DecodeLimitError> {
+        match error.kind() {
+            PayloadError::Limit(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn huge_lengths_fail_without_allocating() {
+        let mut ctx = DecodeContext::default();
+        let error = String::decode_with(&mut Bytes::from_static(&HUGE_LENGTH), &mut ctx);
+        assert!(matches!(
+            limit_error(&error.unwrap_err()),
+            Some(DecodeLimitError::StringTooLong { .. })
+        ));
+
+        let mut ctx = DecodeContext::default();
+        let error = Vec::<u8>::decode_with(&mut Bytes::from_static(&HUGE_LENGTH), &mut ctx);
+        assert!(matches!(
+            limit_error(&error.unwrap_err()),
+            Some(DecodeLimitError::CollectionTooLong { .. })
+        ));
+
+        // without limits, allocating the announced length up front would abort the test
+        let mut ctx = DecodeContext::new(DecodeLimits::UNLIMITED);
+        let error = String::decode_with(&mut Bytes::from_static(&HUGE_LENGTH), &mut ctx);
+        assert!(error.unwrap_err().is_unexpected_end());
+
+        let mut recv: &[u8] = &HUGE_LENGTH;
+        let mut ctx = DecodeContext::new(DecodeLimits::UNLIMITED);
+        let error = String::read_with(&mut recv, &mut ctx).await;
+        assert!(error.unwrap_err().is_unexpected_end());
+
+        let mut recv: &[u8] = &HUGE_LENGTH;
+        let mut ctx = DecodeContext::new(DecodeLimits::UNLIMITED);
+        let error = Vec::<u64>::read_with(&mut recv, &mut ctx).await;
+        assert!(error.unwrap_err().is_unexpected_end());
+    }
+
+    #[tokio::test]
+    async fn too_deep_values_fail() {
+        let limits = DecodeLimits {
+            max_depth: 2,
+            ..DecodeLimits::default()
+        };
+        let bytes = Some(Some(Some(1u8))).to_bytes().unwrap();
+
+        let error = Option::<Option<Option<u8>>>::decode_with(
+            &mut bytes.clone(),
+            &mut DecodeContext::new(limits),
+        );
+        assert_eq!(
+            limit_error(&error.unwrap_err()),
+            Some(&DecodeLimitError::TooDeep { limit: 2 })
+        );
+
+        let mut recv: &[u8] = &bytes;
+        let error =
+            Option::<Option<Option<u8>>>::read_with(&mut recv, &mut DecodeContext::new(limits))
+                .await;
+        assert_eq!(
+            limit_error(&error.unwrap_err()),
+            Some(&DecodeLimitError::TooDeep { limit: 2 })
+        );
+
+        let value = Option::<Option<u8>>::decode_with(
+            &mut Some(Some(1u8)).to_bytes().unwrap(),
+            &mut DecodeContext::new(limits),
+        );
+        assert_eq!(value.unwrap(), Some(Some(1)));
+    }
+
+    /// Decodes the truncated encoding of `value`, then `value` itself with the same context, which
+    /// must be back to its initial depth after each of them.
+    async fn assert_failure_leaves_the_context<T>(value: T)
+    where
+        T: Payload + PartialEq + std::fmt::Debug + Sync,
+    {
+        let bytes = value.to_bytes().unwrap();
+        let truncated = bytes.slice(..bytes.len() - 1);
+        let mut ctx = DecodeContext::new(DecodeLimits {
+            max_depth: 2,
+            ..DecodeLimits::default()
+        });
+
+        let error = T::decode_with(&mut truncated.clone(), &mut ctx).unwrap_err();
+        assert!(error.is_unexpected_end());
+        assert_eq!(ctx.depth, 0);
+        assert_eq!(T::decode_with(&mut bytes.clone(), &mut ctx).unwrap(), value);
+        assert_eq!(ctx.depth, 0);
+
+        let mut recv: &[u8] = &truncated;
+        let error = T::read_with(&mut recv, &mut ctx).await.unwrap_err();
+        assert!(error.is_unexpected_end());
+        assert_eq!(ctx.depth, 0);
+        let mut recv: &[u8] = &bytes;
+        assert_eq!(T::read_with(&mut recv, &mut ctx).await.unwrap(), value);
+    }
+
+    #[tokio::test]
+    async fn failed_decodes_leave_the_context() {
+        assert_failure_leaves_the_context(vec![vec![1u8, 2], vec![3]]).await;
+        assert_failure_leaves_the_context(Some(Some(4u16))).await;
+        assert_failure_leaves_the_context(BTreeMap::from([(1u8, vec![2u8])])).await;
+        assert_failure_leaves_the_context([Some(5u32), Some(6)]).await;
+        assert_failure_leaves_the_context(Result::<Option<u8>, u8>::Ok(Some(7))).await;
+    }
+}
@@ -0,0 +1,239 @@
+//! Code running around every call of a service, like logging or authentication checks.
+//!
+//! A [Middleware] gets each [Call] along with the next [Dispatch], which it may call or not:
+//!
+//! ```text
+//! let server = ChatServiceServer::new(service)
+//!     .with(RequireAuth::new(auth).allow::<chat_service_method::Login>())
+//!     .with(Logging::new("chat"));
+//! ConnectionHandler::new(server).serve(&connection).await;
+//! ```
+//!
+//! Here a call is logged first, then refused if the connection didn't log in, and only then
+//! handled by the service.
+
+use std::collections::HashSet;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tokio::io::AsyncWriteExt;
+
+use crate::rpc::{Call, Dispatch};
+use crate::{
+    AsyncRead, AsyncWrite, Command, Encode, ErrorCode, PayloadError, Response, ResponseError,
+};
+
+/// Runs around the calls of a [Dispatch], added with [Dispatch::with].
+pub trait Middleware: Send + Sync {
+    /// Handles `call`, usually by passing it on to `next`.
+    ///
+    /// A middleware refusing a call must still answer it, or fail so that its stream is reset.
+    fn call<D, W, R>(
+        &self,
+        call: &Call,
+        next: &D,
+        send: &mut W,
+        recv: &mut R,
+    ) -> impl Future<Output = Result<(), PayloadError>> + Send
+    where
+        D: Dispatch,
+        W: AsyncWrite + Unpin + Send + ?Sized,
+        R: AsyncRead + Unpin + Send + ?Sized;
+}
+
+/// A [Dispatch] running its calls through a [Middleware], see [Dispatch::with].
+#[derive(Debug)]
+pub struct Layered<M, D> {
+    middleware: M,
+    inner: D,
+}
+
+impl<M, D> Layered<M, D> {
+    pub fn new(middleware: M, inner: D) -> Layered<M, D> {
+        Layered { middleware, inner }
+    }
+
+    pub fn middleware(&self) -> &M {
+        &self.middleware
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+}
+
+impl<M: Middleware, D: Dispatch> Dispatch for Layered<M, D> {
+    async fn handle<W, R>(
+        &self,
+        call: &Call,
+        send: &mut W,
+        recv: &mut R,
+    ) -> Result<(), PayloadError>
+    where
+        W: AsyncWrite + Unpin + Send + ?Sized,
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        self.middleware.call(call, &self.inner, send, recv).await
+    }
+
+    fn method_name(&self, id: u8) -> Option<&'static str> {
+        self.inner.method_name(id)
+    }
+}
+
+/// Logs a line for every call once it ends, with its method, the address of the client and how
+/// long it took.
+///
+/// The lines are written to stderr, unless another sink is set with [Logging::with_sink]. The
+/// calls abandoned by the [ConnectionHandler](crate::rpc::ConnectionHandler), because they missed
+/// their deadline or the client stopped them, aren't logged.
+#[derive(Clone)]
+pub struct Logging {
+    prefix: String,
+    sink: Arc<dyn Fn(&str) + Send + Sync>,
+}
+
+impl Logging {
+    /// `prefix` starts every line, like the name of the server.
+    pub fn new(prefix: impl Into<String>) -> Logging {
+        Logging {
+            prefix: prefix.into(),
+            sink: Arc::new(|line| eprintln!("{line}")),
+        }
+    }
+
+    /// Hands every line to `sink` instead of writing it to stderr, like a closure forwarding it to
+    /// a logger or ignoring it.
+    pub fn with_sink(mut self, sink: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.sink = Arc::new(sink);
+        self
+    }
+}
+
+impl Debug for Logging {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Logging")
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Middleware for Logging {
+    async fn call<D, W, R>(
+        &self,
+        call: &Call,
+        next: &D,
+        send: &mut W,
+        recv: &mut R,
+    ) -> Result<(), PayloadError>
+    where
+        D: Dispatch,
+        W: AsyncWrite + Unpin + Send + ?Sized,
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let result = next.handle(call, send, recv).await;
+
+        let method = match next.method_name(call.id()) {
+            Some(name) => name.to_string(),
+            None => format!("unknown method {}", call.id()),
+        };
+        let client = call.connection().remote_address();
+        let line = match &result {
+            Ok(()) => format!(
+                "[{}] {client} {method} answered in {:?}",
+                self.prefix,
+                call.elapsed()
+            ),
+            Err(e) => format!(
+                "[{}] {client} {method} failed after {:?}: {e}",
+                self.prefix,
+                call.elapsed()
+            ),
+        };
+        (self.sink)(&line);
+
+        result
+    }
+}
+
+/// Whether the client of a connection logged in, shared by the service setting it and
+/// [RequireAuth] checking it.
+#[derive(Clone, Debug, Default)]
+pub struct AuthState(Arc<AtomicBool>);
+
+impl AuthState {
+    /// A connection that didn't log in yet.
+    pub fn new() -> AuthState {
+        AuthState::default()
+    }
+
+    pub fn set_authenticated(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// Answers the calls made before the connection logged in with an [ErrorCode::UNAUTHENTICATED]
+/// error, except for the methods allowed with [RequireAuth::allow] like the login itself.
+#[derive(Clone, Debug)]
+pub struct RequireAuth {
+    state: AuthState,
+    allowed: HashSet<u8>,
+}
+
+impl RequireAuth {
+    pub fn new(state: AuthState) -> RequireAuth {
+        RequireAuth {
+            state,
+            allowed: HashSet::new(),
+        }
+    }
+
+    /// Lets the method `C` be called before logging in.
+    pub fn allow<C: Command>(mut self) -> Self {
+        self.allowed.insert(C::KIND.into());
+        self
+    }
+
+    pub fn state(&self) -> &AuthState {
+        &self.state
+    }
+}
+
+impl Middleware for RequireAuth {
+    async fn call<D, W, R>(
+        &self,
+        call: &Call,
+        next: &D,
+        send: &mut W,
+        recv: &mut R,
+    ) -> Result<(), PayloadError>
+    where
+        D: Dispatch,
+        W: AsyncWrite + Unpin + Send + ?Sized,
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        if self.state.is_authenticated() || self.allowed.contains(&call.id()) {
+            return next.handle(call, send, recv).await;
+        }
+
+        // an error is encoded the same whatever the output of the method, so the input doesn't
+        // even need to be read
+        let error = ResponseError::new(ErrorCode::UNAUTHENTICATED, "Log in first!");
+        let error = match next.method_name(call.id()) {
+            Some(name) => error.with_details(format!("{name} requires logging in")),
+            None => error,
+        };
+        Response::<()>::Err(error)
+            .write_to_send_stream(send)
+            .await?;
+        send.shutdown().await?;
+
+        Ok(())
+    }
+}
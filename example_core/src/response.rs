@@ -0,0 +1,220 @@
+//! The answer to a request: either its output, or an error telling the client why it failed.
+//!
+//! [Response] is a `Result` whose error is a [ResponseError], encoded as a `u8` tag (0 for the
+//! output, 1 for the error) followed by the output or the error:
+//!
+//! ```text
+//! ResponseError {
+//!     code: u16,
+//!     message: String,
+//!     details: Option<String>,
+//! }
+//! ```
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::schema::{Definition, Definitions, FieldSchema, Schema};
+use crate::{AsyncRead, Bytes, BytesMut, DecodeContext, Encode, Payload, PayloadError};
+
+/// The output of a request, or the reason it failed.
+pub type Response<T> = Result<T, ResponseError>;
+
+/// Identifies the kind of a [ResponseError], so that a client can react to it without parsing the
+/// message.
+///
+/// The codes below 1000 are defined here, the others are free for the protocols.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ErrorCode(pub u16);
+
+impl ErrorCode {
+    /// The request failed for a reason the client can't do anything about.
+    pub const INTERNAL: ErrorCode = ErrorCode(1);
+    /// The input of the request couldn't be decoded.
+    pub const INVALID_INPUT: ErrorCode = ErrorCode(2);
+    /// The client must log in before making the request, or its credentials were refused.
+    pub const UNAUTHENTICATED: ErrorCode = ErrorCode(3);
+    /// The client is logged in, but isn't allowed to make the request.
+    pub const PERMISSION_DENIED: ErrorCode = ErrorCode(4);
+    /// Something the request refers to doesn't exist.
+    pub const NOT_FOUND: ErrorCode = ErrorCode(5);
+    /// Something the request creates already exists.
+    pub const ALREADY_EXISTS: ErrorCode = ErrorCode(6);
+
+    /// The name of a code defined here, like `invalid input`.
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            ErrorCode::INTERNAL => Some("internal error"),
+            ErrorCode::INVALID_INPUT => Some("invalid input"),
+            ErrorCode::UNAUTHENTICATED => Some("unauthenticated"),
+            ErrorCode::PERMISSION_DENIED => Some("permission denied"),
+            ErrorCode::NOT_FOUND => Some("not found"),
+            ErrorCode::ALREADY_EXISTS => Some("already exists"),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{name}"),
+            None => write!(f, "error {}", self.0),
+        }
+    }
+}
+
+impl Encode for ErrorCode {
+    fn encoded_len(&self) -> usize {
+        self.0.encoded_len()
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        self.0.encode(buf)
+    }
+}
+
+impl Payload for ErrorCode {
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<ErrorCode, PayloadError> {
+        Ok(ErrorCode(u16::decode_with(buf, ctx)?))
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<ErrorCode, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        Ok(ErrorCode(u16::read_with(recv, ctx).await?))
+    }
+
+    fn schema(defs: &mut Definitions) -> Schema {
+        u16::schema(defs)
+    }
+}
+
+/// Why a request failed, displayed as `code: message (details)`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ResponseError {
+    pub code: ErrorCode,
+    /// Meant to be shown to the user.
+    pub message: String,
+    /// Meant for debugging, like the error that caused this one.
+    pub details: Option<String>,
+}
+
+impl ResponseError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> ResponseError {
+        ResponseError {
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: impl Display) -> ResponseError {
+        self.details = Some(details.to_string());
+        self
+    }
+
+    /// An [ErrorCode::INTERNAL] error, `details` being the error that caused it.
+    pub fn internal(details: impl Display) -> ResponseError {
+        ResponseError::new(ErrorCode::INTERNAL, "internal error").with_details(details)
+    }
+}
+
+impl Display for ResponseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)?;
+        if let Some(details) = &self.details {
+            write!(f, " ({details})")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for ResponseError {}
+
+impl Encode for ResponseError {
+    fn encoded_len(&self) -> usize {
+        self.code.encoded_len() + self.message.encoded_len() + self.details.encoded_len()
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        self.code.encode(buf)?;
+        self.message.encode(buf)?;
+        self.details.encode(buf)
+    }
+}
+
+impl Payload for ResponseError {
+    fn decode_with(
+        buf: &mut Bytes,
+        ctx: &mut DecodeContext,
+    ) -> Result<ResponseError, PayloadError> {
+        let mut nested = ctx.enter()?;
+        let ctx = &mut *nested;
+        let code =
+            ErrorCode::decode_with(buf, ctx).map_err(|e| e.in_field("ResponseError.code"))?;
+        let message =
+            String::decode_with(buf, ctx).map_err(|e| e.in_field("ResponseError.message"))?;
+        let details = Option::<String>::decode_with(buf, ctx)
+            .map_err(|e| e.in_field("ResponseError.details"))?;
+
+        Ok(ResponseError {
+            code,
+            message,
+            details,
+        })
+    }
+
+    async fn read_with<R>(
+        recv: &mut R,
+        ctx: &mut DecodeContext,
+    ) -> Result<ResponseError, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let mut nested = ctx.enter()?;
+        let ctx = &mut *nested;
+        let code = ErrorCode::read_with(recv, ctx)
+            .await
+            .map_err(|e| e.in_field("ResponseError.code"))?;
+        let message = String::read_with(recv, ctx)
+            .await
+            .map_err(|e| e.in_field("ResponseError.message"))?;
+        let details = Option::<String>::read_with(recv, ctx)
+            .await
+            .map_err(|e| e.in_field("ResponseError.details"))?;
+
+        Ok(ResponseError {
+            code,
+            message,
+            details,
+        })
+    }
+
+    fn schema(defs: &mut Definitions) -> Schema {
+        defs.define::<Self>(|defs| Definition::Struct {
+            fields: vec![
+                FieldSchema {
+                    name: "code".to_string(),
+                    tag: None,
+                    default: false,
+                    schema: ErrorCode::schema(defs),
+                },
+                FieldSchema {
+                    name: "message".to_string(),
+                    tag: None,
+                    default: false,
+                    schema: String::schema(defs),
+                },
+                FieldSchema {
+                    name: "details".to_string(),
+                    tag: None,
+                    default: false,
+                    schema: Option::<String>::schema(defs),
+                },
+            ],
+        })
+    }
+}
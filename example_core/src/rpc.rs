@@ -0,0 +1,571 @@
+//! Calls to a service over QUIC, one bidirectional stream per call.
+//!
+//! A service is a trait marked `#[lib::service]`, whose methods take a [Payload] input and return a
+//! [Response] of a payload:
+//!
+//! ```text
+//! #[lib::service]
+//! pub trait ChatService {
+//!     async fn login(&self, input: LoginInput) -> Response<User>;
+//! }
+//! ```
+//!
+//! The attribute generates a `Command` enum listing the methods, `ChatServiceMethod`, a
+//! `ChatServiceClient` whose methods return `Result<T, RpcError>`, and a `ChatServiceServer`
+//! routing the calls to an implementation of the trait, see [Dispatch]. A [ConnectionHandler] runs
+//! a server on the calls of a connection.
+//!
+//! A call opens a stream, sends the id of the method followed by the input and finishes the
+//! stream, the server answering with the [Response] before finishing its side.
+//!
+//! Both sides bound the calls with [Deadlines]. A call that isn't answered has its stream reset
+//! and stopped with one of the [ResetCodes]: the client gives up on a call when its deadline
+//! expires or when its future is dropped, and the server abandons a call once the client stopped
+//! it.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::future::{poll_fn, Future};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+pub use quinn::Connection;
+use quinn::{ConnectionError, ReadError, RecvStream, SendStream, VarInt, WriteError};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+
+use crate::middleware::{Layered, Middleware};
+use crate::{
+    AsyncRead, AsyncWrite, Command, DecodeLimits, ErrorCode, Payload, PayloadError, Response,
+    ResponseError,
+};
+
+/// The error of a call made by a client generated by `#[lib::service]`.
+#[derive(Debug)]
+pub enum RpcError {
+    /// The server handled the call and answered with an error.
+    Remote(ResponseError),
+    /// Opening the stream of the call failed.
+    Connection(ConnectionError),
+    /// Sending the input or receiving the output failed, or the server answered with invalid data.
+    Payload(PayloadError),
+    /// The call wasn't answered before its deadline, its stream was reset.
+    DeadlineExceeded,
+    /// The server refused the call, already running as many calls as it allows for the
+    /// connection. Calling again later may succeed.
+    RateLimited,
+}
+
+impl Display for RpcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcError::Remote(e) => write!(f, "{e}"),
+            RpcError::Connection(e) => write!(f, "connection lost: {e}"),
+            RpcError::Payload(e) => write!(f, "{e}"),
+            RpcError::DeadlineExceeded => write!(f, "deadline exceeded"),
+            RpcError::RateLimited => write!(f, "too many calls at once"),
+        }
+    }
+}
+
+impl Error for RpcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RpcError::Remote(_) | RpcError::DeadlineExceeded | RpcError::RateLimited => None,
+            RpcError::Connection(e) => Some(e),
+            RpcError::Payload(e) => Some(e),
+        }
+    }
+}
+
+impl From<ConnectionError> for RpcError {
+    fn from(e: ConnectionError) -> Self {
+        RpcError::Connection(e)
+    }
+}
+
+impl From<PayloadError> for RpcError {
+    fn from(e: PayloadError) -> Self {
+        RpcError::Payload(e)
+    }
+}
+
+/// The time a call may take, by command id.
+///
+/// A client counts from the opening of the stream until the output is received, and a server
+/// from the acceptance of the stream until the output is sent. A server bounds the reading of the
+/// id by the default deadline, the command being unknown until then.
+#[derive(Clone, Debug, Default)]
+pub struct Deadlines {
+    default: Option<Duration>,
+    commands: HashMap<u8, Duration>,
+}
+
+impl Deadlines {
+    /// No call has a deadline.
+    pub fn new() -> Deadlines {
+        Deadlines::default()
+    }
+
+    /// Sets the deadline of the commands without their own.
+    pub fn with_default(mut self, deadline: Duration) -> Deadlines {
+        self.default = Some(deadline);
+        self
+    }
+
+    /// Sets the deadline of the command `C`, like `chat_service_method::Login`.
+    pub fn with_command<C: Command>(mut self, deadline: Duration) -> Deadlines {
+        self.commands.insert(C::KIND.into(), deadline);
+        self
+    }
+
+    pub fn default_deadline(&self) -> Option<Duration> {
+        self.default
+    }
+
+    /// The deadline of the command `id`.
+    pub fn get(&self, id: u8) -> Option<Duration> {
+        self.commands.get(&id).copied().or(self.default)
+    }
+}
+
+/// The application error codes the streams of calls are reset and stopped with, when they aren't
+/// answered.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ResetCodes {
+    /// The call failed, like a server receiving an unknown method or a client receiving an
+    /// invalid output. 1 by default.
+    pub failed: VarInt,
+    /// The deadline of the call expired. 2 by default.
+    pub deadline_exceeded: VarInt,
+    /// The client dropped the call before receiving its output. 3 by default.
+    pub cancelled: VarInt,
+    /// The server refused the call, see [ConnectionHandler::with_max_concurrent_calls]. 4 by
+    /// default.
+    pub rate_limited: VarInt,
+}
+
+impl Default for ResetCodes {
+    fn default() -> Self {
+        ResetCodes {
+            failed: VarInt::from_u32(1),
+            deadline_exceeded: VarInt::from_u32(2),
+            cancelled: VarInt::from_u32(3),
+            rate_limited: VarInt::from_u32(4),
+        }
+    }
+}
+
+/// Awaits `future` until `deadline`, returning `None` if it expired first.
+async fn within<F: Future>(deadline: Option<Instant>, future: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+/// The streams of a call made by a client, reset and stopped with the `cancelled` code if they
+/// are dropped before the call ended.
+struct CallStreams {
+    send: SendStream,
+    recv: RecvStream,
+    cancelled: Option<VarInt>,
+}
+
+impl CallStreams {
+    fn close(&mut self, code: VarInt) {
+        // fails when the stream is already closed, which is fine
+        let _ = self.send.reset(code);
+        let _ = self.recv.stop(code);
+        self.cancelled = None;
+    }
+}
+
+impl Drop for CallStreams {
+    fn drop(&mut self) {
+        if let Some(code) = self.cancelled {
+            self.close(code);
+        }
+    }
+}
+
+/// Makes calls on a connection, wrapped by the clients generated by `#[lib::service]`.
+#[derive(Clone, Debug)]
+pub struct RpcClient {
+    connection: Connection,
+    limits: DecodeLimits,
+    deadlines: Deadlines,
+    reset_codes: ResetCodes,
+}
+
+impl RpcClient {
+    pub fn new(connection: Connection) -> RpcClient {
+        RpcClient::with_limits(connection, DecodeLimits::default())
+    }
+
+    /// `limits` bound the outputs of the calls.
+    pub fn with_limits(connection: Connection, limits: DecodeLimits) -> RpcClient {
+        RpcClient {
+            connection,
+            limits,
+            deadlines: Deadlines::new(),
+            reset_codes: ResetCodes::default(),
+        }
+    }
+
+    pub fn with_deadlines(mut self, deadlines: Deadlines) -> RpcClient {
+        self.deadlines = deadlines;
+        self
+    }
+
+    pub fn with_reset_codes(mut self, reset_codes: ResetCodes) -> RpcClient {
+        self.reset_codes = reset_codes;
+        self
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Calls the method `C` on a new stream and waits for its output.
+    ///
+    /// Dropping the future before it completes resets and stops the stream, so that the server
+    /// abandons the call.
+    pub async fn call<C, T>(&self, input: &C::Input) -> Result<T, RpcError>
+    where
+        C: Command<Output = Response<T>>,
+    {
+        let deadline = self
+            .deadlines
+            .get(C::KIND.into())
+            .map(|deadline| Instant::now() + deadline);
+
+        let (send, recv) = within(deadline, self.connection.open_bi())
+            .await
+            .ok_or(RpcError::DeadlineExceeded)??;
+        let mut streams = CallStreams {
+            send,
+            recv,
+            cancelled: Some(self.reset_codes.cancelled),
+        };
+
+        let call = async {
+            C::write_input(&mut streams.send, input).await?;
+            streams.send.finish().await.map_err(PayloadError::from)?;
+
+            C::read_output(&mut streams.recv, &self.limits).await
+        };
+        match within(deadline, call).await {
+            Some(Ok(output)) => {
+                streams.cancelled = None;
+
+                output.map_err(RpcError::Remote)
+            }
+            Some(Err(e)) if closed_with(&e) == Some(self.reset_codes.rate_limited) => {
+                streams.cancelled = None;
+
+                Err(RpcError::RateLimited)
+            }
+            Some(Err(e)) => {
+                streams.close(self.reset_codes.failed);
+
+                Err(e.into())
+            }
+            None => {
+                streams.close(self.reset_codes.deadline_exceeded);
+
+                Err(RpcError::DeadlineExceeded)
+            }
+        }
+    }
+}
+
+/// The code the server reset or stopped the stream of a call with, if that's why it failed.
+fn closed_with(e: &PayloadError) -> Option<VarInt> {
+    match e.kind() {
+        PayloadError::Read(ReadError::Reset(code)) => Some(*code),
+        PayloadError::Write(WriteError::Stopped(code)) => Some(*code),
+        _ => None,
+    }
+}
+
+/// Answers a call of the method `C` with the output of `handler`, once its id was read.
+///
+/// Invalid input is answered with an [ErrorCode::INVALID_INPUT] error, so that the client gets an
+/// [RpcError::Remote] rather than a reset stream, and the call succeeds.
+pub async fn respond<C, T, H, F, W, R>(
+    send: &mut W,
+    recv: &mut R,
+    limits: &DecodeLimits,
+    handler: H,
+) -> Result<(), PayloadError>
+where
+    C: Command<Output = Response<T>>,
+    H: FnOnce(C::Input) -> F,
+    F: Future<Output = Response<T>>,
+    W: AsyncWrite + Unpin + Send + ?Sized,
+    R: AsyncRead + Unpin + Send + ?Sized,
+{
+    let output = match C::read_input(recv, limits).await {
+        Ok(input) => handler(input).await,
+        Err(e) if e.is_transport() => return Err(e),
+        Err(e) => Err(ResponseError::new(
+            ErrorCode::INVALID_INPUT,
+            "the input could not be decoded",
+        )
+        .with_details(e)),
+    };
+
+    C::write_output(send, &output).await?;
+    send.shutdown().await?;
+
+    Ok(())
+}
+
+/// A call served by a [ConnectionHandler], once the id of its method was read.
+#[derive(Clone, Debug)]
+pub struct Call {
+    id: u8,
+    connection: Connection,
+    started: Instant,
+}
+
+impl Call {
+    pub fn new(id: u8, connection: Connection) -> Call {
+        Call {
+            id,
+            connection,
+            started: Instant::now(),
+        }
+    }
+
+    /// The id of the method called.
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// The connection the call was made on.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// The time since the stream of the call was accepted.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// Routes the calls made on a stream to a service, implemented by the servers generated by
+/// `#[lib::service]`.
+///
+/// [Dispatch::with] wraps a dispatcher in a [Middleware], like to log the calls.
+pub trait Dispatch: Send + Sync {
+    /// Handles a call: reads its input from `recv`, calls the method and writes its output to
+    /// `send` before shutting it down.
+    ///
+    /// Fails when the call couldn't be answered, like for an unknown method which fails with
+    /// [PayloadError::UnknownDiscriminant], the type of its output being unknown.
+    fn handle<W, R>(
+        &self,
+        call: &Call,
+        send: &mut W,
+        recv: &mut R,
+    ) -> impl Future<Output = Result<(), PayloadError>> + Send
+    where
+        W: AsyncWrite + Unpin + Send + ?Sized,
+        R: AsyncRead + Unpin + Send + ?Sized;
+
+    /// The name of the method `id`, like `send_message`, or `None` for an unknown method.
+    fn method_name(&self, id: u8) -> Option<&'static str> {
+        let _ = id;
+        None
+    }
+
+    /// Runs the calls through `middleware` before this dispatcher, the last middleware added
+    /// running first.
+    fn with<M: Middleware>(self, middleware: M) -> Layered<M, Self>
+    where
+        Self: Sized,
+    {
+        Layered::new(middleware, self)
+    }
+}
+
+/// The send stream of a call served by a [ConnectionHandler], shared between the call writing
+/// its output and the task watching for the client stopping it.
+#[derive(Clone)]
+struct SharedSend(Arc<Mutex<SendStream>>);
+
+impl SharedSend {
+    fn with<T>(&self, f: impl FnOnce(Pin<&mut SendStream>) -> T) -> T {
+        let mut send = self
+            .0
+            .lock()
+            .expect("the lock is never held across a panic");
+
+        f(Pin::new(&mut send))
+    }
+}
+
+impl AsyncWrite for SharedSend {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.with(|send| send.poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.with(|send| send.poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.with(|send| send.poll_shutdown(cx))
+    }
+}
+
+/// Serves the calls made on a connection, each on its own task so that a slow call doesn't hold
+/// up the others.
+///
+/// At most `max_concurrent_calls` calls run at once, further streams being reset and stopped with
+/// [ResetCodes::rate_limited] until a call ends, so that the client gets an
+/// [RpcError::RateLimited] rather than waiting. A call that fails only affects its own stream, which is reset and stopped with one of
+/// the [ResetCodes]. A call the client stops is abandoned.
+#[derive(Debug)]
+pub struct ConnectionHandler<D> {
+    dispatch: Arc<D>,
+    max_concurrent_calls: usize,
+    deadlines: Arc<Deadlines>,
+    reset_codes: ResetCodes,
+}
+
+impl<D> ConnectionHandler<D>
+where
+    D: Dispatch + 'static,
+{
+    /// Runs up to 32 calls at once, without deadlines.
+    pub fn new(dispatch: D) -> ConnectionHandler<D> {
+        ConnectionHandler {
+            dispatch: Arc::new(dispatch),
+            max_concurrent_calls: 32,
+            deadlines: Arc::new(Deadlines::new()),
+            reset_codes: ResetCodes::default(),
+        }
+    }
+
+    /// The calls run at once, the streams opened beyond being refused. Panics if
+    /// `max_concurrent_calls` is 0.
+    pub fn with_max_concurrent_calls(mut self, max_concurrent_calls: usize) -> Self {
+        assert!(
+            max_concurrent_calls > 0,
+            "at least one call must be allowed"
+        );
+        self.max_concurrent_calls = max_concurrent_calls;
+        self
+    }
+
+    pub fn with_deadlines(mut self, deadlines: Deadlines) -> Self {
+        self.deadlines = Arc::new(deadlines);
+        self
+    }
+
+    pub fn with_reset_codes(mut self, reset_codes: ResetCodes) -> Self {
+        self.reset_codes = reset_codes;
+        self
+    }
+
+    pub fn dispatch(&self) -> &D {
+        &self.dispatch
+    }
+
+    /// Accepts the streams of `connection` until it is closed, returning the reason like
+    /// [Connection::closed].
+    ///
+    /// The calls still running keep going on their own tasks once this returns.
+    pub async fn serve(&self, connection: &Connection) -> ConnectionError {
+        let permits = Arc::new(Semaphore::new(self.max_concurrent_calls));
+
+        loop {
+            let (mut send, mut recv) = match connection.accept_bi().await {
+                Ok(streams) => streams,
+                Err(e) => return e,
+            };
+            let Ok(permit) = Arc::clone(&permits).try_acquire_owned() else {
+                let _ = send.reset(self.reset_codes.rate_limited);
+                let _ = recv.stop(self.reset_codes.rate_limited);
+
+                continue;
+            };
+
+            let dispatch = Arc::clone(&self.dispatch);
+            let connection = connection.clone();
+            let deadlines = Arc::clone(&self.deadlines);
+            let reset_codes = self.reset_codes;
+            tokio::spawn(async move {
+                serve_call(&*dispatch, connection, &deadlines, reset_codes, send, recv).await;
+
+                drop(permit);
+            });
+        }
+    }
+}
+
+async fn serve_call<D: Dispatch>(
+    dispatch: &D,
+    connection: Connection,
+    deadlines: &Deadlines,
+    reset_codes: ResetCodes,
+    send: SendStream,
+    mut recv: RecvStream,
+) {
+    let start = Instant::now();
+    let send = SharedSend(Arc::new(Mutex::new(send)));
+
+    let call = async {
+        let id = within(
+            deadlines
+                .default_deadline()
+                .map(|deadline| start + deadline),
+            u8::read_from_recv_stream(&mut recv),
+        )
+        .await
+        .ok_or(reset_codes.deadline_exceeded)?
+        .map_err(|_| reset_codes.failed)?;
+
+        let call = Call {
+            id,
+            connection,
+            started: start,
+        };
+        let mut call_send = send.clone();
+        within(
+            deadlines.get(id).map(|deadline| start + deadline),
+            dispatch.handle(&call, &mut call_send, &mut recv),
+        )
+        .await
+        .ok_or(reset_codes.deadline_exceeded)?
+        .map_err(|_| reset_codes.failed)
+    };
+    let stopped = poll_fn(|cx| send.with(|mut send| send.poll_stopped(cx)));
+
+    let code = tokio::select! {
+        // an answered call may be stopped by a client that read its output
+        biased;
+        result = call => match result {
+            Ok(()) => return,
+            Err(code) => code,
+        },
+        // the client gave up on the call, so its output isn't needed anymore
+        _ = stopped => reset_codes.cancelled,
+    };
+
+    // fails when the stream is already closed, which is fine
+    send.with(|mut send| send.reset(code)).ok();
+    let _ = recv.stop(code);
+}
@@ -0,0 +1,304 @@
+//! A description of the wire format of [Payload] types, for peers written in other languages.
+//!
+//! [Payload::schema] describes a type as a [Schema]. Types deriving `Payload` are described by a
+//! [Definition] listing their fields, stored once in [Definitions] under their name and referenced
+//! elsewhere by a [Schema::Ref], so that recursive types have a finite description. Names leave
+//! out module paths, so the types of one [Definitions] must have distinct names.
+//!
+//! Every type serializes to JSON with serde, a [Definitions] being a map from type names to their
+//! definitions:
+//!
+//! ```text
+//! "Message": {
+//!   "kind": "struct",
+//!   "fields": [
+//!     { "name": "message", "default": false, "schema": { "type": "string", "length": "varint" } },
+//!     { "name": "sent_by", "default": false, "schema": { "type": "option", "value": { "type": "ref", "name": "User" } } }
+//!   ]
+//! }
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use crate::Payload;
+
+/// The encoding of a value.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Schema {
+    /// 1 byte, `0` or `1`.
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    F32,
+    F64,
+    /// A unicode scalar value as a `u32`.
+    Char,
+    /// Nothing.
+    Unit,
+    /// A QUIC variable-length integer, see [VarInt](crate::VarInt). Signed integers are
+    /// `zigzag` encoded first.
+    #[serde(rename = "varint")]
+    VarInt {
+        zigzag: bool,
+    },
+    /// The length of the string in bytes, then its UTF-8 bytes.
+    String {
+        length: LengthEncoding,
+    },
+    /// The number of bytes, then the bytes.
+    Bytes {
+        length: LengthEncoding,
+    },
+    /// A `u8` tag, `0` for none or `1` followed by the value.
+    Option {
+        value: Box<Schema>,
+    },
+    /// The number of items, then the items. Used by `Vec` and sets.
+    Seq {
+        length: LengthEncoding,
+        items: Box<Schema>,
+    },
+    /// The number of entries, then each key followed by its value.
+    Map {
+        length: LengthEncoding,
+        key: Box<Schema>,
+        value: Box<Schema>,
+    },
+    /// `len` items, without a length since it is known.
+    Array {
+        len: usize,
+        items: Box<Schema>,
+    },
+    /// The items one after the other.
+    Tuple {
+        items: Vec<Schema>,
+    },
+    /// A `u8` tag, `0` followed by `ok` or `1` followed by `err`.
+    Result {
+        ok: Box<Schema>,
+        err: Box<Schema>,
+    },
+    /// The 16 bytes of the UUID.
+    Uuid,
+    /// The seconds as a `u64`, then the sub-second nanoseconds as a `u32`.
+    Duration,
+    /// The [Schema::Duration] since the UNIX epoch.
+    SystemTime,
+    /// The 4 bytes of the address.
+    Ipv4Addr,
+    /// The 16 bytes of the address.
+    Ipv6Addr,
+    /// A `u8` tag, `4` followed by an [Schema::Ipv4Addr] or `6` followed by an [Schema::Ipv6Addr].
+    IpAddr,
+    /// An [Schema::IpAddr], then the port as a `u16`.
+    SocketAddr,
+    /// A type described by the [Definition] named `name`.
+    Ref {
+        name: String,
+    },
+    /// A field encoded by the functions of a `#[payload(with = "module")]` module.
+    With {
+        module: String,
+    },
+    /// A type whose [Payload] impl doesn't describe its encoding.
+    Opaque {
+        ty: String,
+    },
+}
+
+impl Schema {
+    /// Replaces the length encoding of a string, bytes, sequence or map, as done by
+    /// `#[payload(len = "...")]`.
+    pub fn with_length(self, length: LengthEncoding) -> Schema {
+        match self {
+            Schema::String { .. } => Schema::String { length },
+            Schema::Bytes { .. } => Schema::Bytes { length },
+            Schema::Seq { items, .. } => Schema::Seq { length, items },
+            Schema::Map { key, value, .. } => Schema::Map { length, key, value },
+            schema => schema,
+        }
+    }
+}
+
+/// The encoding of the length prefixing a string, bytes, sequence or map, see
+/// [LengthPrefix](crate::LengthPrefix).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LengthEncoding {
+    U8,
+    U16,
+    U32,
+    U64,
+    #[serde(rename = "varint")]
+    VarInt,
+}
+
+/// The encoding of a type deriving `Payload`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Definition {
+    /// The fields one after the other.
+    Struct { fields: Vec<FieldSchema> },
+    /// A `#[payload(versioned)]` struct, see [versioned](crate::versioned).
+    VersionedStruct { fields: Vec<FieldSchema> },
+    /// A `u8` tag identifying the variant, then its fields.
+    Enum { variants: Vec<VariantSchema> },
+}
+
+/// A field sent on the wire, skipped fields are left out.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct FieldSchema {
+    /// The name of the field, or its index in a tuple struct.
+    pub name: String,
+    /// The tag of the field in a versioned struct.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<u32>,
+    /// Whether the field may be missing, being filled with its default value when it wasn't sent.
+    /// Outside of a versioned struct, a trailing field is missing when the data ends before it.
+    pub default: bool,
+    pub schema: Schema,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct VariantSchema {
+    pub name: String,
+    pub tag: u8,
+    pub fields: Vec<FieldSchema>,
+}
+
+/// The definitions of named types, by name.
+#[derive(Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct Definitions {
+    definitions: BTreeMap<String, Definition>,
+    /// Types whose definition is being built, referenced without being defined again.
+    #[serde(skip)]
+    pending: BTreeSet<String>,
+    /// The full path of the type defined under each name.
+    #[serde(skip)]
+    paths: BTreeMap<String, &'static str>,
+}
+
+impl Definitions {
+    pub fn new() -> Definitions {
+        Definitions::default()
+    }
+
+    /// Adds the definition of `T` and of the types it uses, returning the schema of `T`.
+    ///
+    /// Panics if one of them has the same name as another type, see [Definitions::define].
+    pub fn add<T: Payload>(&mut self) -> Schema {
+        T::schema(self)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Definition> {
+        self.definitions.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Definition)> {
+        self.definitions
+            .iter()
+            .map(|(name, definition)| (name.as_str(), definition))
+    }
+
+    /// Defines `T` with the result of `define` unless already defined, returning a reference to
+    /// it. Used by the derived [Payload::schema].
+    ///
+    /// Panics if another type was defined under the name of `T`, like `chat::User` and
+    /// `admin::User`, since their references couldn't be told apart.
+    pub fn define<T: ?Sized>(
+        &mut self,
+        define: impl FnOnce(&mut Definitions) -> Definition,
+    ) -> Schema {
+        let name = type_name::<T>();
+        let path = std::any::type_name::<T>();
+        let defined = *self.paths.entry(name.clone()).or_insert(path);
+        assert!(
+            defined == path,
+            "`{defined}` and `{path}` are both named `{name}` in the schema"
+        );
+
+        if !self.definitions.contains_key(&name) && self.pending.insert(name.clone()) {
+            let definition = define(self);
+            self.pending.remove(&name);
+            self.definitions.insert(name.clone(), definition);
+        }
+
+        Schema::Ref { name }
+    }
+}
+
+/// The name of `T` without module paths, like `Response<Message>`.
+pub fn type_name<T: ?Sized>() -> String {
+    let full = std::any::type_name::<T>();
+    let mut name = String::with_capacity(full.len());
+    let mut segment_start = 0;
+
+    for c in full.chars() {
+        if c == ':' {
+            // drop the module path before `::`
+            name.truncate(segment_start);
+        } else {
+            name.push(c);
+            if !(c.is_alphanumeric() || c == '_') {
+                segment_start = name.len();
+            }
+        }
+    }
+
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod chat {
+        pub struct User;
+    }
+
+    mod admin {
+        pub struct User;
+    }
+
+    fn empty(_: &mut Definitions) -> Definition {
+        Definition::Struct { fields: vec![] }
+    }
+
+    #[test]
+    fn types_are_defined_once() {
+        let mut defs = Definitions::new();
+        let schema = defs.define::<chat::User>(empty);
+        assert_eq!(
+            schema,
+            Schema::Ref {
+                name: "User".to_string()
+            }
+        );
+
+        assert_eq!(
+            defs.define::<chat::User>(|_| unreachable!("already defined")),
+            schema
+        );
+        assert_eq!(defs.iter().count(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "are both named `User` in the schema")]
+    fn types_with_the_same_name_fail() {
+        let mut defs = Definitions::new();
+        defs.define::<chat::User>(empty);
+        defs.define::<admin::User>(empty);
+    }
+}
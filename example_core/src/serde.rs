@@ -0,0 +1,943 @@
+//! A [serde] data format producing the same bytes as the [Payload] impls.
+//!
+//! The [Serializer] and the [Deserializer] encode types deriving `Serialize` and `Deserialize`
+//! like the same types deriving `Payload`:
+//!
+//! | serde | Encoding |
+//! |-------|----------|
+//! | `bool`, numbers, `char` | like their [Payload] impls |
+//! | strings and bytes | a [VarInt] length, then the bytes |
+//! | `Option` | a `u8` tag, `0` for `None` or `1` followed by the value |
+//! | unit, unit structs | nothing |
+//! | newtype structs | the inner value |
+//! | sequences, maps | a [VarInt] element count, then the elements, or each key and its value |
+//! | tuples, structs | the concatenation of their fields |
+//! | enum variants | the index of the variant as a `u8`, then its fields |
+//! | integers using [varint] | like `#[payload(varint)]` fields |
+//!
+//! The format isn't self-describing, so `deserialize_any` and the attributes relying on it such
+//! as `#[serde(flatten)]` or `#[serde(untagged)]` are not supported, and sequences must know their
+//! length before being serialized.
+//!
+//! serde only knows the index of an enum variant, not its discriminant. An enum deriving `Payload`
+//! matches its serde encoding only if its variants are tagged in declaration order starting at 0,
+//! so enums with explicit discriminants like `Variant = 4` or with `#[payload(tag = N)]` must not
+//! be sent through this format. Other types whose serde impls differ from their [Payload] impls,
+//! like [IpAddr](std::net::IpAddr) whose variants are tagged `0` and `1` rather than `4` and `6`,
+//! and `#[payload(versioned)]` structs, have no serde equivalent either.
+//!
+//! [Serde] wraps a whole value, and the functions of this module can be used by fields marked
+//! `#[payload(with = "example_core::serde")]`. A `Serde<T>` is encoded like `T` deriving
+//! `Payload`, without any frame.
+//!
+//! serde can't wait for data, so [read_with] decodes the value from the stream as long as its
+//! bytes are available, reading exactly the bytes that each part of the value needs. When the
+//! stream has to wait, the decoding starts over from the bytes received so far once more arrive,
+//! so a value is decoded about once per wait for data rather than once per read.
+
+use std::fmt::Display;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::task::Poll;
+
+use bytes::BufMut;
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde::ser::{
+    self, Serialize, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant,
+    SerializeTuple, SerializeTupleStruct, SerializeTupleVariant,
+};
+use tokio::io::ReadBuf;
+
+use crate::varint::VarIntInteger;
+use crate::{
+    AsyncRead, Bytes, BytesMut, DecodeContext, Encode, LengthPrefix, Payload, PayloadError, VarInt,
+    READ_CHUNK_SIZE,
+};
+
+/// A value encoded through its serde impls rather than a [Payload] impl.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Serde<T>(pub T);
+
+impl<T> Serde<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Serde<T> {
+    fn from(value: T) -> Serde<T> {
+        Serde(value)
+    }
+}
+
+impl<T> Encode for Serde<T>
+where
+    T: Serialize,
+{
+    fn encoded_len(&self) -> usize {
+        encoded_len(&self.0)
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        encode(&self.0, buf)
+    }
+
+    fn to_bytes(&self) -> Result<Bytes, PayloadError> {
+        let mut buf = BytesMut::new();
+        encode(&self.0, &mut buf)?;
+
+        Ok(buf.freeze())
+    }
+}
+
+impl<T> Payload for Serde<T>
+where
+    T: Serialize + DeserializeOwned + Send + Sync,
+{
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<Serde<T>, PayloadError> {
+        decode_with(buf, ctx).map(Serde)
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<Serde<T>, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        read_with(recv, ctx).await.map(Serde)
+    }
+}
+
+/// Number of bytes taken by `value`, counted by serializing it without writing anything.
+pub fn encoded_len<T: Serialize + ?Sized>(value: &T) -> usize {
+    let mut serializer = Serializer::counting();
+    // an error is reported by `encode`, the length is only used to reserve the buffer
+    let _ = value.serialize(&mut serializer);
+
+    serializer.len
+}
+
+pub fn encode<T: Serialize + ?Sized>(value: &T, buf: &mut BytesMut) -> Result<(), PayloadError> {
+    value.serialize(&mut Serializer::new(buf))
+}
+
+pub fn decode_with<T: DeserializeOwned>(
+    buf: &mut Bytes,
+    ctx: &mut DecodeContext,
+) -> Result<T, PayloadError> {
+    T::deserialize(&mut Deserializer::new(buf, ctx))
+}
+
+/// Reads a value from `recv`, without reading anything past it.
+///
+/// The value is decoded while its bytes are available, each part of it reading exactly the bytes
+/// it needs. When `recv` has to wait for data, the decoding starts over from the bytes received so
+/// far once it is woken up.
+pub async fn read_with<T, R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<T, PayloadError>
+where
+    T: DeserializeOwned,
+    R: AsyncRead + Unpin + Send + ?Sized,
+{
+    let mut received: Vec<u8> = vec![];
+
+    poll_fn(|cx| {
+        let mut attempt = ctx.clone();
+        let mut buf = Bytes::copy_from_slice(&received);
+        let mut pending = false;
+
+        let mut refill = |len: usize| {
+            let start = received.len();
+            let end = start + len;
+
+            while received.len() < end {
+                let filled = received.len();
+                received.resize(filled + (end - filled).min(READ_CHUNK_SIZE), 0);
+
+                let mut read_buf = ReadBuf::new(&mut received[filled..]);
+                let result = Pin::new(&mut *recv).poll_read(cx, &mut read_buf);
+                let read = read_buf.filled().len();
+                received.truncate(filled + read);
+
+                match result {
+                    Poll::Ready(Ok(())) if read == 0 => return Err(PayloadError::UnexpectedEnd),
+                    Poll::Ready(Ok(())) => {}
+                    Poll::Ready(Err(e)) => return Err(e.into()),
+                    Poll::Pending => {
+                        pending = true;
+                        return Err(PayloadError::UnexpectedEnd);
+                    }
+                }
+            }
+
+            Ok(Bytes::copy_from_slice(&received[start..]))
+        };
+
+        let result = T::deserialize(&mut Deserializer {
+            buf: &mut buf,
+            ctx: &mut attempt,
+            refill: Some(&mut refill),
+        });
+        if pending {
+            return Poll::Pending;
+        }
+        if result.is_ok() {
+            *ctx = attempt;
+        }
+
+        Poll::Ready(result)
+    })
+    .await
+}
+
+/// The name of the newtype struct holding a [varint] as a `u64`, which the [Serializer] and the
+/// [Deserializer] encode as a [VarInt].
+const VARINT_STRUCT: &str = "$example_core::VarInt";
+
+/// Encodes an integer field like `#[payload(varint)]`, with
+/// `#[serde(with = "example_core::serde::varint")]`.
+///
+/// Other serde formats see a newtype struct holding the `u64` of the [VarInt], zigzag encoded for
+/// signed integers.
+pub mod varint {
+    use serde::de::{self, Deserializer, Visitor};
+    use serde::ser::{self, Serializer};
+
+    use super::{VarIntInteger, VARINT_STRUCT};
+    use crate::VarInt;
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: VarIntInteger,
+        S: Serializer,
+    {
+        let value = value.to_varint().map_err(ser::Error::custom)?;
+
+        serializer.serialize_newtype_struct(VARINT_STRUCT, &value.into_inner())
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: VarIntInteger,
+        D: Deserializer<'de>,
+    {
+        let value = deserializer.deserialize_newtype_struct(VARINT_STRUCT, VarIntVisitor)?;
+
+        T::from_varint(value).map_err(de::Error::custom)
+    }
+
+    struct VarIntVisitor;
+
+    impl<'de> Visitor<'de> for VarIntVisitor {
+        type Value = VarInt;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "a varint")
+        }
+
+        fn visit_newtype_struct<D: Deserializer<'de>>(
+            self,
+            deserializer: D,
+        ) -> Result<VarInt, D::Error> {
+            let value: u64 = de::Deserialize::deserialize(deserializer)?;
+
+            VarInt::new(value).map_err(de::Error::custom)
+        }
+    }
+}
+
+impl ser::Error for PayloadError {
+    fn custom<T: Display>(msg: T) -> PayloadError {
+        PayloadError::custom(msg)
+    }
+}
+
+impl de::Error for PayloadError {
+    fn custom<T: Display>(msg: T) -> PayloadError {
+        PayloadError::custom(msg)
+    }
+}
+
+/// Serializes values into a buffer.
+pub struct Serializer<'a> {
+    /// `None` when the bytes are only counted, see [encoded_len].
+    buf: Option<&'a mut BytesMut>,
+    /// Number of bytes serialized.
+    len: usize,
+    /// Whether the next `u64` is the value of a [varint].
+    varint: bool,
+}
+
+impl<'a> Serializer<'a> {
+    pub fn new(buf: &'a mut BytesMut) -> Serializer<'a> {
+        Serializer {
+            buf: Some(buf),
+            len: 0,
+            varint: false,
+        }
+    }
+
+    fn counting() -> Serializer<'a> {
+        Serializer {
+            buf: None,
+            len: 0,
+            varint: false,
+        }
+    }
+
+    fn put<T: Encode + ?Sized>(&mut self, value: &T) -> Result<(), PayloadError> {
+        match &mut self.buf {
+            Some(buf) => {
+                let start = buf.len();
+                value.encode(buf)?;
+                self.len += buf.len() - start;
+            }
+            None => self.len += value.encoded_len(),
+        }
+
+        Ok(())
+    }
+
+    fn put_slice(&mut self, bytes: &[u8]) {
+        if let Some(buf) = &mut self.buf {
+            buf.put_slice(bytes);
+        }
+        self.len += bytes.len();
+    }
+
+    fn encode_variant(&mut self, variant_index: u32) -> Result<(), PayloadError> {
+        let tag = u8::try_from(variant_index).map_err(|_| PayloadError::IntegerOverflow {
+            value: i128::from(variant_index),
+            ty: "u8",
+        })?;
+
+        self.put(&tag)
+    }
+
+    fn encode_len(&mut self, len: Option<usize>) -> Result<(), PayloadError> {
+        let len = len.ok_or_else(|| {
+            PayloadError::custom("sequences and maps must know their length to be serialized")
+        })?;
+
+        self.put_len(len)
+    }
+
+    fn put_len(&mut self, len: usize) -> Result<(), PayloadError> {
+        if let Some(buf) = &mut self.buf {
+            <VarInt as LengthPrefix>::encode_len(len, buf)?;
+        }
+        self.len += <VarInt as LengthPrefix>::encoded_len(len);
+
+        Ok(())
+    }
+}
+
+macro_rules! serialize_with_encode {
+    ($($method:ident($ty:ty)),*) => {
+        $(
+            fn $method(self, v: $ty) -> Result<(), PayloadError> {
+                self.put(&v)
+            }
+        )*
+    };
+}
+
+impl<'a, 'b> ser::Serializer for &'b mut Serializer<'a> {
+    type Ok = ();
+    type Error = PayloadError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    serialize_with_encode!(
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_i128(i128),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u128(u128),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str)
+    );
+
+    fn serialize_u64(self, v: u64) -> Result<(), PayloadError> {
+        if std::mem::take(&mut self.varint) {
+            return self.put(&VarInt::new(v)?);
+        }
+
+        self.put(&v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), PayloadError> {
+        self.put_len(v.len())?;
+        self.put_slice(v);
+
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), PayloadError> {
+        self.put(&0b0_u8)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), PayloadError> {
+        self.put(&0b1_u8)?;
+
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), PayloadError> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), PayloadError> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), PayloadError> {
+        self.encode_variant(variant_index)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), PayloadError> {
+        self.varint = name == VARINT_STRUCT;
+        let result = value.serialize(&mut *self);
+        self.varint = false;
+
+        result
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<(), PayloadError> {
+        self.encode_variant(variant_index)?;
+
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self, PayloadError> {
+        self.encode_len(len)?;
+
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self, PayloadError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self, PayloadError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, PayloadError> {
+        self.encode_variant(variant_index)?;
+
+        Ok(self)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self, PayloadError> {
+        self.encode_len(len)?;
+
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self, PayloadError> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, PayloadError> {
+        self.encode_variant(variant_index)?;
+
+        Ok(self)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Implements the serde traits serializing the elements of a compound value one after the other.
+macro_rules! impl_serialize_compound {
+    ($($trait:ident::$method:ident),*) => {
+        $(
+            impl<'a, 'b> $trait for &'b mut Serializer<'a> {
+                type Ok = ();
+                type Error = PayloadError;
+
+                fn $method<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PayloadError> {
+                    value.serialize(&mut **self)
+                }
+
+                fn end(self) -> Result<(), PayloadError> {
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+impl_serialize_compound!(
+    SerializeSeq::serialize_element,
+    SerializeTuple::serialize_element,
+    SerializeTupleStruct::serialize_field,
+    SerializeTupleVariant::serialize_field
+);
+
+impl<'a, 'b> SerializeStruct for &'b mut Serializer<'a> {
+    type Ok = ();
+    type Error = PayloadError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), PayloadError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), PayloadError> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b> SerializeStructVariant for &'b mut Serializer<'a> {
+    type Ok = ();
+    type Error = PayloadError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), PayloadError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), PayloadError> {
+        Ok(())
+    }
+}
+
+impl<'a, 'b> SerializeMap for &'b mut Serializer<'a> {
+    type Ok = ();
+    type Error = PayloadError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), PayloadError> {
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), PayloadError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), PayloadError> {
+        Ok(())
+    }
+}
+
+/// Reads the given number of bytes missing from the buffer of a [Deserializer], see [read_with].
+type Refill<'a> = &'a mut dyn FnMut(usize) -> Result<Bytes, PayloadError>;
+
+/// Deserializes values from the start of a buffer, advancing it past the bytes that were used.
+pub struct Deserializer<'a> {
+    buf: &'a mut Bytes,
+    ctx: &'a mut DecodeContext,
+    refill: Option<Refill<'a>>,
+}
+
+impl<'a> Deserializer<'a> {
+    pub fn new(buf: &'a mut Bytes, ctx: &'a mut DecodeContext) -> Deserializer<'a> {
+        Deserializer {
+            buf,
+            ctx,
+            refill: None,
+        }
+    }
+
+    /// Makes sure that the buffer holds at least `len` bytes, reading the missing ones when
+    /// deserializing from a stream. Fails with [PayloadError::UnexpectedEnd] otherwise.
+    fn require(&mut self, len: usize) -> Result<(), PayloadError> {
+        let missing = len.saturating_sub(self.buf.len());
+        if missing == 0 {
+            return Ok(());
+        }
+        let Some(refill) = &mut self.refill else {
+            return Err(PayloadError::UnexpectedEnd);
+        };
+
+        let missing_bytes = refill(missing)?;
+        let mut joined = BytesMut::with_capacity(len);
+        joined.put(std::mem::take(self.buf));
+        joined.put(missing_bytes);
+        *self.buf = joined.freeze();
+
+        Ok(())
+    }
+
+    /// Makes sure that the buffer holds the whole [VarInt] at its start.
+    fn require_varint(&mut self) -> Result<(), PayloadError> {
+        self.require(1)?;
+
+        self.require(VarInt::len_from_first_byte(self.buf[0]))
+    }
+
+    /// Decodes a value of `T` taking `len` bytes.
+    fn decode_fixed<T: Payload>(&mut self, len: usize) -> Result<T, PayloadError> {
+        self.require(len)?;
+
+        T::decode_with(self.buf, self.ctx)
+    }
+
+    fn decode_len(&mut self) -> Result<usize, PayloadError> {
+        self.require_varint()?;
+
+        <VarInt as LengthPrefix>::decode_len(self.buf, self.ctx)
+    }
+
+    fn decode_bytes(&mut self) -> Result<Bytes, PayloadError> {
+        let len = self.decode_len()?;
+        self.ctx.check_string_len(len)?;
+        self.ctx.consume(len)?;
+        self.require(len)?;
+
+        Ok(self.buf.split_to(len))
+    }
+
+    /// Visits a nested value, checking the depth limit.
+    fn nested<T>(
+        &mut self,
+        visit: impl FnOnce(&mut Deserializer<'_>) -> Result<T, PayloadError>,
+    ) -> Result<T, PayloadError> {
+        let mut nested = self.ctx.enter()?;
+
+        visit(&mut Deserializer {
+            buf: self.buf,
+            ctx: &mut nested,
+            refill: match &mut self.refill {
+                Some(refill) => Some(&mut **refill),
+                None => None,
+            },
+        })
+    }
+}
+
+macro_rules! deserialize_fixed {
+    ($($method:ident($ty:ty, $visit:ident)),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PayloadError> {
+                let value = self.decode_fixed::<$ty>(std::mem::size_of::<$ty>())?;
+
+                visitor.$visit(value)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a, 'b> de::Deserializer<'de> for &'b mut Deserializer<'a> {
+    type Error = PayloadError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, PayloadError> {
+        Err(PayloadError::custom(
+            "the Payload format is not self-describing, the type to deserialize must be known",
+        ))
+    }
+
+    deserialize_fixed!(
+        deserialize_bool(bool, visit_bool),
+        deserialize_i8(i8, visit_i8),
+        deserialize_i16(i16, visit_i16),
+        deserialize_i32(i32, visit_i32),
+        deserialize_i64(i64, visit_i64),
+        deserialize_i128(i128, visit_i128),
+        deserialize_u8(u8, visit_u8),
+        deserialize_u16(u16, visit_u16),
+        deserialize_u32(u32, visit_u32),
+        deserialize_u64(u64, visit_u64),
+        deserialize_u128(u128, visit_u128),
+        deserialize_f32(f32, visit_f32),
+        deserialize_f64(f64, visit_f64),
+        deserialize_char(char, visit_char)
+    );
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PayloadError> {
+        let bytes = self.decode_bytes()?;
+
+        visitor.visit_str(std::str::from_utf8(&bytes)?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PayloadError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PayloadError> {
+        visitor.visit_bytes(&self.decode_bytes()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PayloadError> {
+        visitor.visit_byte_buf(self.decode_bytes()?.to_vec())
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PayloadError> {
+        let first_byte = self.decode_fixed::<u8>(1)?;
+
+        self.nested(|de| match first_byte {
+            0b0 => visitor.visit_none(),
+            0b1 => visitor.visit_some(de),
+            value => Err(PayloadError::UnknownDiscriminant {
+                ty: "Option",
+                value,
+            }),
+        })
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PayloadError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, PayloadError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, PayloadError> {
+        if name == VARINT_STRUCT {
+            self.require_varint()?;
+            let value = VarInt::decode_with(self.buf, self.ctx)?.into_inner();
+
+            return visitor.visit_newtype_struct(value.into_deserializer());
+        }
+
+        self.nested(|de| visitor.visit_newtype_struct(de))
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PayloadError> {
+        let len = self.decode_len()?;
+        self.ctx.check_collection_len(len)?;
+
+        self.nested(|de| visitor.visit_seq(Elements { de, len }))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, PayloadError> {
+        visitor.visit_seq(Elements { de: self, len })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, PayloadError> {
+        self.nested(|de| visitor.visit_seq(Elements { de, len }))
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PayloadError> {
+        let len = self.decode_len()?;
+        self.ctx.check_collection_len(len)?;
+
+        self.nested(|de| visitor.visit_map(Elements { de, len }))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, PayloadError> {
+        self.nested(|de| {
+            visitor.visit_seq(Elements {
+                de,
+                len: fields.len(),
+            })
+        })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, PayloadError> {
+        self.nested(|de| visitor.visit_enum(Variant { de, name, variants }))
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(
+        self,
+        _visitor: V,
+    ) -> Result<V::Value, PayloadError> {
+        Err(PayloadError::custom(
+            "the Payload format doesn't encode field names",
+        ))
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(
+        self,
+        _visitor: V,
+    ) -> Result<V::Value, PayloadError> {
+        Err(PayloadError::custom(
+            "the Payload format is not self-describing, values can't be skipped",
+        ))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// The elements of a sequence, tuple or struct, or the entries of a map.
+struct Elements<'b, 'a> {
+    de: &'b mut Deserializer<'a>,
+    len: usize,
+}
+
+impl<'de, 'a, 'b> SeqAccess<'de> for Elements<'b, 'a> {
+    type Error = PayloadError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, PayloadError> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<'de, 'a, 'b> MapAccess<'de> for Elements<'b, 'a> {
+    type Error = PayloadError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, PayloadError> {
+        self.next_element_seed(seed)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, PayloadError> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+/// An enum variant, identified by its index.
+struct Variant<'b, 'a> {
+    de: &'b mut Deserializer<'a>,
+    name: &'static str,
+    variants: &'static [&'static str],
+}
+
+impl<'de, 'a, 'b> EnumAccess<'de> for Variant<'b, 'a> {
+    type Error = PayloadError;
+    type Variant = &'b mut Deserializer<'a>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), PayloadError> {
+        let tag = self.de.decode_fixed::<u8>(1)?;
+        if usize::from(tag) >= self.variants.len() {
+            return Err(PayloadError::UnknownDiscriminant {
+                ty: self.name,
+                value: tag,
+            });
+        }
+
+        let index: de::value::U32Deserializer<PayloadError> = u32::from(tag).into_deserializer();
+        let variant = seed.deserialize(index)?;
+
+        Ok((variant, self.de))
+    }
+}
+
+impl<'de, 'a, 'b> VariantAccess<'de> for &'b mut Deserializer<'a> {
+    type Error = PayloadError;
+
+    fn unit_variant(self) -> Result<(), PayloadError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, PayloadError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, PayloadError> {
+        visitor.visit_seq(Elements { de: self, len })
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, PayloadError> {
+        visitor.visit_seq(Elements {
+            de: self,
+            len: fields.len(),
+        })
+    }
+}
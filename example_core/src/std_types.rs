@@ -0,0 +1,859 @@
+//! [Payload] implementations for standard library types.
+//!
+//! Wire formats of the types added on top of the numbers, [String], [Vec], [Option] and [uuid::Uuid]
+//! of the crate root:
+//!
+//! | Type | Encoding |
+//! |------|----------|
+//! | `bool` | 1 byte, `0` or `1`, anything else is an error |
+//! | `u128`, `i128` | 16 bytes, big-endian |
+//! | `char` | its scalar value as a `u32` |
+//! | `()` | nothing |
+//! | tuples | the concatenation of their elements |
+//! | `[T; N]` | the `N` elements, without a length since it is known |
+//! | `Box<T>`, `Arc<T>` | the inner value |
+//! | `HashMap`, `BTreeMap` | a [VarInt] entry count, then each key followed by its value |
+//! | `HashSet`, `BTreeSet` | a [VarInt] element count, then the elements |
+//! | `Result<T, E>` | a `u8` tag, `0` followed by `T` or `1` followed by `E` |
+//! | `Duration` | the seconds as a `u64` then the sub-second nanoseconds as a `u32` |
+//! | `SystemTime` | the `Duration` since [UNIX_EPOCH], earlier times can't be encoded |
+//! | `Ipv4Addr`, `Ipv6Addr` | the 4 or 16 bytes of the address |
+//! | `IpAddr` | a `u8` tag, `4` followed by an `Ipv4Addr` or `6` followed by an `Ipv6Addr` |
+//! | `SocketAddr` | the `IpAddr` then the port as a `u16`, IPv6 flow info and scope id are dropped |
+//!
+//! The maps and sets implement [LengthPrefixed], so `#[payload(len = "...")]` works on them like
+//! on a [Vec]. Decoding a map or set with a repeated key keeps the last one.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::{BuildHasher, Hash};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::schema::{Definitions, LengthEncoding, Schema};
+use crate::{
+    AsyncRead, Bytes, BytesMut, DecodeContext, Encode, LengthPrefix, LengthPrefixed, Payload,
+    PayloadError, VarInt,
+};
+
+impl Encode for bool {
+    fn encoded_len(&self) -> usize {
+        1
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        u8::from(*self).encode(buf)
+    }
+}
+
+impl Payload for bool {
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<bool, PayloadError> {
+        bool_from_u8(u8::decode_with(buf, ctx)?)
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<bool, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        bool_from_u8(u8::read_with(recv, ctx).await?)
+    }
+
+    fn schema(_defs: &mut Definitions) -> Schema {
+        Schema::Bool
+    }
+}
+
+fn bool_from_u8(value: u8) -> Result<bool, PayloadError> {
+    match value {
+        0 => Ok(false),
+        1 => Ok(true),
+        value => Err(PayloadError::InvalidValue {
+            value: u64::from(value),
+            ty: "bool",
+        }),
+    }
+}
+
+impl Encode for char {
+    fn encoded_len(&self) -> usize {
+        4
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        u32::from(*self).encode(buf)
+    }
+}
+
+impl Payload for char {
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<char, PayloadError> {
+        char_from_u32(u32::decode_with(buf, ctx)?)
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<char, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        char_from_u32(u32::read_with(recv, ctx).await?)
+    }
+
+    fn schema(_defs: &mut Definitions) -> Schema {
+        Schema::Char
+    }
+}
+
+fn char_from_u32(value: u32) -> Result<char, PayloadError> {
+    char::from_u32(value).ok_or(PayloadError::InvalidValue {
+        value: u64::from(value),
+        ty: "char",
+    })
+}
+
+impl Encode for () {
+    fn encoded_len(&self) -> usize {
+        0
+    }
+
+    fn encode(&self, _buf: &mut BytesMut) -> Result<(), PayloadError> {
+        Ok(())
+    }
+}
+
+impl Payload for () {
+    fn decode_with(_buf: &mut Bytes, _ctx: &mut DecodeContext) -> Result<(), PayloadError> {
+        Ok(())
+    }
+
+    async fn read_with<R>(_recv: &mut R, _ctx: &mut DecodeContext) -> Result<(), PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        Ok(())
+    }
+
+    fn schema(_defs: &mut Definitions) -> Schema {
+        Schema::Unit
+    }
+}
+
+/// Implements [Payload] for a tuple, given each type parameter with its index.
+macro_rules! impl_payload_for_tuple {
+    ($($name:ident $index:tt),+) => {
+        impl<$($name),+> Encode for ($($name,)+)
+        where
+            $($name: Encode,)+
+        {
+            fn encoded_len(&self) -> usize {
+                0 $(+ self.$index.encoded_len())+
+            }
+
+            fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+                $(self.$index.encode(buf)?;)+
+
+                Ok(())
+            }
+        }
+
+        impl<$($name),+> Payload for ($($name,)+)
+        where
+            $($name: Payload + Send + Sync,)+
+        {
+            fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<Self, PayloadError> {
+                Ok(($($name::decode_with(buf, ctx)?,)+))
+            }
+
+            async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<Self, PayloadError>
+            where
+                R: AsyncRead + Unpin + Send + ?Sized,
+            {
+                Ok(($($name::read_with(recv, ctx).await?,)+))
+            }
+
+            fn schema(defs: &mut Definitions) -> Schema {
+                Schema::Tuple {
+                    items: vec![$($name::schema(defs)),+],
+                }
+            }
+        }
+    };
+}
+
+impl_payload_for_tuple!(A 0);
+impl_payload_for_tuple!(A 0, B 1);
+impl_payload_for_tuple!(A 0, B 1, C 2);
+impl_payload_for_tuple!(A 0, B 1, C 2, D 3);
+impl_payload_for_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_payload_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_payload_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_payload_for_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+impl<T, const N: usize> Encode for [T; N]
+where
+    T: Encode,
+{
+    fn encoded_len(&self) -> usize {
+        self.iter().map(T::encoded_len).sum()
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        for item in self.iter() {
+            item.encode(buf)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T, const N: usize> Payload for [T; N]
+where
+    T: Payload + Send + Sync,
+{
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<[T; N], PayloadError> {
+        let mut nested = ctx.enter()?;
+        let ctx = &mut *nested;
+        let mut items: Vec<T> = Vec::with_capacity(N);
+        for _ in 0..N {
+            items.push(T::decode_with(buf, ctx)?);
+        }
+
+        Ok(into_array(items))
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<[T; N], PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let mut nested = ctx.enter()?;
+        let ctx = &mut *nested;
+        let mut items: Vec<T> = Vec::with_capacity(N);
+        for _ in 0..N {
+            items.push(T::read_with(recv, ctx).await?);
+        }
+
+        Ok(into_array(items))
+    }
+
+    fn schema(defs: &mut Definitions) -> Schema {
+        Schema::Array {
+            len: N,
+            items: Box::new(T::schema(defs)),
+        }
+    }
+}
+
+fn into_array<T, const N: usize>(items: Vec<T>) -> [T; N] {
+    match items.try_into() {
+        Ok(array) => array,
+        Err(_) => unreachable!("exactly N items are decoded"),
+    }
+}
+
+/// Implements [Payload] for a smart pointer, encoded as the value it points to.
+macro_rules! impl_payload_for_pointer {
+    ($($ty:ident),*) => {
+        $(
+            impl<T> Encode for $ty<T>
+            where
+                T: Encode + ?Sized,
+            {
+                fn encoded_len(&self) -> usize {
+                    T::encoded_len(self)
+                }
+
+                fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+                    T::encode(self, buf)
+                }
+            }
+
+            impl<T> Payload for $ty<T>
+            where
+                T: Payload + Send + Sync,
+            {
+                fn decode_with(
+                    buf: &mut Bytes,
+                    ctx: &mut DecodeContext,
+                ) -> Result<$ty<T>, PayloadError> {
+                    Ok($ty::new(T::decode_with(buf, ctx)?))
+                }
+
+                async fn read_with<R>(
+                    recv: &mut R,
+                    ctx: &mut DecodeContext,
+                ) -> Result<$ty<T>, PayloadError>
+                where
+                    R: AsyncRead + Unpin + Send + ?Sized,
+                {
+                    Ok($ty::new(T::read_with(recv, ctx).await?))
+                }
+
+                fn schema(defs: &mut Definitions) -> Schema {
+                    T::schema(defs)
+                }
+            }
+        )*
+    };
+}
+
+impl_payload_for_pointer!(Box, Arc);
+
+/// Implements [LengthPrefixed] and [Payload] for a map, given its generics, the map type and the
+/// bounds needed besides `K` and `V` implementing [Payload].
+macro_rules! impl_payload_for_map {
+    ([$($generics:tt)*] $ty:ty where $($bounds:tt)*) => {
+        impl<$($generics)*> LengthPrefixed for $ty
+        where
+            K: Payload + Send + Sync,
+            V: Payload + Send + Sync,
+            $($bounds)*
+        {
+            fn encoded_len_with_prefix<L: LengthPrefix>(&self) -> usize {
+                L::encoded_len(self.len())
+                    + self
+                        .iter()
+                        .map(|(key, value)| key.encoded_len() + value.encoded_len())
+                        .sum::<usize>()
+            }
+
+            fn encode_with_prefix<L: LengthPrefix>(
+                &self,
+                buf: &mut BytesMut,
+            ) -> Result<(), PayloadError> {
+                L::encode_len(self.len(), buf)?;
+
+                for (key, value) in self.iter() {
+                    key.encode(buf)?;
+                    value.encode(buf)?;
+                }
+
+                Ok(())
+            }
+
+            fn decode_with_prefix<L: LengthPrefix>(
+                buf: &mut Bytes,
+                ctx: &mut DecodeContext,
+            ) -> Result<Self, PayloadError> {
+                let len = L::decode_len(buf, ctx)?;
+                ctx.check_collection_len(len)?;
+
+                let mut nested = ctx.enter()?;
+                let ctx = &mut *nested;
+                let mut map = Self::default();
+                for _ in 0..len {
+                    let key = K::decode_with(buf, ctx)?;
+                    let value = V::decode_with(buf, ctx)?;
+                    map.insert(key, value);
+                }
+
+                Ok(map)
+            }
+
+            async fn read_with_prefix<L, R>(
+                recv: &mut R,
+                ctx: &mut DecodeContext,
+            ) -> Result<Self, PayloadError>
+            where
+                L: LengthPrefix,
+                R: AsyncRead + Unpin + Send + ?Sized,
+            {
+                let len = L::read_len(recv, ctx).await?;
+                ctx.check_collection_len(len)?;
+
+                let mut nested = ctx.enter()?;
+                let ctx = &mut *nested;
+                let mut map = Self::default();
+                for _ in 0..len {
+                    let key = K::read_with(recv, ctx).await?;
+                    let value = V::read_with(recv, ctx).await?;
+                    map.insert(key, value);
+                }
+
+                Ok(map)
+            }
+        }
+
+        impl_payload_with_varint_prefix!([$($generics)*] $ty,
+            |defs| Schema::Map {
+                length: LengthEncoding::VarInt,
+                key: Box::new(K::schema(defs)),
+                value: Box::new(V::schema(defs)),
+            },
+        where
+            K: Payload + Send + Sync,
+            V: Payload + Send + Sync,
+            $($bounds)*
+        );
+    };
+}
+
+/// Implements [LengthPrefixed] and [Payload] for a set, given its generics, the set type and the
+/// bounds needed besides `T` implementing [Payload].
+macro_rules! impl_payload_for_set {
+    ([$($generics:tt)*] $ty:ty where $($bounds:tt)*) => {
+        impl<$($generics)*> LengthPrefixed for $ty
+        where
+            T: Payload + Send + Sync,
+            $($bounds)*
+        {
+            fn encoded_len_with_prefix<L: LengthPrefix>(&self) -> usize {
+                L::encoded_len(self.len()) + self.iter().map(T::encoded_len).sum::<usize>()
+            }
+
+            fn encode_with_prefix<L: LengthPrefix>(
+                &self,
+                buf: &mut BytesMut,
+            ) -> Result<(), PayloadError> {
+                L::encode_len(self.len(), buf)?;
+
+                for item in self.iter() {
+                    item.encode(buf)?;
+                }
+
+                Ok(())
+            }
+
+            fn decode_with_prefix<L: LengthPrefix>(
+                buf: &mut Bytes,
+                ctx: &mut DecodeContext,
+            ) -> Result<Self, PayloadError> {
+                let len = L::decode_len(buf, ctx)?;
+                ctx.check_collection_len(len)?;
+
+                let mut nested = ctx.enter()?;
+                let ctx = &mut *nested;
+                let mut set = Self::default();
+                for _ in 0..len {
+                    set.insert(T::decode_with(buf, ctx)?);
+                }
+
+                Ok(set)
+            }
+
+            async fn read_with_prefix<L, R>(
+                recv: &mut R,
+                ctx: &mut DecodeContext,
+            ) -> Result<Self, PayloadError>
+            where
+                L: LengthPrefix,
+                R: AsyncRead + Unpin + Send + ?Sized,
+            {
+                let len = L::read_len(recv, ctx).await?;
+                ctx.check_collection_len(len)?;
+
+                let mut nested = ctx.enter()?;
+                let ctx = &mut *nested;
+                let mut set = Self::default();
+                for _ in 0..len {
+                    set.insert(T::read_with(recv, ctx).await?);
+                }
+
+                Ok(set)
+            }
+        }
+
+        impl_payload_with_varint_prefix!([$($generics)*] $ty,
+            |defs| Schema::Seq {
+                length: LengthEncoding::VarInt,
+                items: Box::new(T::schema(defs)),
+            },
+        where
+            T: Payload + Send + Sync,
+            $($bounds)*
+        );
+    };
+}
+
+/// Implements [Payload] for a [LengthPrefixed] type using a [VarInt] prefix, given the expression
+/// describing its schema.
+macro_rules! impl_payload_with_varint_prefix {
+    ([$($generics:tt)*] $ty:ty, |$defs:ident| $schema:expr, where $($bounds:tt)*) => {
+        impl<$($generics)*> Encode for $ty
+        where
+            $($bounds)*
+        {
+            fn encoded_len(&self) -> usize {
+                self.encoded_len_with_prefix::<VarInt>()
+            }
+
+            fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+                self.encode_with_prefix::<VarInt>(buf)
+            }
+        }
+
+        impl<$($generics)*> Payload for $ty
+        where
+            $($bounds)*
+        {
+            fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<Self, PayloadError> {
+                Self::decode_with_prefix::<VarInt>(buf, ctx)
+            }
+
+            async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<Self, PayloadError>
+            where
+                R: AsyncRead + Unpin + Send + ?Sized,
+            {
+                Self::read_with_prefix::<VarInt, R>(recv, ctx).await
+            }
+
+            fn schema(defs: &mut Definitions) -> Schema {
+                let $defs = defs;
+
+                $schema
+            }
+        }
+    };
+}
+
+impl_payload_for_map!([K, V, S] HashMap<K, V, S> where
+    K: Eq + Hash,
+    S: BuildHasher + Default + Send + Sync,
+);
+impl_payload_for_map!([K, V] BTreeMap<K, V> where K: Ord,);
+impl_payload_for_set!([T, S] HashSet<T, S> where
+    T: Eq + Hash,
+    S: BuildHasher + Default + Send + Sync,
+);
+impl_payload_for_set!([T] BTreeSet<T> where T: Ord,);
+
+impl<T, E> Encode for Result<T, E>
+where
+    T: Encode,
+    E: Encode,
+{
+    fn encoded_len(&self) -> usize {
+        1 + match self {
+            Ok(value) => value.encoded_len(),
+            Err(error) => error.encoded_len(),
+        }
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        match self {
+            Ok(value) => {
+                0u8.encode(buf)?;
+                value.encode(buf)
+            }
+            Err(error) => {
+                1u8.encode(buf)?;
+                error.encode(buf)
+            }
+        }
+    }
+}
+
+impl<T, E> Payload for Result<T, E>
+where
+    T: Payload + Send + Sync,
+    E: Payload + Send + Sync,
+{
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<Self, PayloadError> {
+        let tag = u8::decode_with(buf, ctx)?;
+
+        let mut nested = ctx.enter()?;
+        let ctx = &mut *nested;
+        match tag {
+            0 => T::decode_with(buf, ctx).map(Ok),
+            1 => E::decode_with(buf, ctx).map(Err),
+            value => Err(unknown_result_tag(value)),
+        }
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<Self, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let tag = u8::read_with(recv, ctx).await?;
+
+        let mut nested = ctx.enter()?;
+        let ctx = &mut *nested;
+        match tag {
+            0 => T::read_with(recv, ctx).await.map(Ok),
+            1 => E::read_with(recv, ctx).await.map(Err),
+            value => Err(unknown_result_tag(value)),
+        }
+    }
+
+    fn schema(defs: &mut Definitions) -> Schema {
+        Schema::Result {
+            ok: Box::new(T::schema(defs)),
+            err: Box::new(E::schema(defs)),
+        }
+    }
+}
+
+fn unknown_result_tag(value: u8) -> PayloadError {
+    PayloadError::UnknownDiscriminant {
+        ty: "Result",
+        value,
+    }
+}
+
+impl Encode for Duration {
+    fn encoded_len(&self) -> usize {
+        12
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        self.as_secs().encode(buf)?;
+        self.subsec_nanos().encode(buf)
+    }
+}
+
+impl Payload for Duration {
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<Duration, PayloadError> {
+        let secs = u64::decode_with(buf, ctx)?;
+        let nanos = u32::decode_with(buf, ctx)?;
+
+        duration_from_parts(secs, nanos)
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<Duration, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let secs = u64::read_with(recv, ctx).await?;
+        let nanos = u32::read_with(recv, ctx).await?;
+
+        duration_from_parts(secs, nanos)
+    }
+
+    fn schema(_defs: &mut Definitions) -> Schema {
+        Schema::Duration
+    }
+}
+
+fn duration_from_parts(secs: u64, nanos: u32) -> Result<Duration, PayloadError> {
+    if nanos >= 1_000_000_000 {
+        return Err(PayloadError::InvalidValue {
+            value: u64::from(nanos),
+            ty: "Duration nanoseconds",
+        });
+    }
+
+    Ok(Duration::new(secs, nanos))
+}
+
+impl Encode for SystemTime {
+    fn encoded_len(&self) -> usize {
+        12
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        self.duration_since(UNIX_EPOCH)
+            .map_err(|_| PayloadError::custom("SystemTime before the UNIX epoch can't be encoded"))?
+            .encode(buf)
+    }
+}
+
+impl Payload for SystemTime {
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<SystemTime, PayloadError> {
+        system_time_from_duration(Duration::decode_with(buf, ctx)?)
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<SystemTime, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        system_time_from_duration(Duration::read_with(recv, ctx).await?)
+    }
+
+    fn schema(_defs: &mut Definitions) -> Schema {
+        Schema::SystemTime
+    }
+}
+
+fn system_time_from_duration(duration: Duration) -> Result<SystemTime, PayloadError> {
+    UNIX_EPOCH
+        .checked_add(duration)
+        .ok_or(PayloadError::InvalidValue {
+            value: duration.as_secs(),
+            ty: "SystemTime",
+        })
+}
+
+impl Encode for Ipv4Addr {
+    fn encoded_len(&self) -> usize {
+        4
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        u32::from(*self).encode(buf)
+    }
+}
+
+impl Payload for Ipv4Addr {
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<Ipv4Addr, PayloadError> {
+        Ok(Ipv4Addr::from(u32::decode_with(buf, ctx)?))
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<Ipv4Addr, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        Ok(Ipv4Addr::from(u32::read_with(recv, ctx).await?))
+    }
+
+    fn schema(_defs: &mut Definitions) -> Schema {
+        Schema::Ipv4Addr
+    }
+}
+
+impl Encode for Ipv6Addr {
+    fn encoded_len(&self) -> usize {
+        16
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        u128::from(*self).encode(buf)
+    }
+}
+
+impl Payload for Ipv6Addr {
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<Ipv6Addr, PayloadError> {
+        Ok(Ipv6Addr::from(u128::decode_with(buf, ctx)?))
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<Ipv6Addr, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        Ok(Ipv6Addr::from(u128::read_with(recv, ctx).await?))
+    }
+
+    fn schema(_defs: &mut Definitions) -> Schema {
+        Schema::Ipv6Addr
+    }
+}
+
+impl Encode for IpAddr {
+    fn encoded_len(&self) -> usize {
+        match self {
+            IpAddr::V4(addr) => 1 + addr.encoded_len(),
+            IpAddr::V6(addr) => 1 + addr.encoded_len(),
+        }
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        match self {
+            IpAddr::V4(addr) => {
+                4u8.encode(buf)?;
+                addr.encode(buf)
+            }
+            IpAddr::V6(addr) => {
+                6u8.encode(buf)?;
+                addr.encode(buf)
+            }
+        }
+    }
+}
+
+impl Payload for IpAddr {
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<IpAddr, PayloadError> {
+        match u8::decode_with(buf, ctx)? {
+            4 => Ok(IpAddr::V4(Ipv4Addr::decode_with(buf, ctx)?)),
+            6 => Ok(IpAddr::V6(Ipv6Addr::decode_with(buf, ctx)?)),
+            value => Err(unknown_ip_version(value)),
+        }
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<IpAddr, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        match u8::read_with(recv, ctx).await? {
+            4 => Ok(IpAddr::V4(Ipv4Addr::read_with(recv, ctx).await?)),
+            6 => Ok(IpAddr::V6(Ipv6Addr::read_with(recv, ctx).await?)),
+            value => Err(unknown_ip_version(value)),
+        }
+    }
+
+    fn schema(_defs: &mut Definitions) -> Schema {
+        Schema::IpAddr
+    }
+}
+
+fn unknown_ip_version(value: u8) -> PayloadError {
+    PayloadError::UnknownDiscriminant {
+        ty: "IpAddr",
+        value,
+    }
+}
+
+impl Encode for SocketAddr {
+    fn encoded_len(&self) -> usize {
+        self.ip().encoded_len() + 2
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        self.ip().encode(buf)?;
+        self.port().encode(buf)
+    }
+}
+
+impl Payload for SocketAddr {
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<SocketAddr, PayloadError> {
+        let ip = IpAddr::decode_with(buf, ctx)?;
+        let port = u16::decode_with(buf, ctx)?;
+
+        Ok(SocketAddr::new(ip, port))
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<SocketAddr, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let ip = IpAddr::read_with(recv, ctx).await?;
+        let port = u16::read_with(recv, ctx).await?;
+
+        Ok(SocketAddr::new(ip, port))
+    }
+
+    fn schema(_defs: &mut Definitions) -> Schema {
+        Schema::SocketAddr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DecodeLimits;
+
+    /// A context allowing a single level of nesting, which fails if a decoder doesn't leave it.
+    fn shallow_context() -> DecodeContext {
+        DecodeContext::new(DecodeLimits {
+            max_depth: 1,
+            ..DecodeLimits::default()
+        })
+    }
+
+    #[test]
+    fn unknown_result_tag_leaves_the_context() {
+        let mut ctx = shallow_context();
+
+        let error =
+            Result::<u8, u8>::decode_with(&mut Bytes::from_static(&[2, 0]), &mut ctx).unwrap_err();
+        assert!(matches!(
+            error,
+            PayloadError::UnknownDiscriminant {
+                ty: "Result",
+                value: 2
+            }
+        ));
+
+        let value = Result::<u8, u8>::decode_with(&mut Bytes::from_static(&[1, 7]), &mut ctx);
+        assert_eq!(value.unwrap(), Err(7));
+    }
+
+    #[tokio::test]
+    async fn unknown_result_tag_leaves_the_context_when_reading() {
+        let mut ctx = shallow_context();
+
+        let mut recv: &[u8] = &[3, 0];
+        let error = Result::<u8, u8>::read_with(&mut recv, &mut ctx)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, PayloadError::UnknownDiscriminant { .. }));
+
+        let mut recv: &[u8] = &[0, 7];
+        let value = Result::<u8, u8>::read_with(&mut recv, &mut ctx).await;
+        assert_eq!(value.unwrap(), Ok(7));
+    }
+}
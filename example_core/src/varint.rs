@@ -0,0 +1,370 @@
+//! QUIC variable-length integers, as described in RFC 9000 section 16.
+//!
+//! The two most significant bits of the first byte give the encoded length (1, 2, 4 or 8 bytes)
+//! and the remaining bits hold the value in big-endian, so values up to 2^62 - 1 fit.
+//!
+//! The functions of this module are used by integer fields marked `#[payload(varint)]`.
+
+use bytes::{Buf, BufMut};
+use tokio::io::AsyncReadExt;
+
+use crate::schema::{Definitions, LengthEncoding, Schema};
+use crate::{
+    AsyncRead, Bytes, BytesMut, DecodeContext, Encode, LengthPrefix, Payload, PayloadError,
+};
+
+/// An integer encoded in 1 to 8 bytes depending on its value.
+///
+/// Also used as the default [LengthPrefix] of [String], [Vec] and [Bytes].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct VarInt(u64);
+
+impl VarInt {
+    /// The largest value that can be encoded.
+    pub const MAX: u64 = (1 << 62) - 1;
+
+    pub fn new(value: u64) -> Result<VarInt, PayloadError> {
+        if value > Self::MAX {
+            return Err(PayloadError::IntegerOverflow {
+                value: i128::from(value),
+                ty: "VarInt",
+            });
+        }
+
+        Ok(VarInt(value))
+    }
+
+    pub fn into_inner(self) -> u64 {
+        self.0
+    }
+
+    /// Number of bytes of an encoded varint, read from the two most significant bits of its first
+    /// byte.
+    pub(crate) fn len_from_first_byte(first_byte: u8) -> usize {
+        1 << (first_byte >> 6)
+    }
+}
+
+impl From<u32> for VarInt {
+    fn from(value: u32) -> VarInt {
+        VarInt(u64::from(value))
+    }
+}
+
+impl Encode for VarInt {
+    fn encoded_len(&self) -> usize {
+        match self.0 {
+            0..=0x3f => 1,
+            0x40..=0x3fff => 2,
+            0x4000..=0x3fff_ffff => 4,
+            _ => 8,
+        }
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        match self.encoded_len() {
+            1 => buf.put_u8(self.0 as u8),
+            2 => buf.put_u16(0x4000 | self.0 as u16),
+            4 => buf.put_u32(0x8000_0000 | self.0 as u32),
+            _ => buf.put_u64(0xc000_0000_0000_0000 | self.0),
+        }
+
+        Ok(())
+    }
+}
+
+impl Payload for VarInt {
+    fn decode_with(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<VarInt, PayloadError> {
+        let first_byte = buf.try_get_u8()?;
+        let len = Self::len_from_first_byte(first_byte);
+        ctx.consume(len)?;
+
+        let mut value = u64::from(first_byte & 0x3f);
+        for _ in 1..len {
+            value = (value << 8) | u64::from(buf.try_get_u8()?);
+        }
+
+        Ok(VarInt(value))
+    }
+
+    async fn read_with<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<VarInt, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let first_byte = recv.read_u8().await?;
+        let len = Self::len_from_first_byte(first_byte);
+        ctx.consume(len)?;
+
+        let mut bytes = [0u8; 8];
+        bytes[8 - len] = first_byte & 0x3f;
+        recv.read_exact(&mut bytes[9 - len..]).await?;
+
+        Ok(VarInt(u64::from_be_bytes(bytes)))
+    }
+
+    fn schema(_defs: &mut Definitions) -> Schema {
+        Schema::VarInt { zigzag: false }
+    }
+}
+
+impl LengthPrefix for VarInt {
+    const ENCODING: LengthEncoding = LengthEncoding::VarInt;
+
+    fn encoded_len(len: usize) -> usize {
+        match VarInt::new(len as u64) {
+            Ok(len) => len.encoded_len(),
+            Err(_) => 8,
+        }
+    }
+
+    fn encode_len(len: usize, buf: &mut BytesMut) -> Result<(), PayloadError> {
+        VarInt::new(len as u64)
+            .map_err(|_| PayloadError::LengthOverflow {
+                len: len as u64,
+                ty: "VarInt",
+            })?
+            .encode(buf)
+    }
+
+    fn decode_len(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<usize, PayloadError> {
+        let len = VarInt::decode_with(buf, ctx)?.0;
+
+        usize::try_from(len).map_err(|_| PayloadError::LengthOverflow { len, ty: "usize" })
+    }
+
+    async fn read_len<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<usize, PayloadError>
+    where
+        R: AsyncRead + Unpin + Send + ?Sized,
+    {
+        let len = VarInt::read_with(recv, ctx).await?.0;
+
+        usize::try_from(len).map_err(|_| PayloadError::LengthOverflow { len, ty: "usize" })
+    }
+}
+
+/// An integer that can be encoded as a [VarInt] with `#[payload(varint)]`.
+///
+/// Signed integers are zigzag encoded first (0, -1, 1, -2, ... become 0, 1, 2, 3, ...) so that
+/// small negative values stay small on the wire.
+pub trait VarIntInteger: Sized + Copy {
+    /// Whether the integer is zigzag encoded.
+    const SIGNED: bool;
+
+    fn to_varint(self) -> Result<VarInt, PayloadError>;
+
+    fn from_varint(value: VarInt) -> Result<Self, PayloadError>;
+}
+
+macro_rules! impl_varint_integer_for_unsigned {
+    ($($ty:ty),*) => {
+        $(
+            impl VarIntInteger for $ty {
+                const SIGNED: bool = false;
+
+                fn to_varint(self) -> Result<VarInt, PayloadError> {
+                    VarInt::new(self as u64)
+                }
+
+                fn from_varint(value: VarInt) -> Result<$ty, PayloadError> {
+                    <$ty>::try_from(value.0).map_err(|_| PayloadError::IntegerOverflow {
+                        value: i128::from(value.0),
+                        ty: stringify!($ty),
+                    })
+                }
+            }
+        )*
+    };
+}
+
+impl_varint_integer_for_unsigned!(u8, u16, u32, u64, usize);
+
+macro_rules! impl_varint_integer_for_signed {
+    ($($ty:ty),*) => {
+        $(
+            impl VarIntInteger for $ty {
+                const SIGNED: bool = true;
+
+                fn to_varint(self) -> Result<VarInt, PayloadError> {
+                    let value = self as i64;
+
+                    VarInt::new(((value << 1) ^ (value >> 63)) as u64)
+                }
+
+                fn from_varint(value: VarInt) -> Result<$ty, PayloadError> {
+                    let decoded = ((value.0 >> 1) as i64) ^ -((value.0 & 1) as i64);
+
+                    <$ty>::try_from(decoded).map_err(|_| PayloadError::IntegerOverflow {
+                        value: i128::from(decoded),
+                        ty: stringify!($ty),
+                    })
+                }
+            }
+        )*
+    };
+}
+
+impl_varint_integer_for_signed!(i8, i16, i32, i64, isize);
+
+pub fn encoded_len<T: VarIntInteger>(value: &T) -> usize {
+    match value.to_varint() {
+        Ok(value) => value.encoded_len(),
+        Err(_) => 8,
+    }
+}
+
+pub fn encode<T: VarIntInteger>(value: &T, buf: &mut BytesMut) -> Result<(), PayloadError> {
+    value.to_varint()?.encode(buf)
+}
+
+pub fn decode_with<T: VarIntInteger>(
+    buf: &mut Bytes,
+    ctx: &mut DecodeContext,
+) -> Result<T, PayloadError> {
+    T::from_varint(VarInt::decode_with(buf, ctx)?)
+}
+
+pub fn schema<T: VarIntInteger>() -> Schema {
+    Schema::VarInt { zigzag: T::SIGNED }
+}
+
+pub async fn read_with<T, R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<T, PayloadError>
+where
+    T: VarIntInteger,
+    R: AsyncRead + Unpin + Send + ?Sized,
+{
+    T::from_varint(VarInt::read_with(recv, ctx).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The values at both ends of each encoded length, with the length they are encoded in.
+    const BOUNDARIES: [(u64, usize); 8] = [
+        (0, 1),
+        (63, 1),
+        (64, 2),
+        (16_383, 2),
+        (16_384, 4),
+        ((1 << 30) - 1, 4),
+        (1 << 30, 8),
+        (VarInt::MAX, 8),
+    ];
+
+    #[tokio::test]
+    async fn boundaries() {
+        for (value, len) in BOUNDARIES {
+            let varint = VarInt::new(value).unwrap();
+            let bytes = varint.to_bytes().unwrap();
+            assert_eq!(bytes.len(), len, "{value}");
+            assert_eq!(varint.encoded_len(), len, "{value}");
+            // the two most significant bits hold the log2 of the length
+            assert_eq!(u32::from(bytes[0] >> 6), len.trailing_zeros(), "{value}");
+
+            assert_eq!(VarInt::decode(&mut bytes.clone()).unwrap(), varint);
+            let mut recv: &[u8] = &bytes;
+            assert_eq!(
+                VarInt::read_from_recv_stream(&mut recv).await.unwrap(),
+                varint
+            );
+            assert!(recv.is_empty());
+        }
+    }
+
+    #[test]
+    fn rfc_examples() {
+        // RFC 9000 appendix A.1
+        let examples: [(&[u8], u64); 4] = [
+            (
+                &[0xc2, 0x19, 0x7c, 0x5e, 0xff, 0x14, 0xe8, 0x8c],
+                151_288_809_941_952_652,
+            ),
+            (&[0x9d, 0x7f, 0x3e, 0x7d], 494_878_333),
+            (&[0x7b, 0xbd], 15_293),
+            (&[0x25], 37),
+        ];
+
+        for (bytes, value) in examples {
+            let varint = VarInt::decode(&mut Bytes::copy_from_slice(bytes)).unwrap();
+            assert_eq!(varint.into_inner(), value);
+            assert_eq!(varint.to_bytes().unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn values_too_large_fail() {
+        for value in [VarInt::MAX + 1, u64::MAX] {
+            assert!(matches!(
+                VarInt::new(value),
+                Err(PayloadError::IntegerOverflow { ty: "VarInt", .. })
+            ));
+            assert!(matches!(
+                value.to_varint(),
+                Err(PayloadError::IntegerOverflow { ty: "VarInt", .. })
+            ));
+            assert!(encode(&value, &mut BytesMut::new()).is_err());
+        }
+
+        // fits in a varint, but not in the integer it is decoded to
+        let bytes = VarInt::new(256).unwrap().to_bytes().unwrap();
+        let error = decode_with::<u8>(&mut bytes.clone(), &mut DecodeContext::default());
+        assert!(matches!(
+            error,
+            Err(PayloadError::IntegerOverflow {
+                value: 256,
+                ty: "u8"
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn truncated_varints_fail() {
+        for (value, len) in BOUNDARIES {
+            let bytes = VarInt::new(value).unwrap().to_bytes().unwrap();
+
+            for truncated_len in 0..len {
+                let truncated = bytes.slice(..truncated_len);
+
+                let error = VarInt::decode(&mut truncated.clone()).unwrap_err();
+                assert!(error.is_unexpected_end(), "{value}: {error}");
+                let mut recv: &[u8] = &truncated;
+                let error = VarInt::read_from_recv_stream(&mut recv).await.unwrap_err();
+                assert!(error.is_unexpected_end(), "{value}: {error}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn zigzag_boundaries() {
+        for value in [
+            0i64,
+            -1,
+            1,
+            -32,
+            31,
+            -33,
+            32,
+            i64::from(i32::MIN),
+            (1 << 61) - 1,
+        ] {
+            let mut buf = BytesMut::new();
+            encode(&value, &mut buf).unwrap();
+            assert_eq!(buf.len(), encoded_len(&value), "{value}");
+
+            let bytes = buf.freeze();
+            let decoded: i64 =
+                decode_with(&mut bytes.clone(), &mut DecodeContext::default()).unwrap();
+            assert_eq!(decoded, value);
+            let mut recv: &[u8] = &bytes;
+            let read: i64 = read_with(&mut recv, &mut DecodeContext::default())
+                .await
+                .unwrap();
+            assert_eq!(read, value);
+        }
+
+        // 1 byte holds -32 to 31 once zigzag encoded
+        assert_eq!(encoded_len(&-32i64), 1);
+        assert_eq!(encoded_len(&32i64), 2);
+        assert!(encode(&(1i64 << 61), &mut BytesMut::new()).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+//! The encoding of structs marked `#[payload(versioned)]`, used by the derived code.
+//!
+//! A versioned struct is encoded as a [VarInt] body length followed by the body, which is the
+//! sequence of its fields. Each field is its [VarInt] tag, the [VarInt] length of its value and
+//! the value:
+//!
+//! ```text
+//! body_len | tag len value | tag len value | ...
+//! ```
+//!
+//! Since every field carries its length, a decoder skips the tags it doesn't know, and since the
+//! body carries its length, a struct can be followed by other values whatever fields it holds.
+
+use crate::{
+    AsyncRead, Bytes, BytesMut, DecodeContext, Encode, LengthPrefix, Payload, PayloadError, VarInt,
+};
+
+/// Number of bytes taken by a field with the given tag and value length.
+pub fn field_len(tag: u32, len: usize) -> usize {
+    <VarInt as LengthPrefix>::encoded_len(tag as usize)
+        + <VarInt as LengthPrefix>::encoded_len(len)
+        + len
+}
+
+/// Number of bytes taken by a struct whose fields take `body_len` bytes.
+pub fn encoded_len(body_len: usize) -> usize {
+    <VarInt as LengthPrefix>::encoded_len(body_len) + body_len
+}
+
+pub fn encode_body_len(body_len: usize, buf: &mut BytesMut) -> Result<(), PayloadError> {
+    <VarInt as LengthPrefix>::encode_len(body_len, buf)
+}
+
+/// Writes the tag and the length of a field, which must then be followed by its value.
+pub fn encode_field_header(tag: u32, len: usize, buf: &mut BytesMut) -> Result<(), PayloadError> {
+    VarInt::from(tag).encode(buf)?;
+    <VarInt as LengthPrefix>::encode_len(len, buf)
+}
+
+/// Splits the body of a struct from the start of `buf`.
+pub fn decode_body(buf: &mut Bytes, ctx: &mut DecodeContext) -> Result<Bytes, PayloadError> {
+    let len = <VarInt as LengthPrefix>::decode_len(buf, ctx)?;
+    ctx.check_available(len)?;
+
+    if buf.len() < len {
+        return Err(PayloadError::UnexpectedEnd);
+    }
+
+    Ok(buf.split_to(len))
+}
+
+/// Reads the body of a struct from `recv`.
+pub async fn read_body<R>(recv: &mut R, ctx: &mut DecodeContext) -> Result<Bytes, PayloadError>
+where
+    R: AsyncRead + Unpin + Send + ?Sized,
+{
+    let len = <VarInt as LengthPrefix>::read_len(recv, ctx).await?;
+    ctx.check_available(len)?;
+
+    Ok(Bytes::from(crate::read_exact(recv, len).await?))
+}
+
+/// Splits the next field from `body`, returning its tag and its value.
+pub fn decode_field(
+    body: &mut Bytes,
+    ctx: &mut DecodeContext,
+) -> Result<(u64, Bytes), PayloadError> {
+    let tag = VarInt::decode_with(body, ctx)?.into_inner();
+    let len = <VarInt as LengthPrefix>::decode_len(body, ctx)?;
+
+    if body.len() < len {
+        return Err(PayloadError::UnexpectedEnd);
+    }
+
+    Ok((tag, body.split_to(len)))
+}
@@ -0,0 +1,333 @@
+//! Generates the Rust code of a checked IDL file.
+
+use std::fmt::Write;
+
+use crate::parse::{Command, Commands, Enum, Field, File, Item, Message, Type};
+
+impl Type {
+    fn rust(&self) -> String {
+        match self {
+            Type::Bool => "bool".to_string(),
+            Type::U8 => "u8".to_string(),
+            Type::U16 => "u16".to_string(),
+            Type::U32 => "u32".to_string(),
+            Type::U64 => "u64".to_string(),
+            Type::U128 => "u128".to_string(),
+            Type::I8 => "i8".to_string(),
+            Type::I16 => "i16".to_string(),
+            Type::I32 => "i32".to_string(),
+            Type::I64 => "i64".to_string(),
+            Type::I128 => "i128".to_string(),
+            Type::F32 => "f32".to_string(),
+            Type::F64 => "f64".to_string(),
+            Type::Char => "char".to_string(),
+            Type::String => "String".to_string(),
+            Type::Bytes => "example_core::Bytes".to_string(),
+            Type::Uuid => "uuid::Uuid".to_string(),
+            Type::Duration => "std::time::Duration".to_string(),
+            Type::Timestamp => "std::time::SystemTime".to_string(),
+            Type::List(ty) => format!("Vec<{}>", ty.rust()),
+            Type::Option(ty) => format!("Option<{}>", ty.rust()),
+            Type::Map(key, value) => {
+                format!(
+                    "std::collections::HashMap<{}, {}>",
+                    key.rust(),
+                    value.rust()
+                )
+            }
+            Type::Result(ok, err) => format!("Result<{}, {}>", ok.rust(), err.rust()),
+            Type::Unit => "()".to_string(),
+            Type::Named(name) => name.clone(),
+        }
+    }
+
+    /// Whether the type is `Copy`, so that getters return it by value. The generated enums are.
+    fn is_copy(&self, file: &File) -> bool {
+        match self {
+            Type::String | Type::Bytes | Type::List(_) | Type::Map(_, _) | Type::Result(_, _) => {
+                false
+            }
+            Type::Option(ty) => ty.is_copy(file),
+            Type::Named(name) => file
+                .items
+                .iter()
+                .any(|item| matches!(item, Item::Enum(e) if e.name.name == *name)),
+            _ => true,
+        }
+    }
+}
+
+fn write_docs(out: &mut String, indent: &str, docs: &[String]) {
+    for doc in docs {
+        match doc.is_empty() {
+            true => writeln!(out, "{indent}///").unwrap(),
+            false => writeln!(out, "{indent}/// {doc}").unwrap(),
+        }
+    }
+}
+
+/// Writes the parameter of `new` for a field and the expression storing it.
+fn constructor_parameter(field: &Field) -> (String, String) {
+    let name = &field.name.name;
+
+    match field.ty {
+        Type::String => (
+            format!("{name}: impl Into<String>"),
+            format!("{name}.into()"),
+        ),
+        _ => (format!("{name}: {}", field.ty.rust()), name.clone()),
+    }
+}
+
+/// Writes the return type of the getter of a field and its body.
+fn getter(field: &Field, file: &File) -> (String, String) {
+    let name = &field.name.name;
+
+    match &field.ty {
+        ty if ty.is_copy(file) => (ty.rust(), format!("self.{name}")),
+        Type::String => ("&str".to_string(), format!("&self.{name}")),
+        Type::List(ty) => (format!("&[{}]", ty.rust()), format!("&self.{name}")),
+        Type::Option(ty) if **ty == Type::String => (
+            "Option<&str>".to_string(),
+            format!("self.{name}.as_deref()"),
+        ),
+        Type::Option(ty) => (
+            format!("Option<&{}>", ty.rust()),
+            format!("self.{name}.as_ref()"),
+        ),
+        ty => (format!("&{}", ty.rust()), format!("&self.{name}")),
+    }
+}
+
+fn generate_message(out: &mut String, message: &Message, file: &File) {
+    let name = &message.name.name;
+
+    write_docs(out, "", &message.docs);
+    writeln!(out, "#[derive(lib::Payload, Clone, Debug, PartialEq)]").unwrap();
+    if message.versioned {
+        writeln!(out, "#[payload(versioned)]").unwrap();
+    }
+    writeln!(out, "pub struct {name} {{").unwrap();
+    for field in &message.fields {
+        write_docs(out, "    ", &field.docs);
+
+        let mut attrs = vec![];
+        if let Some(tag) = field.tag {
+            attrs.push(format!("tag = {tag}"));
+        }
+        if field.default {
+            attrs.push("default".to_string());
+        }
+        if field.varint {
+            attrs.push("varint".to_string());
+        }
+        if !attrs.is_empty() {
+            writeln!(out, "    #[payload({})]", attrs.join(", ")).unwrap();
+        }
+
+        writeln!(out, "    {}: {},", field.name.name, field.ty.rust()).unwrap();
+    }
+    writeln!(out, "}}\n").unwrap();
+
+    let (parameters, values): (Vec<_>, Vec<_>) =
+        message.fields.iter().map(constructor_parameter).unzip();
+    writeln!(out, "#[allow(dead_code)]").unwrap();
+    writeln!(out, "impl {name} {{").unwrap();
+    writeln!(out, "    pub fn new({}) -> Self {{", parameters.join(", ")).unwrap();
+    writeln!(out, "        Self {{").unwrap();
+    for (field, value) in message.fields.iter().zip(values) {
+        match field.name.name == value {
+            true => writeln!(out, "            {value},").unwrap(),
+            false => writeln!(out, "            {}: {value},", field.name.name).unwrap(),
+        }
+    }
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "    }}").unwrap();
+    for field in &message.fields {
+        let (ty, body) = getter(field, file);
+
+        writeln!(out).unwrap();
+        write_docs(out, "    ", &field.docs);
+        writeln!(out, "    pub fn {}(&self) -> {ty} {{", field.name.name).unwrap();
+        writeln!(out, "        {body}").unwrap();
+        writeln!(out, "    }}").unwrap();
+    }
+    writeln!(out, "}}\n").unwrap();
+}
+
+fn generate_enum(out: &mut String, enumeration: &Enum) {
+    let name = &enumeration.name.name;
+
+    write_docs(out, "", &enumeration.docs);
+    writeln!(
+        out,
+        "#[derive(lib::Payload, Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]"
+    )
+    .unwrap();
+    writeln!(out, "#[repr(u8)]").unwrap();
+    writeln!(out, "pub enum {name} {{").unwrap();
+    for (i, variant) in enumeration.variants.iter().enumerate() {
+        write_docs(out, "    ", &variant.docs);
+        if i == 0 {
+            writeln!(out, "    #[default]").unwrap();
+        }
+        writeln!(out, "    {} = {},", variant.name.name, variant.tag.unwrap()).unwrap();
+    }
+    writeln!(out, "}}\n").unwrap();
+}
+
+/// Describes the input or output of a command in its doc comment.
+fn command_type_doc(ty: &Type) -> String {
+    match ty {
+        Type::Unit => "nothing".to_string(),
+        ty => format!("[`{}`]", ty.rust()),
+    }
+}
+
+fn generate_command(out: &mut String, command: &Command) {
+    write_docs(out, "    ", &command.docs);
+    if !command.docs.is_empty() {
+        writeln!(out, "    ///").unwrap();
+    }
+    writeln!(out, "    /// Input = {}", command_type_doc(&command.input)).unwrap();
+    writeln!(out, "    ///").unwrap();
+    writeln!(
+        out,
+        "    /// Output = {}",
+        command_type_doc(&command.output)
+    )
+    .unwrap();
+
+    let mut attrs = vec![format!("id = {}", command.id.unwrap())];
+    if command.input != Type::Unit {
+        attrs.push(format!("input = {}", command.input.rust()));
+    }
+    if command.output != Type::Unit {
+        attrs.push(format!("output = {}", command.output.rust()));
+    }
+    writeln!(out, "    #[command({})]", attrs.join(", ")).unwrap();
+    writeln!(out, "    {},", command.name.name).unwrap();
+}
+
+fn generate_commands(out: &mut String, commands: &Commands) {
+    write_docs(out, "", &commands.docs);
+    writeln!(
+        out,
+        "#[derive(lib::Command, Copy, Clone, Debug, Eq, PartialEq, Hash)]"
+    )
+    .unwrap();
+    writeln!(out, "pub enum {} {{", commands.name.name).unwrap();
+    for command in &commands.commands {
+        generate_command(out, command);
+    }
+    writeln!(
+        out,
+        "    /// An id matching no command, like one sent by a newer peer."
+    )
+    .unwrap();
+    writeln!(out, "    #[command(unknown)]").unwrap();
+    writeln!(out, "    Unknown(u8),").unwrap();
+    writeln!(out, "}}\n").unwrap();
+}
+
+pub fn generate(file: &File) -> String {
+    let mut out =
+        String::from("// Generated from an IDL file by the `idl` crate, do not edit.\n\n");
+
+    for item in &file.items {
+        match item {
+            Item::Message(message) => generate_message(&mut out, message, file),
+            Item::Enum(enumeration) => generate_enum(&mut out, enumeration),
+            Item::Commands(commands) => generate_commands(&mut out, commands),
+        }
+    }
+    // a single newline at the end
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn generates_every_item() {
+        let code = crate::generate(
+            "/// A user.
+versioned message User {
+    name: string = 1;
+    default status: Status;
+}
+
+enum Status {
+    Online;
+    Away = 3;
+}
+
+commands Server {
+    Login: string -> User;
+    Logout = 4;
+}
+",
+        )
+        .unwrap();
+        assert_eq!(
+            code,
+            "// Generated from an IDL file by the `idl` crate, do not edit.
+
+/// A user.
+#[derive(lib::Payload, Clone, Debug, PartialEq)]
+#[payload(versioned)]
+pub struct User {
+    #[payload(tag = 1)]
+    name: String,
+    #[payload(tag = 2, default)]
+    status: Status,
+}
+
+#[allow(dead_code)]
+impl User {
+    pub fn new(name: impl Into<String>, status: Status) -> Self {
+        Self {
+            name: name.into(),
+            status,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+}
+
+#[derive(lib::Payload, Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+#[repr(u8)]
+pub enum Status {
+    #[default]
+    Online = 0,
+    Away = 3,
+}
+
+#[derive(lib::Command, Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Server {
+    /// Input = [`String`]
+    ///
+    /// Output = [`User`]
+    #[command(id = 0, input = String, output = User)]
+    Login,
+    /// Input = nothing
+    ///
+    /// Output = nothing
+    #[command(id = 4)]
+    Logout,
+    /// An id matching no command, like one sent by a newer peer.
+    #[command(unknown)]
+    Unknown(u8),
+}
+"
+        );
+    }
+}
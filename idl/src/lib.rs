@@ -0,0 +1,152 @@
+//! Compiles a protocol described in a small IDL into Rust types deriving `Payload`.
+//!
+//! The generator is meant to be called from a `build.rs`, the generated code being included in
+//! the crate with `include!`:
+//!
+//! ```text
+//! // build.rs
+//! fn main() {
+//!     println!("cargo:rerun-if-changed=src/protocol.idl");
+//!
+//!     let out_dir = std::path::PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+//!     idl::compile("src/protocol.idl", out_dir.join("protocol.rs")).unwrap_or_else(|e| panic!("{e}"));
+//! }
+//!
+//! // src/protocol.rs
+//! include!(concat!(env!("OUT_DIR"), "/protocol.rs"));
+//! ```
+//!
+//! The generated code uses the `lib`, `example_core` and `uuid` crates, which must be dependencies
+//! of the crate including it.
+//!
+//! # Syntax
+//!
+//! ```text
+//! // comments are ignored, `///` doc comments are copied to the generated items
+//!
+//! /// A struct with private fields, a `new` constructor taking every field and a getter per field.
+//! message Message {
+//!     message: string;
+//!     sent_by: option<User>;
+//! }
+//!
+//! /// A `#[payload(versioned)]` struct, a field tag defaults to the previous tag plus one.
+//! versioned message User {
+//!     client_id: uuid = 0;
+//!     username: string = 1;
+//!     default nickname: option<string> = 2;
+//! }
+//!
+//! /// An enum without fields, encoded as its `u8` tag. The first variant is the `Default`.
+//! enum Status {
+//!     Online = 0;
+//!     Away;
+//! }
+//!
+//! /// An enum deriving `Command`, with a generated `Unknown(u8)` variant for unknown ids. A
+//! /// command without input or output can leave out its `: input -> output`.
+//! commands ServerCommand {
+//!     Login = 0: LoginInput -> result<LoginOutput, string>;
+//!     SendMessage = 1: SendMessageInput -> ();
+//!     Logout;
+//! }
+//! ```
+//!
+//! Fields of versioned messages may be prefixed by `default`, filling them with
+//! `Default::default()` when they weren't sent, and fields may be prefixed by `varint` for
+//! integers encoded as varints, see the `Payload` derive.
+//!
+//! The types are `bool`, `u8` to `u128`, `i8` to `i128`, `f32`, `f64`, `char`, `string`, `bytes`,
+//! `uuid`, `duration`, `timestamp`, `list<T>`, `option<T>`, `map<K, V>`, `result<T, E>` and the
+//! messages and enums of the file. Commands may also use `()` when they have no input or output.
+
+mod generate;
+mod parse;
+
+use std::error::Error as StdError;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading the IDL file or writing the generated code failed.
+    Io { path: PathBuf, source: io::Error },
+    /// The IDL is invalid, `line` and `column` start at 1. `path` is set by [compile].
+    Syntax {
+        path: Option<PathBuf>,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            Error::Syntax {
+                path: Some(path),
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{line}:{column}: {message}", path.display()),
+            Error::Syntax {
+                path: None,
+                line,
+                column,
+                message,
+            } => write!(f, "{line}:{column}: {message}"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Syntax { .. } => None,
+        }
+    }
+}
+
+/// Generates the Rust code of the protocol described by `source`.
+pub fn generate(source: &str) -> Result<String, Error> {
+    let file = parse::parse(source)?;
+
+    Ok(generate::generate(&file))
+}
+
+/// Generates the Rust code of the protocol described in the file at `input`, writing it to
+/// `output` unless it already holds that code, so that dependent code isn't rebuilt needlessly.
+pub fn compile(input: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<(), Error> {
+    let (input, output) = (input.as_ref(), output.as_ref());
+
+    let source = fs::read_to_string(input).map_err(|source| Error::Io {
+        path: input.to_path_buf(),
+        source,
+    })?;
+    let code = generate(&source).map_err(|e| match e {
+        Error::Syntax {
+            line,
+            column,
+            message,
+            ..
+        } => Error::Syntax {
+            path: Some(input.to_path_buf()),
+            line,
+            column,
+            message,
+        },
+        e => e,
+    })?;
+
+    if fs::read_to_string(output).is_ok_and(|existing| existing == code) {
+        return Ok(());
+    }
+    fs::write(output, code).map_err(|source| Error::Io {
+        path: output.to_path_buf(),
+        source,
+    })
+}
@@ -0,0 +1,922 @@
+//! Parses and checks an IDL file, see the crate documentation for the syntax.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::Error;
+
+/// A position in the source, starting at 1.
+#[derive(Copy, Clone, Debug)]
+struct Pos {
+    line: usize,
+    column: usize,
+}
+
+impl Pos {
+    fn error(self, message: impl Into<String>) -> Error {
+        Error::Syntax {
+            path: None,
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(u64),
+    /// The text of a `///` comment, without the slashes and the first space.
+    Doc(String),
+    Punct(&'static str),
+    End,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Ident(ident) => format!("`{ident}`"),
+            Token::Int(value) => format!("`{value}`"),
+            Token::Doc(_) => "a doc comment".to_string(),
+            Token::Punct(punct) => format!("`{punct}`"),
+            Token::End => "the end of the file".to_string(),
+        }
+    }
+}
+
+const PUNCTS: &[&str] = &["->", "()", "{", "}", "<", ">", ";", ":", ",", "="];
+
+fn tokenize(source: &str) -> Result<Vec<(Token, Pos)>, Error> {
+    let mut tokens = vec![];
+
+    for (line_index, line) in source.lines().enumerate() {
+        let mut rest = line;
+
+        loop {
+            rest = rest.trim_start();
+            let pos = Pos {
+                line: line_index + 1,
+                column: line.len() - rest.len() + 1,
+            };
+
+            if rest.is_empty() {
+                break;
+            } else if let Some(doc) = rest.strip_prefix("///") {
+                tokens.push((
+                    Token::Doc(doc.strip_prefix(' ').unwrap_or(doc).to_string()),
+                    pos,
+                ));
+                break;
+            } else if rest.starts_with("//") {
+                break;
+            }
+
+            let c = rest.chars().next().unwrap();
+            if c.is_ascii_alphabetic() || c == '_' {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                tokens.push((Token::Ident(rest[..end].to_string()), pos));
+                rest = &rest[end..];
+            } else if c.is_ascii_digit() {
+                let end = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                let value = rest[..end]
+                    .parse()
+                    .map_err(|_| pos.error(format!("`{}` is too large", &rest[..end])))?;
+                tokens.push((Token::Int(value), pos));
+                rest = &rest[end..];
+            } else if let Some(punct) = PUNCTS.iter().find(|punct| rest.starts_with(**punct)) {
+                tokens.push((Token::Punct(punct), pos));
+                rest = &rest[punct.len()..];
+            } else {
+                return Err(pos.error(format!("unexpected character `{c}`")));
+            }
+        }
+    }
+
+    let end = Pos {
+        line: source.lines().count() + 1,
+        column: 1,
+    };
+    tokens.push((Token::End, end));
+
+    Ok(tokens)
+}
+
+/// A named thing with the position of its name, for errors.
+#[derive(Clone, Debug)]
+pub struct Name {
+    pub name: String,
+    pos: Pos,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    F32,
+    F64,
+    Char,
+    String,
+    Bytes,
+    Uuid,
+    Duration,
+    Timestamp,
+    List(Box<Type>),
+    Option(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    Result(Box<Type>, Box<Type>),
+    /// `()`, only allowed as the input or output of a command.
+    Unit,
+    /// A message or an enum of the file.
+    Named(String),
+}
+
+impl Type {
+    fn is_varint_integer(&self) -> bool {
+        matches!(
+            self,
+            Type::U8
+                | Type::U16
+                | Type::U32
+                | Type::U64
+                | Type::I8
+                | Type::I16
+                | Type::I32
+                | Type::I64
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct File {
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug)]
+pub enum Item {
+    Message(Message),
+    Enum(Enum),
+    Commands(Commands),
+}
+
+#[derive(Debug)]
+pub struct Message {
+    pub docs: Vec<String>,
+    pub name: Name,
+    pub versioned: bool,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug)]
+pub struct Field {
+    pub docs: Vec<String>,
+    pub name: Name,
+    pub ty: Type,
+    /// The tag of the field, always set once checked if the message is versioned.
+    pub tag: Option<u32>,
+    pub default: bool,
+    pub varint: bool,
+}
+
+#[derive(Debug)]
+pub struct Enum {
+    pub docs: Vec<String>,
+    pub name: Name,
+    pub variants: Vec<Variant>,
+}
+
+#[derive(Debug)]
+pub struct Variant {
+    pub docs: Vec<String>,
+    pub name: Name,
+    /// The tag of the variant, always set once checked.
+    pub tag: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct Commands {
+    pub docs: Vec<String>,
+    pub name: Name,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug)]
+pub struct Command {
+    pub docs: Vec<String>,
+    pub name: Name,
+    /// The id of the command, always set once checked.
+    pub id: Option<u64>,
+    pub input: Type,
+    pub output: Type,
+}
+
+struct Parser {
+    tokens: Vec<(Token, Pos)>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.next].0
+    }
+
+    fn pos(&self) -> Pos {
+        self.tokens[self.next].1
+    }
+
+    fn bump(&mut self) -> (Token, Pos) {
+        let token = self.tokens[self.next].clone();
+        if token.0 != Token::End {
+            self.next += 1;
+        }
+
+        token
+    }
+
+    fn unexpected(&self, expected: &str) -> Error {
+        self.pos().error(format!(
+            "expected {expected}, found {}",
+            self.peek().describe()
+        ))
+    }
+
+    /// Consumes the identifier `keyword` if it is next.
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Token::Ident(ident) if ident == keyword);
+        if found {
+            self.bump();
+        }
+
+        found
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        let found = matches!(self.peek(), Token::Punct(p) if *p == punct);
+        if found {
+            self.bump();
+        }
+
+        found
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<(), Error> {
+        match self.eat_punct(punct) {
+            true => Ok(()),
+            false => Err(self.unexpected(&format!("`{punct}`"))),
+        }
+    }
+
+    fn expect_name(&mut self) -> Result<Name, Error> {
+        match self.peek() {
+            Token::Ident(ident) => {
+                let name = Name {
+                    name: ident.clone(),
+                    pos: self.pos(),
+                };
+                self.bump();
+
+                Ok(name)
+            }
+            _ => Err(self.unexpected("a name")),
+        }
+    }
+
+    fn expect_int(&mut self) -> Result<(u64, Pos), Error> {
+        match *self.peek() {
+            Token::Int(value) => Ok((value, self.bump().1)),
+            _ => Err(self.unexpected("an integer")),
+        }
+    }
+
+    /// Parses an optional `= N`.
+    fn tag(&mut self) -> Result<Option<(u64, Pos)>, Error> {
+        match self.eat_punct("=") {
+            true => self.expect_int().map(Some),
+            false => Ok(None),
+        }
+    }
+
+    fn docs(&mut self) -> Vec<String> {
+        let mut docs = vec![];
+        while let Token::Doc(doc) = self.peek() {
+            docs.push(doc.clone());
+            self.bump();
+        }
+
+        docs
+    }
+
+    fn file(&mut self) -> Result<File, Error> {
+        let mut items = vec![];
+
+        loop {
+            let docs = self.docs();
+            if *self.peek() == Token::End {
+                break;
+            }
+
+            let item = if self.eat_keyword("versioned") {
+                if !self.eat_keyword("message") {
+                    return Err(self.unexpected("`message`"));
+                }
+                Item::Message(self.message(docs, true)?)
+            } else if self.eat_keyword("message") {
+                Item::Message(self.message(docs, false)?)
+            } else if self.eat_keyword("enum") {
+                Item::Enum(self.enumeration(docs)?)
+            } else if self.eat_keyword("commands") {
+                Item::Commands(self.commands(docs)?)
+            } else {
+                return Err(self.unexpected("`message`, `versioned`, `enum` or `commands`"));
+            };
+            items.push(item);
+        }
+
+        Ok(File { items })
+    }
+
+    /// Parses `{ (docs item ;)* }`.
+    fn block<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Parser, Vec<String>) -> Result<T, Error>,
+    ) -> Result<Vec<T>, Error> {
+        self.expect_punct("{")?;
+
+        let mut items = vec![];
+        loop {
+            let docs = self.docs();
+            if self.eat_punct("}") {
+                return Ok(items);
+            }
+            items.push(item(self, docs)?);
+            self.expect_punct(";")?;
+        }
+    }
+
+    fn message(&mut self, docs: Vec<String>, versioned: bool) -> Result<Message, Error> {
+        let name = self.expect_name()?;
+        let fields = self.block(|parser, docs| {
+            let mut default = false;
+            let mut varint = false;
+            loop {
+                if parser.eat_keyword("default") {
+                    default = true;
+                } else if parser.eat_keyword("varint") {
+                    varint = true;
+                } else {
+                    break;
+                }
+            }
+
+            let name = parser.expect_name()?;
+            parser.expect_punct(":")?;
+            let ty_pos = parser.pos();
+            let ty = parser.ty()?;
+            if ty == Type::Unit {
+                return Err(
+                    ty_pos.error("`()` is only allowed as the input or output of a command")
+                );
+            }
+            if varint && !ty.is_varint_integer() {
+                return Err(ty_pos.error("`varint` fields must be integers of at most 64 bits"));
+            }
+
+            let tag = match parser.tag()? {
+                Some((_, pos)) if !versioned => {
+                    return Err(pos.error("field tags are only allowed in versioned messages"));
+                }
+                Some((tag, pos)) => Some(
+                    u32::try_from(tag).map_err(|_| pos.error("field tags must fit in a u32"))?,
+                ),
+                None => None,
+            };
+
+            Ok(Field {
+                docs,
+                name,
+                ty,
+                tag,
+                default,
+                varint,
+            })
+        })?;
+
+        Ok(Message {
+            docs,
+            name,
+            versioned,
+            fields,
+        })
+    }
+
+    fn enumeration(&mut self, docs: Vec<String>) -> Result<Enum, Error> {
+        let name = self.expect_name()?;
+        let variants = self.block(|parser, docs| {
+            Ok(Variant {
+                docs,
+                name: parser.expect_name()?,
+                tag: parser.tag()?.map(|(tag, _)| tag),
+            })
+        })?;
+
+        Ok(Enum {
+            docs,
+            name,
+            variants,
+        })
+    }
+
+    fn commands(&mut self, docs: Vec<String>) -> Result<Commands, Error> {
+        let name = self.expect_name()?;
+        let commands = self.block(|parser, docs| {
+            let name = parser.expect_name()?;
+            let id = parser.tag()?.map(|(id, _)| id);
+            let (input, output) = match parser.eat_punct(":") {
+                true => {
+                    let input = parser.ty()?;
+                    parser.expect_punct("->")?;
+                    (input, parser.ty()?)
+                }
+                false => (Type::Unit, Type::Unit),
+            };
+
+            Ok(Command {
+                docs,
+                name,
+                id,
+                input,
+                output,
+            })
+        })?;
+
+        Ok(Commands {
+            docs,
+            name,
+            commands,
+        })
+    }
+
+    fn ty(&mut self) -> Result<Type, Error> {
+        if self.eat_punct("()") {
+            return Ok(Type::Unit);
+        }
+
+        let name = self.expect_name()?;
+        let ty = match name.name.as_str() {
+            "bool" => Type::Bool,
+            "u8" => Type::U8,
+            "u16" => Type::U16,
+            "u32" => Type::U32,
+            "u64" => Type::U64,
+            "u128" => Type::U128,
+            "i8" => Type::I8,
+            "i16" => Type::I16,
+            "i32" => Type::I32,
+            "i64" => Type::I64,
+            "i128" => Type::I128,
+            "f32" => Type::F32,
+            "f64" => Type::F64,
+            "char" => Type::Char,
+            "string" => Type::String,
+            "bytes" => Type::Bytes,
+            "uuid" => Type::Uuid,
+            "duration" => Type::Duration,
+            "timestamp" => Type::Timestamp,
+            "list" => Type::List(Box::new(self.type_arguments::<1>()?[0].clone())),
+            "option" => Type::Option(Box::new(self.type_arguments::<1>()?[0].clone())),
+            "map" => {
+                let [key, value] = self.type_arguments::<2>()?;
+                Type::Map(Box::new(key), Box::new(value))
+            }
+            "result" => {
+                let [ok, err] = self.type_arguments::<2>()?;
+                Type::Result(Box::new(ok), Box::new(err))
+            }
+            _ => Type::Named(name.name),
+        };
+
+        Ok(ty)
+    }
+
+    /// Parses `<T, ...>` with `N` types.
+    fn type_arguments<const N: usize>(&mut self) -> Result<[Type; N], Error> {
+        self.expect_punct("<")?;
+
+        let mut types = vec![];
+        for i in 0..N {
+            if i > 0 {
+                self.expect_punct(",")?;
+            }
+            let pos = self.pos();
+            let ty = self.ty()?;
+            if ty == Type::Unit {
+                return Err(pos.error("`()` is only allowed as the input or output of a command"));
+            }
+            types.push(ty);
+        }
+        self.expect_punct(">")?;
+
+        Ok(types.try_into().unwrap())
+    }
+}
+
+/// Fails if `names` holds the same name twice.
+fn check_unique<'a>(names: impl IntoIterator<Item = &'a Name>, what: &str) -> Result<(), Error> {
+    let mut seen = BTreeSet::new();
+    for name in names {
+        if !seen.insert(name.name.as_str()) {
+            return Err(name
+                .pos
+                .error(format!("{what} `{}` is defined twice", name.name)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Fills in the missing tags, each defaulting to the previous tag plus one (starting at 0), and
+/// fails if a tag is used twice or is larger than `max`.
+fn resolve_tags<'a>(
+    tags: impl IntoIterator<Item = (&'a Name, &'a mut Option<u64>)>,
+    what: &str,
+    max: u64,
+) -> Result<(), Error> {
+    let mut seen = BTreeMap::new();
+    let mut next = 0;
+    for (name, tag) in tags {
+        let value = *tag.get_or_insert(next);
+        if value > max {
+            return Err(name.pos.error(format!(
+                "the {what} of `{}` must be at most {max}",
+                name.name
+            )));
+        }
+        if let Some(previous) = seen.insert(value, &name.name) {
+            return Err(name.pos.error(format!(
+                "`{}` has the same {what} as `{previous}`: {value}",
+                name.name
+            )));
+        }
+        next = value + 1;
+    }
+
+    Ok(())
+}
+
+/// Fails if `ty` names a type that isn't a message or an enum of the file.
+fn check_type(ty: &Type, types: &BTreeSet<&str>, pos: Pos) -> Result<(), Error> {
+    match ty {
+        Type::Named(name) if !types.contains(name.as_str()) => {
+            Err(pos.error(format!("unknown type `{name}`")))
+        }
+        Type::List(ty) | Type::Option(ty) => check_type(ty, types, pos),
+        Type::Map(a, b) | Type::Result(a, b) => {
+            check_type(a, types, pos)?;
+            check_type(b, types, pos)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check(file: &mut File) -> Result<(), Error> {
+    check_unique(
+        file.items.iter().map(|item| match item {
+            Item::Message(message) => &message.name,
+            Item::Enum(enumeration) => &enumeration.name,
+            Item::Commands(commands) => &commands.name,
+        }),
+        "type",
+    )?;
+    let types: BTreeSet<&str> = file
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Message(message) => Some(message.name.name.as_str()),
+            Item::Enum(enumeration) => Some(enumeration.name.name.as_str()),
+            Item::Commands(_) => None,
+        })
+        .collect();
+
+    for item in &file.items {
+        match item {
+            Item::Message(message) => {
+                for field in &message.fields {
+                    check_type(&field.ty, &types, field.name.pos)?;
+                }
+            }
+            Item::Enum(_) => {}
+            Item::Commands(commands) => {
+                for command in &commands.commands {
+                    check_type(&command.input, &types, command.name.pos)?;
+                    check_type(&command.output, &types, command.name.pos)?;
+                }
+            }
+        }
+    }
+
+    for item in &mut file.items {
+        match item {
+            Item::Message(message) => {
+                check_unique(message.fields.iter().map(|field| &field.name), "field")?;
+
+                if message.versioned {
+                    let mut tags: Vec<Option<u64>> = message
+                        .fields
+                        .iter()
+                        .map(|field| field.tag.map(u64::from))
+                        .collect();
+                    resolve_tags(
+                        message
+                            .fields
+                            .iter()
+                            .map(|field| &field.name)
+                            .zip(&mut tags),
+                        "tag",
+                        u32::MAX as u64,
+                    )?;
+                    for (field, tag) in message.fields.iter_mut().zip(tags) {
+                        field.tag = tag.map(|tag| tag as u32);
+                    }
+                } else if let Some(field) = message.fields.iter().find(|field| field.default) {
+                    return Err(field
+                        .name
+                        .pos
+                        .error("`default` fields are only allowed in versioned messages"));
+                }
+            }
+            Item::Enum(enumeration) => {
+                if enumeration.variants.is_empty() {
+                    return Err(enumeration
+                        .name
+                        .pos
+                        .error("enums must have at least one variant"));
+                }
+                check_unique(
+                    enumeration.variants.iter().map(|variant| &variant.name),
+                    "variant",
+                )?;
+                resolve_tags(
+                    enumeration
+                        .variants
+                        .iter_mut()
+                        .map(|variant| (&variant.name, &mut variant.tag)),
+                    "tag",
+                    u8::MAX as u64,
+                )?;
+            }
+            Item::Commands(commands) => {
+                check_unique(
+                    commands.commands.iter().map(|command| &command.name),
+                    "command",
+                )?;
+                if let Some(command) = commands
+                    .commands
+                    .iter()
+                    .find(|command| command.name.name == "Unknown")
+                {
+                    return Err(command
+                        .name
+                        .pos
+                        .error("`Unknown` is generated for unknown command ids"));
+                }
+                resolve_tags(
+                    commands
+                        .commands
+                        .iter_mut()
+                        .map(|command| (&command.name, &mut command.id)),
+                    "id",
+                    u8::MAX as u64,
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses `source` and checks that it describes a valid protocol.
+pub fn parse(source: &str) -> Result<File, Error> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        next: 0,
+    };
+    let mut file = parser.file()?;
+    check(&mut file)?;
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The error of parsing `source`, as displayed with its position.
+    fn error(source: &str) -> String {
+        parse(source).unwrap_err().to_string()
+    }
+
+    fn message(file: &File, index: usize) -> &Message {
+        match &file.items[index] {
+            Item::Message(message) => message,
+            item => panic!("expected a message, found {item:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_every_item() {
+        let file = parse(
+            "// a comment
+/// A message.
+message Message {
+    /// The text.
+    text: string;
+    varint count: u32;
+    sent_by: option<User>;
+    list: list<map<string, result<u8, Status>>>;
+}
+
+versioned message User {
+    id: uuid = 2;
+    name: string;
+    default nickname: option<string> = 7;
+}
+
+enum Status {
+    Online;
+    Away = 4;
+    Offline;
+}
+
+commands Server {
+    Login = 1: Message -> result<User, string>;
+    Logout;
+    Send = 5: list<Message> -> ();
+}
+",
+        )
+        .unwrap();
+        assert_eq!(file.items.len(), 4);
+
+        let plain = message(&file, 0);
+        assert_eq!(plain.docs, ["A message."]);
+        assert!(!plain.versioned);
+        assert_eq!(plain.fields[0].docs, ["The text."]);
+        assert_eq!(plain.fields[0].ty, Type::String);
+        assert!(plain.fields[1].varint);
+        assert_eq!(
+            plain.fields[2].ty,
+            Type::Option(Box::new(Type::Named("User".to_string())))
+        );
+        assert_eq!(
+            plain.fields[3].ty,
+            Type::List(Box::new(Type::Map(
+                Box::new(Type::String),
+                Box::new(Type::Result(
+                    Box::new(Type::U8),
+                    Box::new(Type::Named("Status".to_string()))
+                ))
+            )))
+        );
+        assert!(plain.fields.iter().all(|field| field.tag.is_none()));
+
+        // tags default to the previous one plus one
+        let versioned = message(&file, 1);
+        assert!(versioned.versioned);
+        let tags: Vec<_> = versioned.fields.iter().map(|field| field.tag).collect();
+        assert_eq!(tags, [Some(2), Some(3), Some(7)]);
+        assert!(versioned.fields[2].default);
+
+        let Item::Enum(status) = &file.items[2] else {
+            panic!("expected an enum");
+        };
+        let tags: Vec<_> = status.variants.iter().map(|variant| variant.tag).collect();
+        assert_eq!(tags, [Some(0), Some(4), Some(5)]);
+
+        let Item::Commands(server) = &file.items[3] else {
+            panic!("expected commands");
+        };
+        let ids: Vec<_> = server.commands.iter().map(|command| command.id).collect();
+        assert_eq!(ids, [Some(1), Some(2), Some(5)]);
+        assert_eq!(server.commands[1].input, Type::Unit);
+        assert_eq!(server.commands[1].output, Type::Unit);
+        assert_eq!(server.commands[2].output, Type::Unit);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(
+            error("message A {\n    a: u8\n}"),
+            "3:1: expected `;`, found `}`"
+        );
+        // unterminated block
+        assert_eq!(
+            error("message A {\n    a: u8;\n"),
+            "3:1: expected a name, found the end of the file"
+        );
+        assert_eq!(
+            error("message A {\n    a: u8 # 3;\n}"),
+            "2:11: unexpected character `#`"
+        );
+        assert_eq!(
+            error("struct A {}"),
+            "1:1: expected `message`, `versioned`, `enum` or `commands`, found `struct`"
+        );
+        assert_eq!(
+            error("versioned enum A {}"),
+            "1:11: expected `message`, found `enum`"
+        );
+        assert_eq!(
+            error("message A {\n    a: map<u8>;\n}"),
+            "2:14: expected `,`, found `>`"
+        );
+        assert_eq!(
+            error("message A {\n    a: u8 = 99999999999999999999;\n}"),
+            "2:13: `99999999999999999999` is too large"
+        );
+    }
+
+    #[test]
+    fn unknown_types() {
+        assert_eq!(
+            error("message A {\n    a: u8;\n    b: list<B>;\n}"),
+            "3:5: unknown type `B`"
+        );
+        assert_eq!(
+            error("message A {}\n\ncommands C {\n    Do: A -> Missing;\n}"),
+            "4:5: unknown type `Missing`"
+        );
+    }
+
+    #[test]
+    fn duplicates() {
+        assert_eq!(
+            error("message A {\n    a: u8;\n    a: u16;\n}"),
+            "3:5: field `a` is defined twice"
+        );
+        assert_eq!(
+            error("message A {}\nenum A {\n    B;\n}"),
+            "2:6: type `A` is defined twice"
+        );
+        assert_eq!(
+            error("versioned message A {\n    a: u8 = 1;\n    b: u8 = 1;\n}"),
+            "3:5: `b` has the same tag as `a`: 1"
+        );
+        // the implicit tag of `c` is the one of `a`
+        assert_eq!(
+            error("enum E {\n    A = 2;\n    B = 1;\n    C;\n}"),
+            "4:5: `C` has the same tag as `A`: 2"
+        );
+        assert_eq!(
+            error("commands C {\n    A = 3;\n    B = 3;\n}"),
+            "3:5: `B` has the same id as `A`: 3"
+        );
+    }
+
+    #[test]
+    fn invalid_items() {
+        assert_eq!(
+            error("message A {\n    a: u8 = 1;\n}"),
+            "2:13: field tags are only allowed in versioned messages"
+        );
+        assert_eq!(
+            error("message A {\n    default a: u8;\n}"),
+            "2:13: `default` fields are only allowed in versioned messages"
+        );
+        assert_eq!(
+            error("message A {\n    varint a: string;\n}"),
+            "2:15: `varint` fields must be integers of at most 64 bits"
+        );
+        assert_eq!(
+            error("message A {\n    a: ();\n}"),
+            "2:8: `()` is only allowed as the input or output of a command"
+        );
+        assert_eq!(
+            error("message A {\n    a: option<()>;\n}"),
+            "2:15: `()` is only allowed as the input or output of a command"
+        );
+        assert_eq!(
+            error("enum E {}"),
+            "1:6: enums must have at least one variant"
+        );
+        assert_eq!(
+            error("enum E {\n    A = 256;\n}"),
+            "2:5: the tag of `A` must be at most 255"
+        );
+        assert_eq!(
+            error("commands C {\n    Unknown;\n}"),
+            "2:5: `Unknown` is generated for unknown command ids"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use syn::{parse_quote, Attribute, Field, Ident, LitInt, LitStr, Path, Type, Variant};
+
+/// Options set on a field with `#[payload(...)]`.
+#[derive(Default)]
+pub struct FieldAttrs {
+    /// `#[payload(skip)]`: the field is not sent and is filled with `Default::default()`.
+    pub skip: bool,
+    /// `#[payload(default)]`: the field is filled with `Default::default()` when it wasn't sent,
+    /// so that it can be added to a struct already used by deployed peers. Outside of
+    /// `#[payload(versioned)]` structs, only trailing fields of the outermost value can be missing.
+    pub default: bool,
+    /// `#[payload(with = "module")]`: the field is handled by the functions of `module` instead of
+    /// its `Payload` impl.
+    pub with: Option<Path>,
+    /// `#[payload(varint)]`: the integer field is encoded as a varint, which is implemented as
+    /// `with = "example_core::varint"`.
+    pub varint: bool,
+    /// `#[payload(len = "u32")]`: the type prefixing the length of a `String`, `Vec` or `Bytes`.
+    pub len: Option<Path>,
+    /// `#[payload(tag = N)]`: the tag identifying the field in a `#[payload(versioned)]` struct.
+    pub tag: Option<LitInt>,
+}
+
+const LENGTH_PREFIXES: &[&str] = &["u8", "u16", "u32", "u64", "varint"];
+
+impl FieldAttrs {
+    pub fn parse(field: &Field) -> syn::Result<FieldAttrs> {
+        let mut attrs = FieldAttrs::default();
+
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("payload")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    attrs.skip = true;
+                } else if meta.path.is_ident("default") {
+                    attrs.default = true;
+                } else if meta.path.is_ident("with") {
+                    attrs.with = Some(meta.value()?.parse::<LitStr>()?.parse::<Path>()?);
+                } else if meta.path.is_ident("varint") {
+                    attrs.varint = true;
+                } else if meta.path.is_ident("tag") {
+                    attrs.tag = Some(meta.value()?.parse::<LitInt>()?);
+                } else if meta.path.is_ident("len") {
+                    let lit = meta.value()?.parse::<LitStr>()?;
+                    attrs.len =
+                        match lit.value().as_str() {
+                            "varint" => Some(parse_quote! { example_core::VarInt }),
+                            value if LENGTH_PREFIXES.contains(&value) => {
+                                Some(lit.parse::<Ident>()?.into())
+                            }
+                            _ => return Err(syn::Error::new_spanned(
+                                lit,
+                                "expected one of \"u8\", \"u16\", \"u32\", \"u64\" or \"varint\"",
+                            )),
+                        };
+                } else {
+                    return Err(meta.error("unsupported payload attribute on field"));
+                }
+
+                Ok(())
+            })?;
+        }
+
+        if attrs.skip
+            && (attrs.default
+                || attrs.varint
+                || attrs.with.is_some()
+                || attrs.len.is_some()
+                || attrs.tag.is_some())
+        {
+            return Err(syn::Error::new_spanned(
+                field,
+                "`skip` cannot be combined with other payload attributes",
+            ));
+        }
+        if [attrs.varint, attrs.with.is_some(), attrs.len.is_some()]
+            .iter()
+            .filter(|set| **set)
+            .count()
+            > 1
+        {
+            return Err(syn::Error::new_spanned(
+                field,
+                "only one of `with`, `varint` and `len` can be used on a field",
+            ));
+        }
+        if attrs.varint {
+            attrs.with = Some(parse_quote! { example_core::varint });
+        }
+
+        Ok(attrs)
+    }
+}
+
+/// Options set on a struct with `#[payload(...)]`.
+#[derive(Default)]
+pub struct ContainerAttrs {
+    /// `#[payload(versioned)]`: the fields are tagged so that they can be added and removed
+    /// without breaking peers using another version of the struct.
+    pub versioned: bool,
+}
+
+impl ContainerAttrs {
+    pub fn parse(attrs: &[Attribute]) -> syn::Result<ContainerAttrs> {
+        let mut container_attrs = ContainerAttrs::default();
+
+        for attr in attrs.iter().filter(|a| a.path().is_ident("payload")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("versioned") {
+                    container_attrs.versioned = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported payload attribute on type"))
+                }
+            })?;
+        }
+
+        Ok(container_attrs)
+    }
+}
+
+/// Reads the value of `#[payload(tag = N)]` from a variant, if present.
+pub fn parse_variant_tag_attr(variant: &Variant) -> syn::Result<Option<LitInt>> {
+    let mut tag = None;
+
+    for attr in variant
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("payload"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("tag") {
+                tag = Some(meta.value()?.parse::<LitInt>()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported payload attribute on enum variant"))
+            }
+        })?;
+    }
+
+    Ok(tag)
+}
+
+/// Options set on a variant of an enum deriving `Command` with `#[command(...)]`.
+#[derive(Default)]
+pub struct CommandAttrs {
+    /// `#[command(id = N)]`: the id of the command, sent as its `u8` discriminant.
+    pub id: Option<LitInt>,
+    /// `#[command(input = T)]`: the type sent after the id, `()` by default.
+    pub input: Option<Type>,
+    /// `#[command(output = T)]`: the type sent back by the peer handling the command, `()` by
+    /// default.
+    pub output: Option<Type>,
+    /// `#[command(unknown)]`: the `Unknown(u8)` variant holding the ids matching no command.
+    pub unknown: bool,
+}
+
+impl CommandAttrs {
+    pub fn parse(variant: &Variant) -> syn::Result<CommandAttrs> {
+        let mut attrs = CommandAttrs::default();
+
+        for attr in variant
+            .attrs
+            .iter()
+            .filter(|a| a.path().is_ident("command"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("id") {
+                    attrs.id = Some(meta.value()?.parse::<LitInt>()?);
+                } else if meta.path.is_ident("input") {
+                    attrs.input = Some(meta.value()?.parse::<Type>()?);
+                } else if meta.path.is_ident("output") {
+                    attrs.output = Some(meta.value()?.parse::<Type>()?);
+                } else if meta.path.is_ident("unknown") {
+                    attrs.unknown = true;
+                } else {
+                    return Err(meta.error("unsupported command attribute on enum variant"));
+                }
+
+                Ok(())
+            })?;
+        }
+
+        if attrs.unknown && (attrs.id.is_some() || attrs.input.is_some() || attrs.output.is_some())
+        {
+            return Err(syn::Error::new_spanned(
+                variant,
+                "`unknown` cannot be combined with other command attributes",
+            ));
+        }
+
+        Ok(attrs)
+    }
+}
@@ -0,0 +1,214 @@
+//! The `Command` derive, see [crate::derive_command].
+
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Fields, Type};
+
+use crate::attrs::CommandAttrs;
+use crate::resolve_variant_tags;
+
+/// Converts a type name like `ServerCommand` to a module name like `server_command`.
+pub(crate) fn to_snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::with_capacity(name.len() + 4);
+
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            // `HttpServer` and `HTTPServer` both give `http_server`
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_is_lower)
+            {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+
+    snake
+}
+
+pub fn expand_command(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let DeriveInput {
+        vis,
+        ident,
+        generics,
+        data,
+        ..
+    } = input;
+
+    let Data::Enum(data) = data else {
+        return Err(syn::Error::new_spanned(
+            ident,
+            "Command can only be derived for enums",
+        ));
+    };
+    if !generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            generics,
+            "Command cannot be derived for generic enums",
+        ));
+    }
+
+    let mut commands = vec![];
+    let mut unknown = None;
+    for variant in &data.variants {
+        let attrs = CommandAttrs::parse(variant)?;
+
+        if attrs.unknown {
+            let holds_u8 = match &variant.fields {
+                Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                    matches!(&fields.unnamed[0].ty, Type::Path(ty) if ty.path.is_ident("u8"))
+                }
+                _ => false,
+            };
+            if !holds_u8 {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "the `unknown` variant must hold the id as a single `u8`, like `Unknown(u8)`",
+                ));
+            }
+            if unknown.replace(&variant.ident).is_some() {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "only one variant can be `unknown`",
+                ));
+            }
+        } else if !variant.fields.is_empty() {
+            return Err(syn::Error::new_spanned(
+                &variant.fields,
+                "commands cannot have fields, their input is declared with `#[command(input = T)]`",
+            ));
+        } else {
+            commands.push((variant, attrs));
+        }
+    }
+    let Some(unknown) = unknown else {
+        return Err(syn::Error::new_spanned(
+            ident,
+            "Command enums need a `#[command(unknown)] Unknown(u8)` variant holding unknown ids",
+        ));
+    };
+
+    let ids = resolve_variant_tags(
+        commands.iter().map(|(variant, _)| *variant),
+        |variant| CommandAttrs::parse(variant).map(|attrs| attrs.id),
+        "command id",
+    )?;
+    let variants: Vec<_> = commands.iter().map(|(variant, _)| &variant.ident).collect();
+    let names: Vec<String> = variants.iter().map(|variant| variant.to_string()).collect();
+    let unit = syn::parse_quote! { () };
+    let inputs: Vec<&Type> = commands
+        .iter()
+        .map(|(_, attrs)| attrs.input.as_ref().unwrap_or(&unit))
+        .collect();
+    let outputs: Vec<&Type> = commands
+        .iter()
+        .map(|(_, attrs)| attrs.output.as_ref().unwrap_or(&unit))
+        .collect();
+
+    let module = format_ident!("{}", to_snake_case(&ident.to_string()));
+    let module_doc = format!("The commands of [`{ident}`], see `example_core::Command`.");
+    let marker_docs: Vec<String> = variants
+        .iter()
+        .map(|variant| format!("The [`{ident}::{variant}`](super::{ident}::{variant}) command."))
+        .collect();
+
+    // `value` is a reference to the enum
+    let id_of_value = quote! {
+        match value {
+            #(#ident::#variants => #ids,)*
+            #ident::#unknown(id) => *id,
+        }
+    };
+
+    Ok(quote! {
+        impl From<u8> for #ident {
+            fn from(id: u8) -> #ident {
+                match id {
+                    #(#ids => #ident::#variants,)*
+                    id => #ident::#unknown(id),
+                }
+            }
+        }
+
+        impl From<#ident> for u8 {
+            fn from(command: #ident) -> u8 {
+                let value = &command;
+
+                #id_of_value
+            }
+        }
+
+        impl example_core::Encode for #ident {
+            fn encoded_len(&self) -> usize {
+                1
+            }
+
+            fn encode(
+                &self,
+                buf: &mut example_core::BytesMut,
+            ) -> Result<(), example_core::PayloadError> {
+                let value = self;
+                let id: u8 = #id_of_value;
+
+                example_core::Encode::encode(&id, buf)
+            }
+        }
+
+        impl example_core::Payload for #ident {
+            fn decode_with(
+                buf: &mut example_core::Bytes,
+                ctx: &mut example_core::DecodeContext,
+            ) -> Result<Self, example_core::PayloadError> {
+                Ok(Self::from(<u8 as example_core::Payload>::decode_with(buf, ctx)?))
+            }
+
+            #[must_use]
+            async fn read_with<R>(
+                recv: &mut R,
+                ctx: &mut example_core::DecodeContext,
+            ) -> Result<Self, example_core::PayloadError>
+            where
+                R: example_core::AsyncRead + Unpin + Send + ?Sized,
+            {
+                Ok(Self::from(<u8 as example_core::Payload>::read_with(recv, ctx).await?))
+            }
+
+            fn schema(
+                defs: &mut example_core::schema::Definitions,
+            ) -> example_core::schema::Schema {
+                defs.define::<Self>(|_| example_core::schema::Definition::Enum {
+                    variants: vec![#(
+                        example_core::schema::VariantSchema {
+                            name: ::std::string::String::from(#names),
+                            tag: #ids,
+                            fields: vec![],
+                        }
+                    ),*],
+                })
+            }
+        }
+
+        #[doc = #module_doc]
+        #[allow(dead_code)]
+        #vis mod #module {
+            #(
+                #[doc = #marker_docs]
+                #[derive(Copy, Clone, Debug)]
+                pub struct #variants;
+            )*
+        }
+
+        #(
+            impl example_core::Command for #module::#variants {
+                type Kind = #ident;
+                const KIND: #ident = #ident::#variants;
+
+                type Input = #inputs;
+                type Output = #outputs;
+            }
+        )*
+    })
+}
@@ -1,71 +1,910 @@
+mod attrs;
+mod command;
+mod service;
+
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote, ToTokens};
 use syn::Data::Struct;
-use syn::{parse_macro_input, Data, DeriveInput, Fields};
+use syn::{
+    parse_macro_input, parse_quote, Data, DataEnum, DeriveInput, Expr, ExprLit, Field, Fields,
+    Generics, Index, Lit, LitInt, Member, Variant,
+};
 
-fn generate_read_from_recv_code(fields: &Fields) -> proc_macro2::TokenStream {
-    let field_code = fields.iter().map(|field| {
-        let field_name = field.ident.as_ref().unwrap();
-        let field_type = &field.ty;
+use crate::attrs::{parse_variant_tag_attr, ContainerAttrs, FieldAttrs};
+
+/// Builds an expression reading a single field from `recv`, where `name` is the field prefixed by
+/// its type for error messages.
+fn generate_field_read(field: &Field, attrs: &FieldAttrs, name: &str) -> proc_macro2::TokenStream {
+    let field_type = &field.ty;
+
+    if attrs.skip {
+        return quote! { ::core::default::Default::default() };
+    }
 
-        quote! {
-            #field_name: <#field_type as example_core::Payload>::read_from_recv_stream(recv).await?,
+    let read = match (&attrs.with, &attrs.len) {
+        (Some(with), _) => quote! { #with::read_with(recv, ctx).await },
+        (None, Some(len)) => quote! {
+            <#field_type as example_core::LengthPrefixed>::read_with_prefix::<#len, _>(recv, ctx).await
+        },
+        (None, None) => {
+            quote! { <#field_type as example_core::Payload>::read_with(recv, ctx).await }
         }
-    });
+    };
+
+    if attrs.default {
+        // the bytes of the field are counted to tell a missing field from a truncated one
+        return quote! {
+            {
+                let mut __counted = example_core::CountingRead::new(&mut *recv);
+                let result = {
+                    let recv = &mut __counted;
+                    #read
+                };
+                example_core::default_if_missing(result, __counted.count(), ctx)
+                    .map_err(|e| e.in_field(#name))?
+            }
+        };
+    }
+
+    quote! { #read.map_err(|e| e.in_field(#name))? }
+}
+
+/// Builds an expression decoding a single field from `buf`, where `name` is the field prefixed by
+/// its type for error messages.
+fn generate_field_decode(
+    field: &Field,
+    attrs: &FieldAttrs,
+    name: &str,
+) -> proc_macro2::TokenStream {
+    if attrs.skip {
+        return quote! { ::core::default::Default::default() };
+    }
+
+    let decode = generate_field_decode_value(field, attrs);
+
+    if attrs.default {
+        return quote! {
+            {
+                let available = buf.len();
+                example_core::default_if_missing(#decode, available, ctx)
+                    .map_err(|e| e.in_field(#name))?
+            }
+        };
+    }
+
+    quote! { #decode.map_err(|e| e.in_field(#name))? }
+}
+
+/// Builds an expression decoding the value of a single field from `buf` into a `Result`.
+fn generate_field_decode_value(field: &Field, attrs: &FieldAttrs) -> proc_macro2::TokenStream {
+    let field_type = &field.ty;
 
-    quote! {
-        #(#field_code)*
+    match (&attrs.with, &attrs.len) {
+        (Some(with), _) => quote! { #with::decode_with(buf, ctx) },
+        (None, Some(len)) => quote! {
+            <#field_type as example_core::LengthPrefixed>::decode_with_prefix::<#len>(buf, ctx)
+        },
+        (None, None) => quote! { <#field_type as example_core::Payload>::decode_with(buf, ctx) },
     }
 }
 
-fn generate_write_to_send_code(fields: &Fields) -> proc_macro2::TokenStream {
-    let field_code = fields.iter().map(|field| {
-        let field_name = field.ident.as_ref().unwrap();
+/// Builds an expression computing the encoded length of a single field, `value` being a
+/// reference to the field.
+fn generate_field_encoded_len(
+    field: &Field,
+    attrs: &FieldAttrs,
+    value: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let field_type = &field.ty;
 
-        quote! {
-            self.#field_name.write_to_send_stream(send).await?;
+    match (attrs.skip, &attrs.with, &attrs.len) {
+        (true, _, _) => quote! { 0 },
+        (false, Some(with), _) => quote! { #with::encoded_len(#value) },
+        (false, None, Some(len)) => quote! {
+            <#field_type as example_core::LengthPrefixed>::encoded_len_with_prefix::<#len>(#value)
+        },
+        (false, None, None) => quote! { example_core::Encode::encoded_len(#value) },
+    }
+}
+
+/// Builds a statement encoding a single field into `buf`, `value` being a reference to the field.
+fn generate_field_encode(
+    field: &Field,
+    attrs: &FieldAttrs,
+    value: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let field_type = &field.ty;
+
+    match (attrs.skip, &attrs.with, &attrs.len) {
+        (true, _, _) => quote! {},
+        (false, Some(with), _) => quote! { #with::encode(#value, buf)?; },
+        (false, None, Some(len)) => quote! {
+            <#field_type as example_core::LengthPrefixed>::encode_with_prefix::<#len>(#value, buf)?;
+        },
+        (false, None, None) => quote! { example_core::Encode::encode(#value, buf)?; },
+    }
+}
+
+/// Builds an expression describing the encoding of a single field as an
+/// `example_core::schema::Schema`.
+fn generate_field_schema(field: &Field, attrs: &FieldAttrs) -> proc_macro2::TokenStream {
+    let field_type = &field.ty;
+
+    match (attrs.varint, &attrs.with, &attrs.len) {
+        (true, _, _) => quote! { example_core::varint::schema::<#field_type>() },
+        (false, Some(with), _) => {
+            let module = with.to_token_stream().to_string().replace(' ', "");
+            quote! {
+                example_core::schema::Schema::With {
+                    module: ::std::string::String::from(#module),
+                }
+            }
+        }
+        (false, None, Some(len)) => quote! {
+            <#field_type as example_core::Payload>::schema(defs)
+                .with_length(<#len as example_core::LengthPrefix>::ENCODING)
+        },
+        (false, None, None) => quote! { <#field_type as example_core::Payload>::schema(defs) },
+    }
+}
+
+/// Builds an `example_core::schema::FieldSchema` expression for every field that is sent, given
+/// the tag of each field of a versioned struct.
+fn generate_field_schemas(
+    fields: &Fields,
+    attrs: &[FieldAttrs],
+    tags: Option<&[Option<u32>]>,
+) -> Vec<proc_macro2::TokenStream> {
+    fields
+        .iter()
+        .zip(attrs)
+        .enumerate()
+        .filter(|(_, (_, attrs))| !attrs.skip)
+        .map(|(i, (field, attrs))| {
+            let name = match &field.ident {
+                Some(ident) => ident.to_string(),
+                None => i.to_string(),
+            };
+            let tag = match tags.and_then(|tags| tags[i]) {
+                Some(tag) => quote! { Some(#tag) },
+                None => quote! { None },
+            };
+            let default = attrs.default;
+            let schema = generate_field_schema(field, attrs);
+
+            quote! {
+                example_core::schema::FieldSchema {
+                    name: ::std::string::String::from(#name),
+                    tag: #tag,
+                    default: #default,
+                    schema: #schema,
+                }
+            }
+        })
+        .collect()
+}
+
+/// Parses the `#[payload(...)]` attributes of every field.
+///
+/// Unless the fields are `versioned`, fields marked `default` must be the last ones sent, since
+/// only the end of the data tells that they weren't sent.
+fn parse_field_attrs(fields: &Fields, versioned: bool) -> syn::Result<Vec<FieldAttrs>> {
+    let mut all_attrs: Vec<FieldAttrs> = vec![];
+    let mut after_default = false;
+
+    for field in fields.iter() {
+        let attrs = FieldAttrs::parse(field)?;
+
+        if let (false, Some(tag)) = (versioned, &attrs.tag) {
+            return Err(syn::Error::new_spanned(
+                tag,
+                "field tags are only allowed in `#[payload(versioned)]` structs",
+            ));
+        }
+        if !versioned && after_default && !attrs.default && !attrs.skip {
+            return Err(syn::Error::new_spanned(
+                field,
+                "fields following a `default` field must be `default` unless the struct is `#[payload(versioned)]`",
+            ));
         }
-    });
+        after_default |= attrs.default;
 
-    quote! {
-        #(#field_code)*
+        all_attrs.push(attrs);
     }
+
+    Ok(all_attrs)
 }
 
-#[proc_macro_derive(Payload)]
-pub fn derive_payload(input: TokenStream) -> TokenStream {
-    let DeriveInput { ident, data, .. } = parse_macro_input!(input as DeriveInput);
+/// Code generated for the fields of a struct or of an enum variant.
+struct FieldsCode {
+    /// Expression reading the fields from `recv` and constructing the value.
+    read: proc_macro2::TokenStream,
+    /// Expression decoding the fields from `buf` and constructing the value.
+    decode: proc_macro2::TokenStream,
+    /// Expression computing the encoded length of the fields.
+    encoded_len: proc_macro2::TokenStream,
+    /// Statements encoding the fields into `buf`.
+    encode: proc_macro2::TokenStream,
+    /// Expression building the `Vec<FieldSchema>` of the fields.
+    schema: proc_macro2::TokenStream,
+}
+
+/// Builds an expression constructing `path` from an expression for each of its `fields`.
+fn generate_construct(
+    path: &proc_macro2::TokenStream,
+    fields: &Fields,
+    field_values: Vec<proc_macro2::TokenStream>,
+) -> proc_macro2::TokenStream {
+    match fields {
+        Fields::Named(_) => {
+            let names = fields.iter().map(|f| f.ident.as_ref().unwrap());
+            quote! { #path { #(#names: #field_values,)* } }
+        }
+        Fields::Unnamed(_) => quote! { #path(#(#field_values,)*) },
+        Fields::Unit => quote! { #path },
+    }
+}
+
+/// Names every field as `name.field`, or `name.index` for tuple fields, for error messages.
+fn generate_field_names(name: &str, fields: &Fields) -> Vec<String> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match &field.ident {
+            Some(ident) => format!("{name}.{ident}"),
+            None => format!("{name}.{i}"),
+        })
+        .collect()
+}
+
+/// Builds a `&self.field` expression for every field of a struct.
+fn generate_self_values(fields: &Fields) -> Vec<proc_macro2::TokenStream> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let member = match &field.ident {
+                Some(ident) => Member::Named(ident.clone()),
+                None => Member::Unnamed(Index::from(i)),
+            };
+
+            quote! { &self.#member }
+        })
+        .collect()
+}
+
+/// Generates the code handling `fields`, where `path` is either `Self` or `Self::Variant`, `name`
+/// is the matching `Type` or `Type::Variant` used in error messages and `values` holds an
+/// expression referencing each field.
+fn generate_fields_code(
+    path: proc_macro2::TokenStream,
+    name: &str,
+    fields: &Fields,
+    attrs: &[FieldAttrs],
+    values: &[proc_macro2::TokenStream],
+) -> FieldsCode {
+    let field_names = generate_field_names(name, fields);
+
+    let read = generate_construct(
+        &path,
+        fields,
+        fields
+            .iter()
+            .zip(attrs)
+            .zip(&field_names)
+            .map(|((field, attrs), name)| generate_field_read(field, attrs, name))
+            .collect(),
+    );
+    let decode = generate_construct(
+        &path,
+        fields,
+        fields
+            .iter()
+            .zip(attrs)
+            .zip(&field_names)
+            .map(|((field, attrs), name)| generate_field_decode(field, attrs, name))
+            .collect(),
+    );
+
+    let lens = fields
+        .iter()
+        .zip(attrs)
+        .zip(values)
+        .map(|((field, attrs), value)| generate_field_encoded_len(field, attrs, value));
+    let encodes = fields
+        .iter()
+        .zip(attrs)
+        .zip(values)
+        .map(|((field, attrs), value)| generate_field_encode(field, attrs, value));
+
+    let schemas = generate_field_schemas(fields, attrs, None);
+
+    FieldsCode {
+        read,
+        decode,
+        encoded_len: quote! { 0 #(+ #lens)* },
+        encode: quote! { #(#encodes)* },
+        schema: quote! { vec![#(#schemas),*] },
+    }
+}
+
+/// Adds a `Payload + Send + Sync` bound to every type parameter.
+fn add_payload_bounds(mut generics: Generics) -> Generics {
+    let type_params: Vec<proc_macro2::Ident> =
+        generics.type_params().map(|p| p.ident.clone()).collect();
+
+    let where_clause = generics.make_where_clause();
+    for param in type_params {
+        where_clause
+            .predicates
+            .push(parse_quote! { #param: example_core::Payload + Send + Sync });
+    }
+
+    generics
+}
+
+/// Computes the wire discriminant of every variant.
+///
+/// The discriminant is taken from `#[payload(tag = N)]`, then from an explicit `Variant = N`,
+/// otherwise it is the previous discriminant plus one (starting at 0), just like rustc does.
+fn resolve_enum_tags(data: &DataEnum) -> syn::Result<Vec<u8>> {
+    resolve_variant_tags(
+        data.variants.iter(),
+        parse_variant_tag_attr,
+        "Payload discriminant",
+    )
+}
+
+/// Computes the `u8` tag of every variant, `explicit` reading the tag set by an attribute.
+///
+/// The tag is taken from `explicit`, then from an explicit `Variant = N`, otherwise it is the
+/// previous tag plus one (starting at 0). `what` names a tag in errors.
+fn resolve_variant_tags<'a>(
+    variants: impl IntoIterator<Item = &'a Variant>,
+    explicit: impl Fn(&Variant) -> syn::Result<Option<LitInt>>,
+    what: &str,
+) -> syn::Result<Vec<u8>> {
+    let mut tags: Vec<u8> = vec![];
+    let mut next: u16 = 0;
+
+    for variant in variants {
+        let explicit = match explicit(variant)? {
+            Some(lit) => Some(lit),
+            None => match &variant.discriminant {
+                Some((
+                    _,
+                    Expr::Lit(ExprLit {
+                        lit: Lit::Int(lit), ..
+                    }),
+                )) => Some(lit.clone()),
+                Some((_, expr)) => {
+                    return Err(syn::Error::new_spanned(
+                        expr,
+                        format!("{what}s must be integer literals"),
+                    ))
+                }
+                None => None,
+            },
+        };
+
+        let tag: u8 = match explicit {
+            Some(lit) => lit
+                .base10_parse::<u8>()
+                .map_err(|_| syn::Error::new_spanned(&lit, format!("{what}s must fit in a u8")))?,
+            None => u8::try_from(next).map_err(|_| {
+                syn::Error::new_spanned(&variant.ident, format!("{what}s must fit in a u8"))
+            })?,
+        };
+
+        if tags.contains(&tag) {
+            return Err(syn::Error::new_spanned(
+                &variant.ident,
+                format!("duplicate {what} {tag}"),
+            ));
+        }
+
+        tags.push(tag);
+        next = u16::from(tag) + 1;
+    }
+
+    Ok(tags)
+}
+
+/// The bodies of the generated `Payload` methods.
+struct PayloadCode {
+    read: proc_macro2::TokenStream,
+    decode: proc_macro2::TokenStream,
+    encoded_len: proc_macro2::TokenStream,
+    encode: proc_macro2::TokenStream,
+    /// Expression building the `example_core::schema::Definition` of the type.
+    schema: proc_macro2::TokenStream,
+}
+
+fn generate_struct_code(ident: &proc_macro2::Ident, fields: &Fields) -> syn::Result<PayloadCode> {
+    let attrs = parse_field_attrs(fields, false)?;
+    let values = generate_self_values(fields);
+
+    let FieldsCode {
+        read,
+        decode,
+        encoded_len,
+        encode,
+        schema,
+    } = generate_fields_code(quote! { Self }, &ident.to_string(), fields, &attrs, &values);
 
-    let read_from_recv_code = match &data {
-        Struct(data) => generate_read_from_recv_code(&data.fields),
-        Data::Enum(_) => quote! { compile_error!("enum") },
-        Data::Union(_) => quote! { compile_error!("union") },
+    Ok(PayloadCode {
+        read: quote! { Ok(#read) },
+        decode: quote! { Ok(#decode) },
+        encoded_len,
+        encode: quote! {
+            #encode
+
+            Ok(())
+        },
+        schema: quote! { example_core::schema::Definition::Struct { fields: #schema } },
+    })
+}
+
+/// Computes the tag of every field of a versioned struct, `None` for skipped fields.
+///
+/// The tag is taken from `#[payload(tag = N)]`, otherwise it is the previous tag plus one (starting
+/// at 0), like enum discriminants.
+fn resolve_field_tags(fields: &Fields, attrs: &[FieldAttrs]) -> syn::Result<Vec<Option<u32>>> {
+    let mut tags: Vec<Option<u32>> = vec![];
+    let mut next: u64 = 0;
+
+    for (field, attrs) in fields.iter().zip(attrs) {
+        if attrs.skip {
+            tags.push(None);
+            continue;
+        }
+
+        let tag: u32 = match &attrs.tag {
+            Some(lit) => lit.base10_parse::<u32>().map_err(|_| {
+                syn::Error::new_spanned(lit, "Payload field tags must fit in a u32")
+            })?,
+            None => u32::try_from(next).map_err(|_| {
+                syn::Error::new_spanned(field, "Payload field tags must fit in a u32")
+            })?,
+        };
+
+        if tags.contains(&Some(tag)) {
+            return Err(syn::Error::new_spanned(
+                field,
+                format!("duplicate Payload field tag {tag}"),
+            ));
+        }
+
+        tags.push(Some(tag));
+        next = u64::from(tag) + 1;
+    }
+
+    Ok(tags)
+}
+
+/// Generates the code of a `#[payload(versioned)]` struct, see `example_core::versioned`.
+///
+/// Fields are decoded into an `Option` each while walking the body, then fields that weren't sent
+/// are filled with `Default::default()` if marked `default`, or fail decoding otherwise.
+fn generate_versioned_struct_code(
+    ident: &proc_macro2::Ident,
+    fields: &Fields,
+) -> syn::Result<PayloadCode> {
+    let attrs = parse_field_attrs(fields, true)?;
+    let tags = resolve_field_tags(fields, &attrs)?;
+    let values = generate_self_values(fields);
+    let field_names = generate_field_names(&ident.to_string(), fields);
+    let schemas = generate_field_schemas(fields, &attrs, Some(&tags));
+
+    let mut lens = vec![];
+    let mut encodes = vec![];
+    let mut slots = vec![];
+    let mut decode_arms = vec![];
+    let mut field_values = vec![];
+    for (i, (((field, attrs), tag), (value, name))) in fields
+        .iter()
+        .zip(&attrs)
+        .zip(tags)
+        .zip(values.iter().zip(&field_names))
+        .enumerate()
+    {
+        let Some(tag) = tag else {
+            field_values.push(quote! { ::core::default::Default::default() });
+            continue;
+        };
+        let tag = proc_macro2::Literal::u32_unsuffixed(tag);
+        let field_type = &field.ty;
+        let slot = format_ident!("__field{}", i);
+
+        let len = generate_field_encoded_len(field, attrs, value);
+        let encode = generate_field_encode(field, attrs, value);
+        lens.push(quote! { example_core::versioned::field_len(#tag, #len) });
+        encodes.push(quote! {
+            example_core::versioned::encode_field_header(#tag, #len, buf)?;
+            #encode
+        });
+
+        let decode = generate_field_decode_value(field, attrs);
+        slots.push(quote! { let mut #slot: Option<#field_type> = None; });
+        decode_arms.push(quote! {
+            #tag => #slot = Some(#decode.map_err(|e| e.in_field(#name))?),
+        });
+
+        let missing = if attrs.default {
+            quote! { ::core::default::Default::default() }
+        } else {
+            quote! { return Err(example_core::PayloadError::MissingField.in_field(#name)) }
+        };
+        field_values.push(quote! {
+            match #slot {
+                Some(value) => value,
+                None => #missing,
+            }
+        });
+    }
+
+    let construct = generate_construct(&quote! { Self }, fields, field_values);
+    let decode_fields = quote! {
+        #(#slots)*
+        while !body.is_empty() {
+            let (tag, mut value) = example_core::versioned::decode_field(&mut body, ctx)?;
+            let buf = &mut value;
+
+            match tag {
+                #(#decode_arms)*
+                // fields added by a newer version of the struct
+                _ => {}
+            }
+        }
+
+        Ok(#construct)
     };
 
-    let write_to_send_code = match &data {
-        Struct(data) => generate_write_to_send_code(&data.fields),
-        Data::Enum(_) => quote! { compile_error!("enum") },
-        Data::Union(_) => quote! { compile_error!("union") },
+    Ok(PayloadCode {
+        read: quote! {
+            let mut body = example_core::versioned::read_body(recv, ctx).await?;
+            #decode_fields
+        },
+        decode: quote! {
+            let mut body = example_core::versioned::decode_body(buf, ctx)?;
+            #decode_fields
+        },
+        encoded_len: quote! { example_core::versioned::encoded_len(0 #(+ #lens)*) },
+        encode: quote! {
+            example_core::versioned::encode_body_len(0 #(+ #lens)*, buf)?;
+            #(#encodes)*
+
+            Ok(())
+        },
+        schema: quote! {
+            example_core::schema::Definition::VersionedStruct {
+                fields: vec![#(#schemas),*],
+            }
+        },
+    })
+}
+
+fn generate_enum_code(ident: &proc_macro2::Ident, data: &DataEnum) -> syn::Result<PayloadCode> {
+    let tags = resolve_enum_tags(data)?;
+
+    let mut read_arms = vec![];
+    let mut decode_arms = vec![];
+    let mut encoded_len_arms = vec![];
+    let mut encode_arms = vec![];
+    let mut variant_schemas = vec![];
+    for (variant, tag) in data.variants.iter().zip(tags) {
+        let variant_name = &variant.ident;
+        let attrs = parse_field_attrs(&variant.fields, false)?;
+
+        // fields are bound to generated names, so that a field named like a parameter of the
+        // generated methods (`buf`, `recv`, `ctx`...) doesn't shadow it
+        let values: Vec<proc_macro2::TokenStream> = (0..variant.fields.len())
+            .map(|i| format_ident!("__field{}", i).to_token_stream())
+            .collect();
+        // skipped fields are matched with `_` so that they don't produce unused bindings
+        let bindings =
+            variant
+                .fields
+                .iter()
+                .zip(&attrs)
+                .zip(&values)
+                .map(|((field, attrs), value)| match (&field.ident, attrs.skip) {
+                    (Some(ident), true) => quote! { #ident: _ },
+                    (Some(ident), false) => quote! { #ident: #value },
+                    (None, true) => quote! { _ },
+                    (None, false) => value.clone(),
+                });
+        let pattern = match &variant.fields {
+            Fields::Named(_) => quote! { Self::#variant_name { #(#bindings,)* } },
+            Fields::Unnamed(_) => quote! { Self::#variant_name(#(#bindings,)*) },
+            Fields::Unit => quote! { Self::#variant_name },
+        };
+
+        let FieldsCode {
+            read,
+            decode,
+            encoded_len,
+            encode,
+            schema,
+        } = generate_fields_code(
+            quote! { Self::#variant_name },
+            &format!("{ident}::{variant_name}"),
+            &variant.fields,
+            &attrs,
+            &values,
+        );
+
+        read_arms.push(quote! { #tag => Ok(#read), });
+        decode_arms.push(quote! { #tag => Ok(#decode), });
+        encoded_len_arms.push(quote! { #pattern => 1 + #encoded_len, });
+        encode_arms.push(quote! {
+            #pattern => {
+                example_core::Encode::encode(&#tag, buf)?;
+                #encode
+            }
+        });
+        let variant_name = variant_name.to_string();
+        variant_schemas.push(quote! {
+            example_core::schema::VariantSchema {
+                name: ::std::string::String::from(#variant_name),
+                tag: #tag,
+                fields: #schema,
+            }
+        });
+    }
+
+    let unknown = quote! {
+        unknown => Err(example_core::PayloadError::UnknownDiscriminant {
+            ty: stringify!(#ident),
+            value: unknown,
+        }),
     };
 
-    let expanded = quote! {
-        #[async_trait::async_trait]
-        impl example_core::Payload for #ident {
-            #[must_use]
-            async fn read_from_recv_stream(recv: &mut quinn::RecvStream) -> anyhow::Result<#ident> {
-                Ok(#ident {
-                    #read_from_recv_code
-                })
+    Ok(PayloadCode {
+        read: quote! {
+            let tag = <u8 as example_core::Payload>::read_with(recv, ctx).await?;
+
+            match tag {
+                #(#read_arms)*
+                #unknown
             }
+        },
+        decode: quote! {
+            let tag = <u8 as example_core::Payload>::decode_with(buf, ctx)?;
 
-            #[must_use]
-            async fn write_to_send_stream(&self, send: &mut quinn::SendStream) -> anyhow::Result<()> {
-                #write_to_send_code
+            match tag {
+                #(#decode_arms)*
+                #unknown
+            }
+        },
+        encoded_len: quote! {
+            match self {
+                #(#encoded_len_arms)*
+            }
+        },
+        encode: quote! {
+            match self {
+                #(#encode_arms)*
+            }
 
-                Ok(())
+            Ok(())
+        },
+        schema: quote! {
+            example_core::schema::Definition::Enum {
+                variants: vec![#(#variant_schemas),*],
             }
+        },
+    })
+}
+
+/// Derives `example_core::Payload`, along with `example_core::Encode`.
+///
+/// Structs are encoded as the concatenation of their fields, tuple structs included. Unit structs
+/// take no bytes on the wire. Every type parameter of a generic type is required to implement
+/// `Payload + Send + Sync`.
+///
+/// Enums are encoded as a `u8` discriminant followed by the fields of the variant. The
+/// discriminant of a variant can be picked with `#[payload(tag = N)]`, and decoding an unknown
+/// discriminant returns an error.
+///
+/// Decoding errors are `example_core::PayloadError`s naming the field that failed, like
+/// `failed decoding Message.sent_by: invalid UTF-8: ...`.
+///
+/// Fields accept the following attributes:
+///
+/// - `#[payload(skip)]`: the field is not sent and is filled with `Default::default()`.
+/// - `#[payload(default)]`: the field is filled with `Default::default()` when it wasn't sent.
+///   Used for fields added to a struct after peers using it were deployed. Unless the struct is
+///   `#[payload(versioned)]`, the `default` fields must be the last ones, and are only filled
+///   when the data ends before them in the outermost value, like the input of a command: a
+///   nested value can't tell its end from the start of what follows, so it fails decoding.
+/// - `#[payload(with = "module")]`: the field is handled by `module::encoded_len(&value)`,
+///   `module::encode(&value, buf)`, `module::decode_with(buf, ctx)` and
+///   `module::read_with(recv, ctx)` instead of its `Payload` impl. These functions have the same signatures as the methods of
+///   `Payload`.
+/// - `#[payload(varint)]`: the integer field is encoded as a QUIC style varint instead of a fixed
+///   width integer. Signed integers are zigzag encoded.
+/// - `#[payload(len = "u32")]`: the length of a `String`, `Vec` or `Bytes` field is prefixed by the
+///   given type (`u8`, `u16`, `u32` or `u64`) instead of a varint.
+///
+/// Structs marked `#[payload(versioned)]` are encoded with a tag and a length for every field, as
+/// described in `example_core::versioned`, so that peers using different versions of the struct
+/// can talk to each other. Unknown fields are skipped, and fields that weren't sent are filled with
+/// `Default::default()` when marked `default` or fail decoding otherwise. The tag of a field is picked with `#[payload(tag = N)]`, otherwise it is the
+/// previous tag plus one. A field must keep its tag once deployed, and the tag of a removed field
+/// must not be reused.
+///
+/// `Payload::schema` describes the type as an `example_core::schema::Definition` listing the
+/// fields sent on the wire with their attributes, so the format can be published for peers
+/// written in other languages. A field using `with` is only described by the name of its module.
+#[proc_macro_derive(Payload, attributes(payload))]
+pub fn derive_payload(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand_payload(input) {
+        Ok(expanded) => expanded.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_payload(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let DeriveInput {
+        attrs,
+        ident,
+        generics,
+        data,
+        ..
+    } = input;
+    let container_attrs = ContainerAttrs::parse(&attrs)?;
+
+    let PayloadCode {
+        read,
+        decode,
+        encoded_len,
+        encode,
+        schema,
+    } = match &data {
+        Struct(data) if container_attrs.versioned => {
+            generate_versioned_struct_code(&ident, &data.fields)?
+        }
+        Struct(data) => generate_struct_code(&ident, &data.fields)?,
+        Data::Enum(data) if container_attrs.versioned => {
+            return Err(syn::Error::new_spanned(
+                data.enum_token,
+                "`versioned` is only supported on structs",
+            ))
+        }
+        Data::Enum(data) => generate_enum_code(&ident, data)?,
+        Data::Union(data) => {
+            return Err(syn::Error::new_spanned(
+                data.union_token,
+                "Payload cannot be derived for unions",
+            ))
         }
     };
 
-    expanded.into()
+    let generics = add_payload_bounds(generics);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics example_core::Encode for #ident #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn encoded_len(&self) -> usize {
+                #encoded_len
+            }
+
+            #[allow(unused_variables)]
+            fn encode(
+                &self,
+                buf: &mut example_core::BytesMut,
+            ) -> Result<(), example_core::PayloadError> {
+                #encode
+            }
+        }
+
+        impl #impl_generics example_core::Payload for #ident #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn decode_with(
+                buf: &mut example_core::Bytes,
+                ctx: &mut example_core::DecodeContext,
+            ) -> Result<Self, example_core::PayloadError> {
+                // leaves the nested value however the decoding ends, including through `?`
+                let mut __nested = ctx.enter()?;
+                let ctx: &mut example_core::DecodeContext = &mut __nested;
+
+                #decode
+            }
+
+            #[must_use]
+            #[allow(unused_variables)]
+            async fn read_with<R>(
+                recv: &mut R,
+                ctx: &mut example_core::DecodeContext,
+            ) -> Result<Self, example_core::PayloadError>
+            where
+                R: example_core::AsyncRead + Unpin + Send + ?Sized,
+            {
+                // leaves the nested value however the decoding ends, including through `?`
+                let mut __nested = ctx.enter()?;
+                let ctx: &mut example_core::DecodeContext = &mut __nested;
+
+                #read
+            }
+
+            #[allow(unused_variables)]
+            fn schema(
+                defs: &mut example_core::schema::Definitions,
+            ) -> example_core::schema::Schema {
+                defs.define::<Self>(|defs| #schema)
+            }
+        }
+    })
+}
+
+/// Derives `example_core::Payload` for an enum of commands identified by a `u8` id, along with
+/// `From<u8>` and `Into<u8>`.
+///
+/// Every variant is a command without fields, except the one marked `#[command(unknown)]` that
+/// holds the ids matching no command, like `Unknown(u8)`. Decoding never fails then, so a peer
+/// can answer a command it doesn't know instead of dropping the stream.
+///
+/// Variants accept `#[command(id = N, input = T, output = U)]`, every part being optional:
+///
+/// - `id`: the id of the command, otherwise an explicit `Variant = N` or the previous id plus one
+///   (starting at 0).
+/// - `input`: the type sent after the id by the peer issuing the command, `()` by default.
+/// - `output`: the type sent back by the peer handling the command, `()` by default.
+///
+/// For every command, a marker type implementing `example_core::Command` with these types is
+/// generated in a module named after the enum, like `server_command::Login` for
+/// `ServerCommand::Login`. The enum must be `Copy`.
+#[proc_macro_derive(Command, attributes(command))]
+pub fn derive_command(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match command::expand_command(input) {
+        Ok(expanded) => expanded.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Turns a trait into a service called over QUIC, one bidirectional stream per call, see
+/// `example_core::rpc`.
+///
+/// Every method is `async`, takes `&self` and at most one input, and returns an
+/// `example_core::Response<T>`, where the input and `T` are `Payload`s:
+///
+/// ```text
+/// #[lib::service]
+/// pub trait ChatService {
+///     async fn login(&self, input: LoginInput) -> Response<User>;
+///     async fn send_message(&self, input: SendMessageInput) -> Response<()>;
+/// }
+/// ```
+///
+/// The methods return `Send` futures once expanded, and the trait requires `Send + Sync`. Along
+/// with the trait, the attribute generates:
+///
+/// - `ChatServiceMethod`: an enum deriving `Command` with a variant per method, the ids following
+///   the order of the methods. Methods can only be added at the end without breaking deployed
+///   peers.
+/// - `ChatServiceClient`: wraps a connection, with a method per method of the trait returning
+///   `Result<T, example_core::RpcError>`.
+/// - `ChatServiceServer<S>`: routes the calls to `S: ChatService`, implementing
+///   `example_core::rpc::Dispatch`.
+#[proc_macro_attribute]
+pub fn service(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        let attr = proc_macro2::TokenStream::from(attr);
+
+        return syn::Error::new_spanned(attr, "service takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let service = parse_macro_input!(item as syn::ItemTrait);
+
+    match service::expand_service(service) {
+        Ok(expanded) => expanded.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
 }
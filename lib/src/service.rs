@@ -0,0 +1,332 @@
+//! The `service` attribute, see [crate::service].
+
+use quote::{format_ident, quote};
+use syn::{
+    parse_quote, FnArg, GenericArgument, Ident, ItemTrait, PathArguments, ReturnType, TraitItem,
+    TraitItemFn, Type,
+};
+
+use crate::command::to_snake_case;
+
+/// Converts a method name like `send_message` to a variant name like `SendMessage`.
+fn to_camel_case(name: &str) -> String {
+    name.split('_')
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .into_iter()
+                .flat_map(char::to_uppercase)
+                .chain(chars)
+        })
+        .collect()
+}
+
+/// A method of the service.
+struct Method {
+    name: Ident,
+    variant: Ident,
+    /// `None` for a method without input, sent as `()`.
+    input: Option<Type>,
+    output: Type,
+}
+
+/// Extracts `T` from `Response<T>`.
+fn response_output(ty: &Type) -> Option<Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Response" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+
+    match args.args.iter().collect::<Vec<_>>()[..] {
+        [GenericArgument::Type(output)] => Some(output.clone()),
+        _ => None,
+    }
+}
+
+/// Checks the signature of a method and turns it into a method returning a `Send` future, so that
+/// the server can be spawned.
+fn parse_method(method: &mut TraitItemFn) -> syn::Result<Method> {
+    let sig = &mut method.sig;
+
+    if sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(
+            sig.fn_token,
+            "service methods must be `async`",
+        ));
+    }
+    if let Some(default) = &method.default {
+        return Err(syn::Error::new_spanned(
+            default,
+            "service methods cannot have a default implementation",
+        ));
+    }
+    if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+        return Err(syn::Error::new_spanned(
+            &sig.generics,
+            "service methods cannot be generic",
+        ));
+    }
+    let takes_ref_self = sig
+        .receiver()
+        .is_some_and(|receiver| receiver.reference.is_some() && receiver.mutability.is_none());
+    if !takes_ref_self {
+        return Err(syn::Error::new_spanned(
+            &sig.inputs,
+            "service methods must take `&self`",
+        ));
+    }
+
+    let input = match sig.inputs.iter().skip(1).collect::<Vec<_>>()[..] {
+        [] => None,
+        [FnArg::Typed(arg)] => Some((*arg.ty).clone()),
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &sig.inputs,
+                "service methods take a single input besides `&self`, use a message for more",
+            ))
+        }
+    };
+    let (response, output) = match &sig.output {
+        ReturnType::Type(_, ty) => response_output(ty).map(|output| (ty.clone(), output)),
+        ReturnType::Default => None,
+    }
+    .ok_or_else(|| {
+        syn::Error::new_spanned(
+            &sig.output,
+            "service methods must return an `example_core::Response<T>`",
+        )
+    })?;
+
+    sig.asyncness = None;
+    sig.output = parse_quote! {
+        -> impl ::std::future::Future<Output = #response> + Send
+    };
+
+    Ok(Method {
+        name: sig.ident.clone(),
+        variant: format_ident!("{}", to_camel_case(&sig.ident.to_string())),
+        input,
+        output,
+    })
+}
+
+pub fn expand_service(mut service: ItemTrait) -> syn::Result<proc_macro2::TokenStream> {
+    if !service.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &service.generics,
+            "service traits cannot be generic",
+        ));
+    }
+
+    let mut methods = vec![];
+    let mut docs = vec![];
+    for item in &mut service.items {
+        let TraitItem::Fn(method) = item else {
+            return Err(syn::Error::new_spanned(
+                item,
+                "service traits can only have async methods",
+            ));
+        };
+
+        docs.push(
+            method
+                .attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("doc"))
+                .cloned()
+                .collect::<Vec<_>>(),
+        );
+        methods.push(parse_method(method)?);
+    }
+    if methods.len() > 256 {
+        return Err(syn::Error::new_spanned(
+            &service.ident,
+            "a service has at most 256 methods, their ids being a `u8`",
+        ));
+    }
+
+    service.colon_token.get_or_insert_with(Default::default);
+    service.supertraits.push(parse_quote! { Send });
+    service.supertraits.push(parse_quote! { Sync });
+
+    let vis = &service.vis;
+    let ident = &service.ident;
+    let method_enum = format_ident!("{ident}Method");
+    let module = format_ident!("{}", to_snake_case(&method_enum.to_string()));
+    let client = format_ident!("{ident}Client");
+    let server = format_ident!("{ident}Server");
+
+    let method_enum_doc = format!("The methods of [`{ident}`], the ids following their order.");
+    let client_doc =
+        format!("Calls the methods of [`{ident}`] on a connection, see `example_core::rpc`.");
+    let server_doc = format!(
+        "Routes the calls made on a connection to an implementation of [`{ident}`], see \
+         `example_core::rpc::Dispatch`."
+    );
+
+    let names: Vec<_> = methods.iter().map(|method| &method.name).collect();
+    let variants: Vec<_> = methods.iter().map(|method| &method.variant).collect();
+    let ids = (0..methods.len()).map(|id| proc_macro2::Literal::u8_unsuffixed(id as u8));
+    let unit: Type = parse_quote! { () };
+    let inputs: Vec<_> = methods
+        .iter()
+        .map(|method| method.input.as_ref().unwrap_or(&unit))
+        .collect();
+    let outputs: Vec<_> = methods.iter().map(|method| &method.output).collect();
+
+    // a method without input takes no argument, and `()` is sent
+    let client_params = methods.iter().map(|method| match &method.input {
+        Some(input) => quote! { input: #input },
+        None => quote! {},
+    });
+    let client_inputs = methods.iter().map(|method| match &method.input {
+        Some(_) => quote! { &input },
+        None => quote! { &() },
+    });
+    let handlers = methods.iter().map(|method| {
+        let name = &method.name;
+
+        match &method.input {
+            Some(_) => quote! { |input| self.service.#name(input) },
+            None => quote! { |()| self.service.#name() },
+        }
+    });
+    let unknown_name = method_enum.to_string();
+    let method_names = names.iter().map(|name| name.to_string());
+
+    Ok(quote! {
+        #service
+
+        #[doc = #method_enum_doc]
+        #[derive(lib::Command, Copy, Clone, Debug, Eq, PartialEq, Hash)]
+        #vis enum #method_enum {
+            #(
+                #[command(id = #ids, input = #inputs, output = example_core::Response<#outputs>)]
+                #variants,
+            )*
+            /// An id matching no method, like one called by a newer client.
+            #[command(unknown)]
+            Unknown(u8),
+        }
+
+        #[doc = #client_doc]
+        #[derive(Clone, Debug)]
+        #vis struct #client {
+            client: example_core::rpc::RpcClient,
+        }
+
+        #[allow(dead_code)]
+        impl #client {
+            pub fn new(connection: example_core::rpc::Connection) -> Self {
+                Self {
+                    client: example_core::rpc::RpcClient::new(connection),
+                }
+            }
+
+            /// `limits` bound the outputs of the calls.
+            pub fn with_limits(
+                connection: example_core::rpc::Connection,
+                limits: example_core::DecodeLimits,
+            ) -> Self {
+                Self {
+                    client: example_core::rpc::RpcClient::with_limits(connection, limits),
+                }
+            }
+
+            pub fn with_deadlines(self, deadlines: example_core::rpc::Deadlines) -> Self {
+                Self {
+                    client: self.client.with_deadlines(deadlines),
+                }
+            }
+
+            pub fn with_reset_codes(self, reset_codes: example_core::rpc::ResetCodes) -> Self {
+                Self {
+                    client: self.client.with_reset_codes(reset_codes),
+                }
+            }
+
+            pub fn connection(&self) -> &example_core::rpc::Connection {
+                self.client.connection()
+            }
+
+            #(
+                #(#docs)*
+                pub async fn #names(
+                    &self,
+                    #client_params
+                ) -> Result<#outputs, example_core::RpcError> {
+                    self.client.call::<#module::#variants, _>(#client_inputs).await
+                }
+            )*
+        }
+
+        #[doc = #server_doc]
+        #vis struct #server<S> {
+            service: S,
+            limits: example_core::DecodeLimits,
+        }
+
+        #[allow(dead_code)]
+        impl<S: #ident> #server<S> {
+            pub fn new(service: S) -> Self {
+                Self::with_limits(service, example_core::DecodeLimits::default())
+            }
+
+            /// `limits` bound the inputs of the calls.
+            pub fn with_limits(service: S, limits: example_core::DecodeLimits) -> Self {
+                Self { service, limits }
+            }
+
+            pub fn service(&self) -> &S {
+                &self.service
+            }
+        }
+
+        impl<S: #ident> example_core::rpc::Dispatch for #server<S> {
+            async fn handle<W, R>(
+                &self,
+                call: &example_core::rpc::Call,
+                send: &mut W,
+                recv: &mut R,
+            ) -> Result<(), example_core::PayloadError>
+            where
+                W: example_core::AsyncWrite + Unpin + Send + ?Sized,
+                R: example_core::AsyncRead + Unpin + Send + ?Sized,
+            {
+                match #method_enum::from(call.id()) {
+                    #(
+                        #method_enum::#variants => {
+                            example_core::rpc::respond::<#module::#variants, _, _, _, _, _>(
+                                send,
+                                recv,
+                                &self.limits,
+                                #handlers,
+                            )
+                            .await
+                        }
+                    )*
+                    #method_enum::Unknown(value) => {
+                        Err(example_core::PayloadError::UnknownDiscriminant {
+                            ty: #unknown_name,
+                            value,
+                        })
+                    }
+                }
+            }
+
+            fn method_name(&self, id: u8) -> Option<&'static str> {
+                match #method_enum::from(id) {
+                    #(#method_enum::#variants => Some(#method_names),)*
+                    #method_enum::Unknown(_) => None,
+                }
+            }
+        }
+    })
+}
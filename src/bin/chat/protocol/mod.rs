@@ -1,37 +1,14 @@
-mod login;
-mod message;
-mod user;
+//! Generated from `chat.idl` by build.rs, along with the [ChatService] called by the clients.
 
-pub use login::{LoginInput, LoginOutput};
-pub use message::{Message, SendMessageInput};
-use num_derive::{FromPrimitive, ToPrimitive};
-pub use user::User;
+include!(concat!(env!("OUT_DIR"), "/chat_protocol.rs"));
 
-#[repr(u8)]
-#[derive(Copy, Clone, Eq, PartialEq, ToPrimitive, FromPrimitive)]
-pub enum ClientCommand {
-    /// Payload = [Vec<Message>]
-    NewMessage = 0,
+use example_core::Response;
 
-    Unknown = u8::MAX,
-}
-
-#[repr(u8)]
-#[derive(Eq, PartialEq, ToPrimitive, FromPrimitive)]
-pub enum ServerCommand {
-    /// Input = [LoginInput]
-    /// Output = [LoginOutput]
-    Login = 0,
-    /// Input = [SendMessageInput]
-    /// Output = [None]
-    SendMessage = 1,
-
-    Unknown = u8::MAX,
-}
-
-#[repr(u8)]
-#[derive(Eq, PartialEq, ToPrimitive, FromPrimitive)]
-pub enum ServerResponse {
-    Success = 0,
-    Error = 1,
+/// Called by a client on a bi stream per call, the server sending the messages back as
+/// [ClientCommand]s.
+#[lib::service]
+pub trait ChatService {
+    /// Must be called first, the other calls failing until the user is logged in.
+    async fn login(&self, input: LoginInput) -> Response<User>;
+    async fn send_message(&self, input: SendMessageInput) -> Response<()>;
 }
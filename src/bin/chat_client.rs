@@ -3,143 +3,233 @@ mod common;
 
 use std::error::Error;
 use std::sync::OnceLock;
+use std::time::Duration;
 
 use anyhow::anyhow;
-use num_traits::FromPrimitive;
-use quinn::Connection;
+use quinn::{Connection, Endpoint, RecvStream};
 
 use tokio::fs;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::sync::Mutex;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::{mpsc, Mutex};
 
 use crate::chat::protocol::{
-    ClientCommand, LoginInput, Message, SendMessageInput, ServerCommand, ServerResponse, User,
+    client_command, ChatServiceClient, ClientCommand, LoginInput, Message, SendMessageInput, User,
 };
-use crate::common::{create_stop_signal, make_client_endpoint};
-use example_core::Payload;
+use crate::common::{
+    close_message, create_stop_signal, make_client_endpoint, should_reconnect, AppErrorCode,
+    CALL_DEADLINE, CLIENT_DECODE_LIMITS,
+};
+use example_core::rpc::Deadlines;
+use example_core::{Command, ErrorCode, Payload, RpcError};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<(), Box<dyn Error>> {
-    let server_addr = "127.0.0.1:5000".parse().unwrap();
-    let cert_der = fs::read("certs/cert.der")
-        .await
-        .map_err(|e| anyhow!("Unable to read cert.der file: {}", e))?;
-    let endpoint = make_client_endpoint("0.0.0.0:0".parse().unwrap(), &[&cert_der])?;
-    // connect to server
-    let connection = endpoint
-        .connect(server_addr, "localhost")
-        .unwrap()
-        .await
-        .unwrap();
-
     print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
 
+    // stdin is read by a single task, the lines outliving the connections
+    let mut lines = read_lines();
+
     println!("Type your username and press enter.");
-    let mut reader = BufReader::new(tokio::io::stdin());
-    let mut username: String = String::new();
-    reader.read_line(&mut username).await?;
-
-    let _user: User;
-    {
-        let (mut send, mut recv) = connection.open_bi().await?;
-        let login_input: LoginInput = LoginInput::new(username.trim());
-
-        send.write_u8(ServerCommand::Login as u8).await?;
-        login_input.write_to_send_stream(&mut send).await?;
-        if ServerResponse::Error
-            == ServerResponse::from_u8(recv.read_u8().await?).unwrap_or(ServerResponse::Error)
-        {
-            let error_message = String::read_from_recv_stream(&mut recv).await?;
-
-            Err(anyhow!("Failed to login! {error_message}"))?;
-        };
+    let Some(mut username) = lines.recv().await else {
+        return Ok(());
+    };
 
-        _user = User::read_from_recv_stream(&mut recv).await?;
-    }
-    reload_screen().await;
+    let (_, mut stop_signal_recv) = create_stop_signal().await;
 
-    // receive commands loop
-    tokio::spawn({
-        let connection = connection.clone();
+    let mut connected = connect().await?;
+    loop {
+        let (endpoint, connection) = connected;
+
+        let client = ChatServiceClient::with_limits(connection.clone(), CLIENT_DECODE_LIMITS)
+            .with_deadlines(Deadlines::new().with_default(CALL_DEADLINE));
+        // the server sends all the messages again after logging in
+        MESSAGES
+            .get_or_init(|| Mutex::new(vec![]))
+            .lock()
+            .await
+            .clear();
+        let _user: User = loop {
+            match client.login(LoginInput::new(username.trim())).await {
+                Ok(user) => break user,
+                // someone else is logged in with this username, another one may work
+                Err(RpcError::Remote(error)) if error.code == ErrorCode::ALREADY_EXISTS => {
+                    println!("{} Type another username and press enter.", error.message);
+                    let next_username = tokio::select! {
+                        line = lines.recv() => line,
+                        _ = stop_signal_recv.recv() => None,
+                    };
+                    username = match next_username {
+                        Some(username) => username,
+                        None => {
+                            AppErrorCode::NormalShutdown.close(&connection);
+                            endpoint.wait_idle().await;
+
+                            return Ok(());
+                        }
+                    };
+                }
+                Err(RpcError::Remote(error)) => {
+                    AppErrorCode::AuthFailed.close(&connection);
+                    endpoint.wait_idle().await;
+                    Err(anyhow!("Failed to login! {error}"))?
+                }
+                Err(e) => Err(e)?,
+            }
+        };
+        reload_screen().await;
 
-        async move {
-            let _ = receive_commands(connection).await;
-        }
-    });
+        let receive = tokio::spawn({
+            let connection = connection.clone();
 
-    // send message loop
-    tokio::spawn({
-        let connection = connection.clone();
+            async move {
+                if let Err(e) = receive_commands(connection).await {
+                    eprintln!("Stopped receiving messages: {e}");
+                }
+            }
+        });
 
-        async move {
-            let _ = send_messages(connection).await;
-        }
-    });
+        let reason = tokio::select! {
+            reason = connection.closed() => reason,
+            // stdin was closed
+            () = send_messages(&client, &mut lines) => {
+                AppErrorCode::NormalShutdown.close(&connection);
+                endpoint.wait_idle().await;
 
-    let (stop_signal_sender, mut stop_signal_recv) = create_stop_signal().await;
+                return Ok(());
+            }
+            _ = stop_signal_recv.recv() => {
+                AppErrorCode::NormalShutdown.close(&connection);
+                endpoint.wait_idle().await;
 
-    tokio::spawn({
-        let endpoint = endpoint.clone();
-        let connection = connection.clone();
+                return Ok(());
+            }
+        };
+        receive.abort();
 
-        async move {
-            endpoint.wait_idle().await;
-            println!(
-                "Lost connection! Reason: {}",
-                connection.close_reason().unwrap()
-            );
-            let _ = stop_signal_sender.send(()).await;
+        println!("Lost connection! Reason: {}", close_message(&reason));
+        if !should_reconnect(&reason) {
+            return Ok(());
         }
-    });
-
-    let _ = stop_signal_recv.recv().await;
 
-    connection.close(0_u32.into(), b"done");
-
-    Ok(())
+        println!("Reconnecting...");
+        connected = match reconnect(&mut stop_signal_recv).await {
+            Some(connected) => connected,
+            None => return Ok(()),
+        };
+    }
 }
 
-async fn send_messages(connection: Connection) -> anyhow::Result<()> {
+/// Connects again until it works, waiting longer after each failure, or until the client is
+/// stopped.
+async fn reconnect(stop_signal_recv: &mut mpsc::Receiver<()>) -> Option<(Endpoint, Connection)> {
+    let mut delay = RECONNECT_DELAY;
     loop {
-        let (mut send, mut recv) = connection.open_bi().await?;
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = stop_signal_recv.recv() => return None,
+        }
+        tokio::select! {
+            result = connect() => match result {
+                Ok(connected) => return Some(connected),
+                Err(e) => println!("Failed to reconnect! {e}"),
+            },
+            _ = stop_signal_recv.recv() => return None,
+        }
 
-        let mut reader = BufReader::new(tokio::io::stdin());
-        let mut message_text: String = String::new();
-        reader.read_line(&mut message_text).await?;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        println!("Reconnecting in {} seconds...", delay.as_secs());
+    }
+}
 
-        let message: SendMessageInput = SendMessageInput::new(message_text.trim());
-        send.write_u8(ServerCommand::SendMessage as u8).await?;
-        message.write_to_send_stream(&mut send).await?;
+/// Connects to the server, whose certificate changes whenever it restarts.
+async fn connect() -> anyhow::Result<(Endpoint, Connection), Box<dyn Error>> {
+    let server_addr = "127.0.0.1:5000".parse().unwrap();
+    let cert_der = fs::read("certs/cert.der")
+        .await
+        .map_err(|e| anyhow!("Unable to read cert.der file: {}", e))?;
+    let endpoint = make_client_endpoint("0.0.0.0:0".parse().unwrap(), &[&cert_der])?;
+    let connection = endpoint.connect(server_addr, "localhost")?.await?;
+
+    Ok((endpoint, connection))
+}
 
-        assert_eq!(recv.read_u8().await?, ServerResponse::Success as u8);
+/// Sends the lines typed by the user as messages, until stdin is closed.
+async fn send_messages(client: &ChatServiceClient, lines: &mut mpsc::Receiver<String>) {
+    while let Some(line) = lines.recv().await {
+        let message: SendMessageInput = SendMessageInput::new(line.trim());
+        if let Err(e) = client.send_message(message).await {
+            println!("Failed to send the message! {e}");
+        }
     }
 }
 
+/// Reads the lines of stdin on a task of its own, until stdin is closed or the receiver is
+/// dropped.
+fn read_lines() -> mpsc::Receiver<String> {
+    let (send, recv) = mpsc::channel(16);
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) => {
+                    if send.send(line).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    eprintln!("Stopped reading stdin: {e}");
+                    return;
+                }
+            }
+        }
+    });
+
+    recv
+}
+
+/// The time to wait before connecting again to a server that closed the connection, doubled
+/// after each failed attempt.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// The longest time to wait between two attempts to connect again.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
 static MESSAGES: OnceLock<Mutex<Vec<Message>>> = OnceLock::new();
 
+/// Receives the commands sent by the server, each on its own stream, until the connection is
+/// lost. A stream that fails only loses its own command.
 async fn receive_commands(connection: Connection) -> anyhow::Result<()> {
     loop {
         let mut recv = connection.accept_uni().await?;
 
-        let command: u8 = recv.read_u8().await?;
+        if let Err(e) = receive_command(&mut recv).await {
+            eprintln!("Failed to receive a command: {e}");
+        }
+    }
+}
 
-        match ClientCommand::from_u8(command).unwrap_or(ClientCommand::Unknown) {
-            ClientCommand::NewMessage => {
-                let mut messages = Vec::<Message>::read_from_recv_stream(&mut recv).await?;
+async fn receive_command(recv: &mut RecvStream) -> anyhow::Result<()> {
+    match ClientCommand::read_from_recv_stream(recv).await? {
+        ClientCommand::NewMessage => {
+            let mut messages =
+                client_command::NewMessage::read_input(recv, &CLIENT_DECODE_LIMITS).await?;
 
-                MESSAGES
-                    .get_or_init(|| Mutex::new(vec![]))
-                    .lock()
-                    .await
-                    .append(&mut messages);
+            MESSAGES
+                .get_or_init(|| Mutex::new(vec![]))
+                .lock()
+                .await
+                .append(&mut messages);
 
-                reload_screen().await;
-            }
-            ClientCommand::Unknown => {
-                println!("Unknown client command: {}", command);
-            }
+            reload_screen().await;
+        }
+        ClientCommand::Unknown(command) => {
+            println!("Unknown client command: {}", command);
         }
     }
+
+    Ok(())
 }
 
 async fn reload_screen() {
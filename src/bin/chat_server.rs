@@ -5,18 +5,22 @@ use anyhow::anyhow;
 use std::collections::HashMap;
 use std::sync::OnceLock;
 
-use example_core::Payload;
-use num_traits::FromPrimitive;
+use example_core::middleware::{AuthState, Logging, RequireAuth};
+use example_core::rpc::{ConnectionHandler, Deadlines, Dispatch};
+use example_core::{Command, Encoded, ErrorCode, Response, ResponseError};
 use quinn::Connection;
 use tokio::fs;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::{Mutex, MutexGuard};
 use uuid::Uuid;
 
 use crate::chat::protocol::{
-    ClientCommand, LoginInput, Message, SendMessageInput, ServerCommand, ServerResponse, User,
+    chat_service_method, client_command, ChatService, ChatServiceServer, LoginInput, Message,
+    SendMessageInput, User,
+};
+use crate::common::{
+    create_stop_signal, make_server_endpoint, AppErrorCode, CALL_DEADLINE, MAX_CONCURRENT_CALLS,
+    SERVER_DECODE_LIMITS,
 };
-use crate::common::{create_stop_signal, make_server_endpoint};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -30,8 +34,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let endpoint = endpoint.clone();
 
         async move {
-            loop {
-                let incoming_conn = endpoint.accept().await.unwrap();
+            // stops once the endpoint is closed
+            while let Some(incoming_conn) = endpoint.accept().await {
                 let conn = incoming_conn.await.unwrap();
                 println!(
                     "[server] connection accepted: addr={}",
@@ -49,7 +53,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let _ = stop_signal_recv.recv().await;
 
     println!("Shutting down.");
-    endpoint.close(0_u32.into(), b"Shut down");
+    AppErrorCode::ServerRestarting.close_endpoint(&endpoint);
+    endpoint.wait_idle().await;
 
     Ok(())
 }
@@ -59,77 +64,90 @@ static USERS: OnceLock<Mutex<HashMap<ConnectionStableId, User>>> = OnceLock::new
 static CONNECTIONS: OnceLock<Mutex<HashMap<ConnectionStableId, Connection>>> = OnceLock::new();
 static MESSAGES: OnceLock<Mutex<Vec<Message>>> = OnceLock::new();
 
-async fn await_commands(connection: Connection) -> anyhow::Result<()> {
-    while let Ok((mut send, mut recv)) = connection.accept_bi().await {
-        let command = recv.read_u8().await?;
-
-        match ServerCommand::from_u8(command).unwrap_or(ServerCommand::Unknown) {
-            ServerCommand::Login => {
-                println!("> Login");
-
-                let payload: LoginInput = LoginInput::read_from_recv_stream(&mut recv).await?;
-                match login(&connection, Uuid::new_v4(), payload).await {
-                    Ok(user) => {
-                        send.write_u8(ServerResponse::Success as u8).await?;
-
-                        user.write_to_send_stream(&mut send).await?;
-
-                        let mut previous_messages_send = connection.open_uni().await?;
-                        let messages_guard =
-                            MESSAGES.get_or_init(|| Mutex::new(vec![])).lock().await;
-                        previous_messages_send
-                            .write_u8(ClientCommand::NewMessage as u8)
-                            .await?;
-                        messages_guard
-                            .write_to_send_stream(&mut previous_messages_send)
-                            .await?;
-
-                        let message: Message = Message::new(
-                            format!(
-                                "{username} has entered the chat!",
-                                username = user.username()
-                            )
-                            .as_str(),
-                            None,
-                        );
-                        let _ = propagate_message(message, Some(&connection)).await;
-                    }
-                    Err(e) => {
-                        send.write_u8(ServerResponse::Error as u8).await?;
-
-                        e.to_string().write_to_send_stream(&mut send).await?;
-                    }
-                };
-            }
-            ServerCommand::SendMessage => {
-                println!("> SendMessage");
-
-                let input = SendMessageInput::read_from_recv_stream(&mut recv).await?;
-                let guard = USERS.get().unwrap().lock().await;
-                if let Some(user) = guard.get(&connection.stable_id()) {
-                    let user = user.clone();
-                    drop(guard);
-
-                    let message: Message = Message::new(input.message(), Some(user.clone()));
-                    MESSAGES
-                        .get_or_init(|| Mutex::new(vec![]))
-                        .lock()
-                        .await
-                        .push(message.clone());
-
-                    send.write_u8(ServerResponse::Success as u8).await?;
-
-                    propagate_message(message, None).await?;
-                } else {
-                    // user not found
-                    send.write_u8(ServerResponse::Error as u8).await?;
-                }
-            }
-            ServerCommand::Unknown => {
-                println!("> Unknown");
-            }
-        }
+/// The service of a connection, which logs in a single user.
+struct ChatConnection {
+    connection: Connection,
+    auth: AuthState,
+}
+
+impl ChatService for ChatConnection {
+    async fn login(&self, input: LoginInput) -> Response<User> {
+        let connection = &self.connection;
+        let user = login(connection, Uuid::new_v4(), input).await?;
+        self.auth.set_authenticated();
+
+        // the history is encoded once while holding the lock, without being copied, and the lock
+        // is released before sending it
+        let previous_messages = {
+            let messages = MESSAGES.get_or_init(|| Mutex::new(vec![])).lock().await;
+
+            Encoded::<[Message]>::new(&messages).map_err(ResponseError::internal)?
+        };
+        let mut previous_messages_send = connection
+            .open_uni()
+            .await
+            .map_err(ResponseError::internal)?;
+        client_command::NewMessage::write_encoded_input(
+            &mut previous_messages_send,
+            &previous_messages,
+        )
+        .await
+        .map_err(ResponseError::internal)?;
+
+        let message: Message = Message::new(
+            format!(
+                "{username} has entered the chat!",
+                username = user.username()
+            )
+            .as_str(),
+            None,
+        );
+        spawn_propagate_message(message, Some(connection.clone()));
+
+        Ok(user)
+    }
+
+    async fn send_message(&self, input: SendMessageInput) -> Response<()> {
+        let guard = USERS
+            .get_or_init(|| Mutex::new(HashMap::new()))
+            .lock()
+            .await;
+        let Some(user) = guard.get(&self.connection.stable_id()).cloned() else {
+            return Err(ResponseError::new(
+                ErrorCode::UNAUTHENTICATED,
+                "Log in before sending messages!",
+            ));
+        };
+        drop(guard);
+
+        let message: Message = Message::new(input.message(), Some(user));
+        MESSAGES
+            .get_or_init(|| Mutex::new(vec![]))
+            .lock()
+            .await
+            .push(message.clone());
+
+        // the sender gets its response without waiting for every connection to get the message
+        spawn_propagate_message(message, None);
+
+        Ok(())
     }
+}
+
+async fn await_commands(connection: Connection) -> anyhow::Result<()> {
+    let auth = AuthState::new();
+    let service = ChatConnection {
+        connection: connection.clone(),
+        auth: auth.clone(),
+    };
+    let server = ChatServiceServer::with_limits(service, SERVER_DECODE_LIMITS)
+        .with(RequireAuth::new(auth).allow::<chat_service_method::Login>())
+        .with(Logging::new("server"));
+    ConnectionHandler::new(server)
+        .with_max_concurrent_calls(MAX_CONCURRENT_CALLS)
+        .with_deadlines(Deadlines::new().with_default(CALL_DEADLINE))
+        .serve(&connection)
+        .await;
 
     if let Some(map) = CONNECTIONS.get() {
         println!("Remove connection {}", connection.stable_id());
@@ -142,37 +160,69 @@ async fn await_commands(connection: Connection) -> anyhow::Result<()> {
                 format!("{username} has left the chat!", username = user.username()).as_str(),
                 None,
             );
-            let _ = propagate_message(message, Some(&connection)).await;
+            let _ = propagate_message(&message, Some(&connection)).await;
         }
     }
 
     Ok(())
 }
 
+/// Propagates the message in the background, so that a call doesn't wait for slow connections.
+fn spawn_propagate_message(message: Message, ignored_connection: Option<Connection>) {
+    tokio::spawn(async move {
+        if let Err(e) = propagate_message(&message, ignored_connection.as_ref()).await {
+            eprintln!("[server] failed to propagate message: {e}");
+        }
+    });
+}
+
 // SEND Message COMMAND TO ALL CONNECTIONS
+//
+// A connection failing to receive the message is logged, and doesn't prevent the others from
+// receiving it.
 async fn propagate_message(
-    message: Message,
+    message: &Message,
     ignored_connection: Option<&Connection>,
 ) -> anyhow::Result<()> {
-    let guard = CONNECTIONS
+    // encoded once as a list of a single message, then shared by all the connections
+    let encoded = Encoded::new(std::slice::from_ref(message))?;
+
+    // the lock is released before sending, so that a slow connection doesn't block the others
+    let connections: Vec<Connection> = CONNECTIONS
         .get()
         .ok_or(anyhow!("CONNECTION_MAP not initialized"))?
         .lock()
-        .await;
-    for (client_id, connection) in guard.iter() {
-        if ignored_connection.is_none() || *client_id != ignored_connection.unwrap().stable_id() {
-            let mut send = connection.open_uni().await?;
-            send.write_u8(ClientCommand::NewMessage as u8).await?;
-            vec![message.clone()]
-                .write_to_send_stream(&mut send)
-                .await?;
+        .await
+        .iter()
+        .filter(|(client_id, _)| {
+            ignored_connection.is_none_or(|ignored| **client_id != ignored.stable_id())
+        })
+        .map(|(_, connection)| connection.clone())
+        .collect();
+
+    for connection in connections {
+        if let Err(e) = send_message_command(&connection, &encoded).await {
+            eprintln!(
+                "[server] failed to send message to {}: {e}",
+                connection.remote_address()
+            );
         }
     }
 
     Ok(())
 }
 
-async fn login(connection: &Connection, uuid: Uuid, payload: LoginInput) -> anyhow::Result<User> {
+async fn send_message_command(
+    connection: &Connection,
+    encoded: &Encoded<[Message]>,
+) -> anyhow::Result<()> {
+    let mut send = connection.open_uni().await?;
+    client_command::NewMessage::write_encoded_input(&mut send, encoded).await?;
+
+    Ok(())
+}
+
+async fn login(connection: &Connection, uuid: Uuid, payload: LoginInput) -> Response<User> {
     let username: &str = payload.username().trim();
 
     let mut users_guard = USERS
@@ -198,10 +248,13 @@ async fn login(connection: &Connection, uuid: Uuid, payload: LoginInput) -> anyh
 fn validate_username(
     username: &str,
     users_guard: &MutexGuard<HashMap<ConnectionStableId, User>>,
-) -> anyhow::Result<()> {
+) -> Result<(), ResponseError> {
     for user in users_guard.values() {
         if user.username() == username {
-            return Err(anyhow!("Username already used!"));
+            return Err(ResponseError::new(
+                ErrorCode::ALREADY_EXISTS,
+                "Username already used!",
+            ));
         }
     }
 
@@ -0,0 +1,89 @@
+//! The application error codes the examples close connections with.
+//!
+//! The streams of calls are reset and stopped with the defaults of
+//! [ResetCodes](example_core::rpc::ResetCodes), 1 to 4, which these codes leave out so that a code
+//! means the same thing wherever it is received.
+
+use quinn::{ConnectionError, Endpoint, VarInt};
+
+/// Why a connection was closed by the application.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum AppErrorCode {
+    /// The peer is done, like a client quitting.
+    NormalShutdown = 0,
+    /// The client couldn't log in.
+    AuthFailed = 5,
+    /// The server is shutting down, and should be back soon.
+    ServerRestarting = 6,
+}
+
+impl AppErrorCode {
+    pub fn code(self) -> VarInt {
+        VarInt::from_u32(self as u32)
+    }
+
+    pub fn from_code(code: VarInt) -> Option<AppErrorCode> {
+        [
+            AppErrorCode::NormalShutdown,
+            AppErrorCode::AuthFailed,
+            AppErrorCode::ServerRestarting,
+        ]
+        .into_iter()
+        .find(|app_code| app_code.code() == code)
+    }
+
+    /// Explains the code to the user.
+    pub fn message(self) -> &'static str {
+        match self {
+            AppErrorCode::NormalShutdown => "the connection was closed",
+            AppErrorCode::AuthFailed => "the login failed",
+            AppErrorCode::ServerRestarting => "the server is restarting",
+        }
+    }
+
+    /// Whether connecting again may work, once the server is back.
+    pub fn should_reconnect(self) -> bool {
+        matches!(self, AppErrorCode::ServerRestarting)
+    }
+
+    /// Closes `connection` with this code, its reason being the [AppErrorCode::message].
+    #[allow(unused)]
+    pub fn close(self, connection: &quinn::Connection) {
+        connection.close(self.code(), self.message().as_bytes());
+    }
+
+    /// Closes all the connections of `endpoint` with this code.
+    #[allow(unused)]
+    pub fn close_endpoint(self, endpoint: &Endpoint) {
+        endpoint.close(self.code(), self.message().as_bytes());
+    }
+}
+
+/// Explains why a connection was lost to the user.
+pub fn close_message(error: &ConnectionError) -> String {
+    match error {
+        ConnectionError::ApplicationClosed(close) => {
+            match AppErrorCode::from_code(close.error_code) {
+                Some(code) => code.message().to_string(),
+                None => format!(
+                    "closed by the peer with the unknown code {}",
+                    close.error_code
+                ),
+            }
+        }
+        ConnectionError::TimedOut => "the server stopped answering".to_string(),
+        e => e.to_string(),
+    }
+}
+
+/// Whether connecting again may work after losing a connection, like when the server restarts
+/// or the network failed, but not when the client was refused.
+pub fn should_reconnect(error: &ConnectionError) -> bool {
+    match error {
+        ConnectionError::ApplicationClosed(close) => {
+            AppErrorCode::from_code(close.error_code).is_some_and(AppErrorCode::should_reconnect)
+        }
+        ConnectionError::TimedOut | ConnectionError::Reset => true,
+        _ => false,
+    }
+}
@@ -1,5 +1,11 @@
 //! Commonly used code in most examples.
 
+mod app_error;
+
+#[allow(unused)]
+pub use app_error::{close_message, should_reconnect, AppErrorCode};
+
+use example_core::DecodeLimits;
 use quinn::{ClientConfig, Endpoint, ServerConfig};
 use std::time::Duration;
 use std::{error::Error, net::SocketAddr, sync::Arc};
@@ -75,6 +81,34 @@ async fn configure_server() -> Result<(ServerConfig, Vec<u8>), Box<dyn Error>> {
     Ok((server_config, cert_der))
 }
 
+/// Limits applied by the servers to the payloads sent by clients, which are all small.
+#[allow(unused)]
+pub const SERVER_DECODE_LIMITS: DecodeLimits = DecodeLimits {
+    max_total_bytes: 64 * 1024,
+    max_collection_len: 1024,
+    max_depth: 8,
+    max_string_len: 4 * 1024,
+};
+
+/// Limits applied by the clients to the payloads sent by the server, which replays the whole chat
+/// history after logging in.
+#[allow(unused)]
+pub const CLIENT_DECODE_LIMITS: DecodeLimits = DecodeLimits {
+    max_total_bytes: 256 * 1024 * 1024,
+    max_collection_len: 16 * 1024 * 1024,
+    max_depth: 8,
+    max_string_len: 64 * 1024,
+};
+
+/// The time a call may take by default, on both the client and the server side.
+#[allow(unused)]
+pub const CALL_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Calls a server runs at once for a single connection, further calls being refused with
+/// [RpcError::RateLimited](example_core::RpcError::RateLimited).
+#[allow(unused)]
+pub const MAX_CONCURRENT_CALLS: usize = 16;
+
 #[allow(unused)]
 pub const ALPN_QUIC_HTTP: &[&[u8]] = &[b"hq-29"];
 
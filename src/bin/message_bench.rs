@@ -0,0 +1,104 @@
+//! Measures encoding and decoding a batch of chat messages, as sent to a client when it logs in.
+//!
+//! Run with `cargo run --release --bin message_bench`. Besides the time taken, the number of heap
+//! allocations is counted, since decoding shouldn't allocate more than the decoded values need.
+
+#[allow(unused)]
+mod chat;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use example_core::{Bytes, Encode, Payload};
+use uuid::Uuid;
+
+use crate::chat::protocol::{Message, User};
+
+/// Counts the allocations made by the global allocator.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const MESSAGES: usize = 1_000;
+const ITERATIONS: u32 = 500;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let messages: Vec<Message> = (0..MESSAGES)
+        .map(|i| {
+            let user = (i % 3 != 0).then(|| User::new(Uuid::new_v4(), format!("user-{}", i % 7)));
+            Message::new(format!("message number {i}"), user)
+        })
+        .collect();
+    let bytes: Bytes = messages.to_bytes().unwrap();
+
+    println!(
+        "{MESSAGES} messages, {} bytes, {ITERATIONS} iterations",
+        bytes.len()
+    );
+
+    report("encode", ITERATIONS, || {
+        black_box(black_box(&messages).to_bytes().unwrap());
+    });
+
+    report("decode", ITERATIONS, || {
+        let mut buf = bytes.clone();
+        black_box(Vec::<Message>::decode(&mut buf).unwrap());
+    });
+
+    let started = Instant::now();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..ITERATIONS {
+        let mut recv: &[u8] = &bytes;
+        black_box(
+            Vec::<Message>::read_from_recv_stream(&mut recv)
+                .await
+                .unwrap(),
+        );
+    }
+    print_line(
+        "read",
+        ITERATIONS,
+        started.elapsed(),
+        ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+    );
+}
+
+fn report(name: &str, iterations: u32, mut f: impl FnMut()) {
+    let started = Instant::now();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..iterations {
+        f();
+    }
+
+    print_line(
+        name,
+        iterations,
+        started.elapsed(),
+        ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+    );
+}
+
+fn print_line(name: &str, iterations: u32, elapsed: Duration, allocations: usize) {
+    println!(
+        "{name:>8}: {:>10.1?} per batch, {:>7} allocations per batch",
+        elapsed / iterations,
+        allocations / iterations as usize
+    );
+}
@@ -4,28 +4,27 @@ mod protocol;
 use std::collections::HashMap;
 
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use anyhow::anyhow;
-use num_derive::{FromPrimitive, ToPrimitive};
-use num_traits::FromPrimitive;
-use quinn::Connection;
 use std::sync::OnceLock;
 
-use crate::protocol::LoginInput;
-use crate::protocol::{LoginOutput, PingInput, PingOutput};
-use common::{make_client_endpoint, make_server_endpoint};
-use example_core::Payload;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use crate::protocol::{
+    ping_service_method, LoginInput, LoginOutput, PingInput, PingOutput, PingService,
+    PingServiceClient, PingServiceServer,
+};
+use common::{
+    make_client_endpoint, make_server_endpoint, AppErrorCode, CALL_DEADLINE, MAX_CONCURRENT_CALLS,
+    SERVER_DECODE_LIMITS,
+};
+use example_core::middleware::{AuthState, Logging, RequireAuth};
+use example_core::rpc::{ConnectionHandler, Deadlines, Dispatch};
+use example_core::{ErrorCode, Response, ResponseError, RpcError};
 use tokio::signal;
 use tokio::sync::{mpsc, Mutex};
 
 use uuid::Uuid;
 
-enum ServerResponse {
-    Success = 0x00,
-    Error = 0x01,
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let server_addr = "127.0.0.1:5000".parse().unwrap();
@@ -43,7 +42,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 );
 
                 tokio::spawn(async move {
-                    let _ = await_commands(conn).await;
+                    let auth = AuthState::new();
+                    let service = PingServer { auth: auth.clone() };
+                    let server = PingServiceServer::with_limits(service, SERVER_DECODE_LIMITS)
+                        .with(RequireAuth::new(auth).allow::<ping_service_method::Login>())
+                        .with(Logging::new("server"));
+                    ConnectionHandler::new(server)
+                        .with_max_concurrent_calls(MAX_CONCURRENT_CALLS)
+                        .with_deadlines(Deadlines::new().with_default(CALL_DEADLINE))
+                        .serve(&conn)
+                        .await;
                 });
             }
         }
@@ -68,7 +76,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             match signal::ctrl_c().await {
                 Ok(()) => {
                     println!("CTRL_C CLICKED!!!");
-                    connection.close(0u32.into(), b"closed prematurely");
+                    AppErrorCode::NormalShutdown.close(&connection);
 
                     let _ = stop_signal_sender.send(()).await;
 
@@ -113,7 +121,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 async fn connect_and_ping(
     i: u16,
     server_addr: SocketAddr,
-    server_cert: &Vec<u8>,
+    server_cert: &[u8],
 ) -> anyhow::Result<()> {
     let endpoint = make_client_endpoint("0.0.0.0:0".parse().unwrap(), &[server_cert])
         .map_err(|e| anyhow!("error while creating client endpoint: {}", e))?;
@@ -125,26 +133,19 @@ async fn connect_and_ping(
         .unwrap();
     println!("[client] connected: addr={}", connection.remote_address());
 
+    // pings are answered right away, a slow one means the server is struggling
+    let deadlines = Deadlines::new()
+        .with_default(CALL_DEADLINE)
+        .with_command::<ping_service_method::Ping>(Duration::from_secs(1));
+    let client = PingServiceClient::new(connection.clone()).with_deadlines(deadlines);
     let mut j: u32 = 0;
-    let uuid: Uuid = login(&connection).await?;
+    let uuid: Uuid = login(&client).await?;
 
     while j < 100 {
-        let (mut send, mut recv) = connection
-            .open_bi()
-            .await
-            .map_err(|e| anyhow!("failed to open stream: {}", e))?;
-
-        send.write_u8(Command::Ping as u8).await?;
-
-        let payload = PingInput::new(uuid, j);
-
-        payload.write_to_send_stream(&mut send).await?;
-        send.finish()
+        let output: PingOutput = client
+            .ping(PingInput::new(uuid, j))
             .await
-            .map_err(|e| anyhow!("failed to shutdown stream: {}", e))?;
-
-        let _response_code: u8 = recv.read_u8().await?;
-        let output: PingOutput = PingOutput::read_from_recv_stream(&mut recv).await?;
+            .map_err(|e| anyhow!("Ping failed! {}", e))?;
 
         println!("> Pong ({i},{j})");
         assert_eq!(output.iteration(), j);
@@ -153,51 +154,25 @@ async fn connect_and_ping(
         j += 1;
     }
 
-    connection.close(0u32.into(), b"done");
+    AppErrorCode::NormalShutdown.close(&connection);
 
     Ok(())
 }
 
-async fn login(connection: &Connection) -> anyhow::Result<Uuid> {
-    let (mut send, mut recv) = connection
-        .open_bi()
-        .await
-        .map_err(|e| anyhow!("failed to open stream: {}", e))?;
-
-    send.write_u8(Command::Login as u8).await?;
-
+async fn login(client: &PingServiceClient) -> anyhow::Result<Uuid> {
     let username: String = "test".to_string();
     let password: String = "test".to_string();
 
-    let login_payload = LoginInput::new(username, password);
-    login_payload.write_to_send_stream(&mut send).await?;
-    send.finish()
-        .await
-        .map_err(|e| anyhow!("failed to shutdown stream: {}", e))?;
-
-    let resp: u8 = recv.read_u8().await?;
-
-    if resp == ServerResponse::Success as u8 {
-        let resp: LoginOutput = LoginOutput::read_from_recv_stream(&mut recv).await?;
-
-        println!("uuid = {}", resp.client_id());
-        Ok(resp.client_id())
-    } else {
-        let message_bytes = recv.read_to_end(usize::MAX).await?;
-        let message: String = String::from_utf8(message_bytes)?;
-        panic!("{}", format!("Login failed! {}", message));
+    match client.login(LoginInput::new(username, password)).await {
+        Ok(resp) => {
+            println!("uuid = {}", resp.client_id());
+            Ok(resp.client_id())
+        }
+        Err(RpcError::Remote(message)) => Err(anyhow!("Login failed! {message}")),
+        Err(e) => Err(anyhow!("failed to login: {}", e)),
     }
 }
 
-#[repr(u8)]
-#[derive(Eq, PartialEq, ToPrimitive, FromPrimitive)]
-pub enum Command {
-    Login = 0x01,
-    Ping = 0x02,
-
-    Unknown = u8::MAX,
-}
-
 #[allow(dead_code)]
 struct User {
     pub client_id: Uuid,
@@ -211,59 +186,45 @@ impl User {
 
 static MAP: OnceLock<Mutex<HashMap<Uuid, User>>> = OnceLock::new();
 
-async fn await_commands(connection: Connection) -> anyhow::Result<()> {
-    while let Ok((mut send, mut recv)) = connection.accept_bi().await {
-        let command = recv.read_u8().await?;
-        match Command::from_u8(command).unwrap_or(Command::Unknown) {
-            Command::Login => {
-                println!("> Login");
-
-                let payload: LoginInput = LoginInput::read_from_recv_stream(&mut recv).await?;
-
-                if payload.username() == "test" && payload.password() == "test" {
-                    let uuid = Uuid::new_v4();
-
-                    MAP.get_or_init(|| Mutex::new(HashMap::new()))
-                        .lock()
-                        .await
-                        .insert(uuid, User::new(uuid));
-
-                    send.write_u8(ServerResponse::Success as u8).await?;
-                    let output: LoginOutput = LoginOutput::new(uuid);
-                    output.write_to_send_stream(&mut send).await?;
-                } else {
-                    send.write_u8(ServerResponse::Error as u8).await?;
-                    send.write_all("Invalid username or password.".as_bytes())
-                        .await?;
-                }
-
-                send.finish()
-                    .await
-                    .map_err(|e| anyhow!("failed to shutdown stream: {}", e))?;
-            }
-            Command::Ping => {
-                println!("> Ping");
-
-                let input: PingInput = PingInput::read_from_recv_stream(&mut recv).await?;
-
-                {
-                    let guard = MAP.get().unwrap().lock().await;
-                    let user: Option<&User> = guard.get(&input.client_id());
-                    assert!(user.is_some());
-                }
+/// Serves the calls of a connection, `auth` being set once it logged in.
+struct PingServer {
+    auth: AuthState,
+}
 
-                send.write_u8(ServerResponse::Success as u8).await?;
-                let output: PingOutput = PingOutput::new(input.iteration());
-                output.write_to_send_stream(&mut send).await?;
-                send.finish()
-                    .await
-                    .map_err(|e| anyhow!("failed to shutdown stream: {}", e))?;
-            }
-            Command::Unknown => {
-                println!("> Nothing");
-            }
+impl PingService for PingServer {
+    async fn login(&self, input: LoginInput) -> Response<LoginOutput> {
+        if input.username() == "test" && input.password() == "test" {
+            let uuid = Uuid::new_v4();
+
+            MAP.get_or_init(|| Mutex::new(HashMap::new()))
+                .lock()
+                .await
+                .insert(uuid, User::new(uuid));
+            self.auth.set_authenticated();
+
+            Ok(LoginOutput::new(uuid))
+        } else {
+            Err(ResponseError::new(
+                ErrorCode::UNAUTHENTICATED,
+                "Invalid username or password.",
+            ))
         }
     }
 
-    Ok(())
+    async fn ping(&self, input: PingInput) -> Response<PingOutput> {
+        let known_user = MAP
+            .get_or_init(|| Mutex::new(HashMap::new()))
+            .lock()
+            .await
+            .contains_key(&input.client_id());
+
+        if known_user {
+            Ok(PingOutput::new(input.iteration()))
+        } else {
+            Err(
+                ResponseError::new(ErrorCode::UNAUTHENTICATED, "Log in before pinging!")
+                    .with_details(format_args!("unknown client {}", input.client_id())),
+            )
+        }
+    }
 }
@@ -1,5 +1,12 @@
-mod login;
-mod ping;
+//! Generated from `ping.idl` by build.rs, along with the [PingService] called by the clients.
 
-pub use login::{LoginInput, LoginOutput};
-pub use ping::{PingInput, PingOutput};
+include!(concat!(env!("OUT_DIR"), "/ping_protocol.rs"));
+
+use example_core::Response;
+
+/// Called by a client on a bi stream per call.
+#[lib::service]
+pub trait PingService {
+    async fn login(&self, input: LoginInput) -> Response<LoginOutput>;
+    async fn ping(&self, input: PingInput) -> Response<PingOutput>;
+}
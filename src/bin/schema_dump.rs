@@ -0,0 +1,40 @@
+//! Prints the schema of every type of the ping and chat protocols as JSON, for peers implemented
+//! in other languages.
+//!
+//! Run with `cargo run --bin schema_dump > schema.json`. The output maps each protocol module to
+//! the definitions of its types, see [example_core::schema]. The command enums give the ids of
+//! the calls and of the messages pushed by the chat server.
+
+#[allow(unused)]
+mod chat;
+#[allow(unused)]
+mod protocol;
+
+use std::collections::BTreeMap;
+
+use example_core::schema::Definitions;
+use example_core::ResponseError;
+
+fn main() -> Result<(), serde_json::Error> {
+    let mut chat = Definitions::new();
+    chat.add::<chat::protocol::ChatServiceMethod>();
+    chat.add::<chat::protocol::ClientCommand>();
+    chat.add::<chat::protocol::LoginInput>();
+    chat.add::<chat::protocol::Message>();
+    chat.add::<chat::protocol::SendMessageInput>();
+    chat.add::<chat::protocol::User>();
+    chat.add::<ResponseError>();
+
+    let mut ping = Definitions::new();
+    ping.add::<protocol::PingServiceMethod>();
+    ping.add::<protocol::LoginInput>();
+    ping.add::<protocol::LoginOutput>();
+    ping.add::<protocol::PingInput>();
+    ping.add::<protocol::PingOutput>();
+    ping.add::<ResponseError>();
+
+    let protocols = BTreeMap::from([("chat::protocol", chat), ("protocol", ping)]);
+    println!("{}", serde_json::to_string_pretty(&protocols)?);
+
+    Ok(())
+}
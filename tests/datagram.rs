@@ -0,0 +1,68 @@
+//! Values sent in datagrams between two endpoints on the loopback interface.
+
+#[allow(unused)]
+#[path = "../src/bin/common/mod.rs"]
+mod common;
+
+use example_core::{send_datagram, Bytes, DatagramRecv, PayloadError};
+use quinn::Connection;
+
+use crate::common::{make_client_endpoint, make_server_endpoint};
+
+/// Connects a client to a server, returning the connections of the server and of the client.
+async fn connect() -> (Connection, Connection) {
+    let (server, server_cert) = make_server_endpoint("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let client = make_client_endpoint("127.0.0.1:0".parse().unwrap(), &[&server_cert]).unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    tokio::join!(
+        async { server.accept().await.unwrap().await.unwrap() },
+        async {
+            client
+                .connect(server_addr, "localhost")
+                .unwrap()
+                .await
+                .unwrap()
+        }
+    )
+}
+
+#[tokio::test]
+async fn datagrams_carry_one_value() {
+    let (server, client) = connect().await;
+    let mut recv = DatagramRecv::<(u32, String)>::new(server);
+
+    send_datagram(&client, &(7, "typing".to_string())).unwrap();
+    assert_eq!(recv.recv().await.unwrap(), (7, "typing".to_string()));
+
+    // a datagram with a byte the value doesn't use only fails its own value
+    client
+        .send_datagram(Bytes::from_static(&[0, 0, 0, 1, 0, 0]))
+        .unwrap();
+    assert!(matches!(
+        recv.recv().await,
+        Err(PayloadError::TrailingBytes { len: 1 })
+    ));
+    send_datagram(&client, &(8, String::new())).unwrap();
+    assert_eq!(recv.recv().await.unwrap(), (8, String::new()));
+}
+
+#[tokio::test]
+async fn values_larger_than_a_datagram_fail() {
+    let (_server, client) = connect().await;
+    let max = client.max_datagram_size().unwrap();
+
+    let value = vec![0_u8; max];
+    match send_datagram(&client, &value) {
+        Err(PayloadError::DatagramTooLarge {
+            len,
+            max: error_max,
+        }) => {
+            assert!(len > max);
+            assert_eq!(error_max, max);
+        }
+        result => panic!("expected DatagramTooLarge, got {result:?}"),
+    }
+}
@@ -0,0 +1,231 @@
+//! Round trips of types deriving `Payload`, through both the buffer and the stream decoders.
+
+use example_core::{Bytes, DecodeContext, DecodeLimits, Encode, Payload};
+
+/// Encodes `value`, then checks that both decoders give it back and use every byte.
+async fn assert_round_trip<T>(value: &T) -> Bytes
+where
+    T: Payload + PartialEq + std::fmt::Debug + Sync,
+{
+    let bytes = value.to_bytes().unwrap();
+    assert_eq!(bytes.len(), value.encoded_len());
+
+    let mut buf = bytes.clone();
+    assert_eq!(&T::decode(&mut buf).unwrap(), value);
+    assert!(buf.is_empty());
+
+    let mut recv: &[u8] = &bytes;
+    assert_eq!(&T::read_from_recv_stream(&mut recv).await.unwrap(), value);
+    assert!(recv.is_empty());
+
+    bytes
+}
+
+/// Fields named like the parameters of the generated methods.
+#[derive(lib::Payload, Debug, PartialEq)]
+enum Shadowing {
+    Named {
+        buf: String,
+        recv: u8,
+        send: Vec<u16>,
+        ctx: Option<u32>,
+        defs: bool,
+    },
+    Tuple(u8, String),
+}
+
+#[tokio::test]
+async fn variant_fields_named_like_parameters() {
+    let bytes = assert_round_trip(&Shadowing::Named {
+        buf: "buf".to_string(),
+        recv: 1,
+        send: vec![2, 3],
+        ctx: Some(4),
+        defs: true,
+    })
+    .await;
+    assert_eq!(bytes[0], 0);
+
+    assert_round_trip(&Shadowing::Tuple(5, "tuple".to_string())).await;
+}
+
+/// A versioned struct as deployed first.
+#[derive(lib::Payload, Debug, PartialEq)]
+#[payload(versioned)]
+struct ProfileV1 {
+    name: String,
+}
+
+/// The same struct once a field was added.
+#[derive(lib::Payload, Debug, PartialEq)]
+#[payload(versioned)]
+struct ProfileV2 {
+    name: String,
+    #[payload(default)]
+    age: Option<u8>,
+}
+
+#[tokio::test]
+async fn default_fields_inside_collections() {
+    let old = vec![
+        ProfileV1 {
+            name: "a".to_string(),
+        },
+        ProfileV1 {
+            name: "b".to_string(),
+        },
+    ];
+    let bytes = assert_round_trip(&old).await;
+
+    // the missing field of the first element doesn't take the bytes of the second one
+    let expected = vec![
+        ProfileV2 {
+            name: "a".to_string(),
+            age: None,
+        },
+        ProfileV2 {
+            name: "b".to_string(),
+            age: None,
+        },
+    ];
+    assert_eq!(
+        Vec::<ProfileV2>::decode(&mut bytes.clone()).unwrap(),
+        expected
+    );
+    let mut recv: &[u8] = &bytes;
+    assert_eq!(
+        Vec::<ProfileV2>::read_from_recv_stream(&mut recv)
+            .await
+            .unwrap(),
+        expected
+    );
+}
+
+#[tokio::test]
+async fn truncated_values_fail() {
+    let bytes = ProfileV2 {
+        name: "a".to_string(),
+        age: Some(30),
+    }
+    .to_bytes()
+    .unwrap();
+
+    for len in 0..bytes.len() {
+        let truncated = bytes.slice(..len);
+
+        let error = ProfileV2::decode(&mut truncated.clone()).unwrap_err();
+        assert!(error.is_unexpected_end(), "{len} bytes: {error}");
+        let mut recv: &[u8] = &truncated;
+        let error = ProfileV2::read_from_recv_stream(&mut recv)
+            .await
+            .unwrap_err();
+        assert!(error.is_unexpected_end(), "{len} bytes: {error}");
+    }
+}
+
+#[tokio::test]
+async fn failed_decodes_leave_the_context() {
+    let value = vec![ProfileV1 {
+        name: "a".to_string(),
+    }];
+    let bytes = value.to_bytes().unwrap();
+    let truncated = bytes.slice(..bytes.len() - 1);
+    // just deep enough for the vector and the struct, so a level left behind fails the next decode
+    let mut ctx = DecodeContext::new(DecodeLimits {
+        max_depth: 2,
+        ..DecodeLimits::default()
+    });
+
+    let error = Vec::<ProfileV1>::decode_with(&mut truncated.clone(), &mut ctx).unwrap_err();
+    assert!(error.is_unexpected_end(), "{error}");
+    assert_eq!(
+        Vec::<ProfileV1>::decode_with(&mut bytes.clone(), &mut ctx).unwrap(),
+        value
+    );
+
+    let mut recv: &[u8] = &truncated;
+    let error = Vec::<ProfileV1>::read_with(&mut recv, &mut ctx)
+        .await
+        .unwrap_err();
+    assert!(error.is_unexpected_end(), "{error}");
+    let mut recv: &[u8] = &bytes;
+    assert_eq!(
+        Vec::<ProfileV1>::read_with(&mut recv, &mut ctx)
+            .await
+            .unwrap(),
+        value
+    );
+}
+
+/// A struct that isn't versioned, as deployed first.
+#[derive(lib::Payload, Debug, PartialEq)]
+struct NoteV1 {
+    text: String,
+}
+
+/// The same struct once trailing fields were added.
+#[derive(lib::Payload, Debug, PartialEq)]
+struct NoteV2 {
+    text: String,
+    #[payload(default)]
+    pinned: bool,
+    #[payload(default)]
+    tags: Vec<String>,
+}
+
+#[tokio::test]
+async fn trailing_default_fields() {
+    let new = NoteV2 {
+        text: "note".to_string(),
+        pinned: true,
+        tags: vec!["a".to_string()],
+    };
+    assert_round_trip(&new).await;
+
+    let old = assert_round_trip(&NoteV1 {
+        text: "note".to_string(),
+    })
+    .await;
+    let expected = NoteV2 {
+        text: "note".to_string(),
+        pinned: false,
+        tags: vec![],
+    };
+    assert_eq!(NoteV2::decode(&mut old.clone()).unwrap(), expected);
+    let mut recv: &[u8] = &old;
+    assert_eq!(
+        NoteV2::read_from_recv_stream(&mut recv).await.unwrap(),
+        expected
+    );
+
+    // a field that was only partly received isn't filled
+    let bytes = new.to_bytes().unwrap();
+    let truncated = bytes.slice(..bytes.len() - 1);
+    assert!(NoteV2::decode(&mut truncated.clone())
+        .unwrap_err()
+        .is_unexpected_end());
+    let mut recv: &[u8] = &truncated;
+    assert!(NoteV2::read_from_recv_stream(&mut recv)
+        .await
+        .unwrap_err()
+        .is_unexpected_end());
+}
+
+#[tokio::test]
+async fn nested_values_dont_fill_default_fields() {
+    let old = vec![NoteV1 {
+        text: "note".to_string(),
+    }]
+    .to_bytes()
+    .unwrap();
+
+    // the end of the `Vec` isn't the end of its element
+    assert!(Vec::<NoteV2>::decode(&mut old.clone())
+        .unwrap_err()
+        .is_unexpected_end());
+    let mut recv: &[u8] = &old;
+    assert!(Vec::<NoteV2>::read_from_recv_stream(&mut recv)
+        .await
+        .unwrap_err()
+        .is_unexpected_end());
+}
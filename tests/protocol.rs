@@ -0,0 +1,101 @@
+//! Round trips of the protocol types of the examples over in-memory streams, without any QUIC
+//! endpoint.
+
+#[allow(unused)]
+#[path = "../src/bin/chat/mod.rs"]
+mod chat;
+#[allow(unused)]
+#[path = "../src/bin/protocol/mod.rs"]
+mod protocol;
+
+use std::fmt::Debug;
+
+use example_core::{Command, DecodeLimits, Payload};
+use uuid::Uuid;
+
+use crate::chat::protocol::{client_command, ClientCommand, Message, SendMessageInput, User};
+
+/// Checks that `value` is given back by `decode`, by `read_from_recv_stream` on a slice, and by
+/// `read_from_recv_stream` on a duplex stream small enough to split the value across reads.
+async fn assert_round_trip<T>(value: &T)
+where
+    T: Payload + PartialEq + Debug + Sync,
+{
+    let bytes = value.to_bytes().unwrap();
+    assert_eq!(bytes.len(), value.encoded_len());
+
+    let mut buf = bytes.clone();
+    assert_eq!(&T::decode(&mut buf).unwrap(), value);
+    assert!(buf.is_empty());
+
+    let mut recv: &[u8] = &bytes;
+    assert_eq!(&T::read_from_recv_stream(&mut recv).await.unwrap(), value);
+    assert!(recv.is_empty());
+
+    let (mut send, mut recv) = tokio::io::duplex(3);
+    let (written, read) = tokio::join!(
+        value.write_to_send_stream(&mut send),
+        T::read_from_recv_stream(&mut recv)
+    );
+    written.unwrap();
+    assert_eq!(&read.unwrap(), value);
+}
+
+fn user() -> User {
+    User::new(Uuid::from_u128(0x1234), "alice")
+}
+
+#[tokio::test]
+async fn chat_types() {
+    assert_round_trip(&chat::protocol::LoginInput::new("alice")).await;
+    assert_round_trip(&user()).await;
+    assert_round_trip(&User::new(Uuid::nil(), "")).await;
+    assert_round_trip(&SendMessageInput::new("hello, world")).await;
+    assert_round_trip(&Message::new("hello", Some(user()))).await;
+    assert_round_trip(&Message::new("alice has entered the chat!", None)).await;
+    assert_round_trip(&vec![
+        Message::new("é".repeat(200), Some(user())),
+        Message::new("", None),
+    ])
+    .await;
+}
+
+#[tokio::test]
+async fn ping_types() {
+    let client_id = Uuid::from_u128(42);
+
+    assert_round_trip(&protocol::LoginInput::new("test", "secret")).await;
+    assert_round_trip(&protocol::LoginOutput::new(client_id)).await;
+    assert_round_trip(&protocol::PingInput::new(client_id, u32::MAX)).await;
+    assert_round_trip(&protocol::PingOutput::new(7)).await;
+}
+
+#[tokio::test]
+async fn client_commands() {
+    assert_round_trip(&ClientCommand::NewMessage).await;
+    assert_round_trip(&ClientCommand::Unknown(200)).await;
+
+    let messages = vec![
+        Message::new("hello", Some(user())),
+        Message::new("bye", None),
+    ];
+    let (mut send, mut recv) = tokio::io::duplex(5);
+    let send_command = async {
+        client_command::NewMessage::write_input(&mut send, &messages[..])
+            .await
+            .unwrap();
+        drop(send);
+    };
+    let receive_command = async {
+        let command = ClientCommand::read_from_recv_stream(&mut recv)
+            .await
+            .unwrap();
+        assert_eq!(command, ClientCommand::NewMessage);
+
+        client_command::NewMessage::read_input(&mut recv, &DecodeLimits::default())
+            .await
+            .unwrap()
+    };
+    let ((), received) = tokio::join!(send_command, receive_command);
+    assert_eq!(received, messages);
+}
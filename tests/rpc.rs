@@ -0,0 +1,236 @@
+//! Calls to a service between two endpoints on the loopback interface.
+
+#[allow(unused)]
+#[path = "../src/bin/common/mod.rs"]
+mod common;
+
+use std::time::Duration;
+
+use example_core::rpc::{ConnectionHandler, Deadlines, ResetCodes};
+use example_core::{Encode, ErrorCode, PayloadError, Response, ResponseError, RpcError};
+use quinn::{Connection, ReadError, ReadToEndError};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+use crate::common::{make_client_endpoint, make_server_endpoint};
+
+#[lib::service]
+pub trait TestService {
+    async fn echo(&self, input: String) -> Response<String>;
+    async fn fail(&self) -> Response<()>;
+    /// Never answers, until the call is abandoned.
+    async fn wait(&self) -> Response<()>;
+}
+
+/// Reports the calls of `wait` starting and being dropped.
+struct TestServer {
+    started: mpsc::UnboundedSender<()>,
+    dropped: mpsc::UnboundedSender<()>,
+}
+
+/// Reports being dropped, along with the future holding it.
+struct OnDrop(mpsc::UnboundedSender<()>);
+
+impl Drop for OnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.send(());
+    }
+}
+
+impl TestService for TestServer {
+    async fn echo(&self, input: String) -> Response<String> {
+        Ok(input)
+    }
+
+    async fn fail(&self) -> Response<()> {
+        Err(ResponseError::new(ErrorCode::NOT_FOUND, "nothing here"))
+    }
+
+    async fn wait(&self) -> Response<()> {
+        let _dropped = OnDrop(self.dropped.clone());
+        let _ = self.started.send(());
+
+        std::future::pending().await
+    }
+}
+
+/// The calls of `wait` starting and being dropped on the server.
+struct Events {
+    started: mpsc::UnboundedReceiver<()>,
+    dropped: mpsc::UnboundedReceiver<()>,
+}
+
+/// Connects a client to a server running `handler` on a [TestServer], returning the connection of
+/// the client.
+async fn serve(
+    handler: impl FnOnce(
+        TestServiceServer<TestServer>,
+    ) -> ConnectionHandler<TestServiceServer<TestServer>>,
+) -> (Connection, Events) {
+    let (server, server_cert) = make_server_endpoint("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let client = make_client_endpoint("127.0.0.1:0".parse().unwrap(), &[&server_cert]).unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    let (started_send, started) = mpsc::unbounded_channel();
+    let (dropped_send, dropped) = mpsc::unbounded_channel();
+    let handler = handler(TestServiceServer::new(TestServer {
+        started: started_send,
+        dropped: dropped_send,
+    }));
+    tokio::spawn(async move {
+        let connection = server.accept().await.unwrap().await.unwrap();
+
+        handler.serve(&connection).await
+    });
+
+    let connection = client
+        .connect(server_addr, "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+
+    (connection, Events { started, dropped })
+}
+
+#[tokio::test]
+async fn calls_are_answered() {
+    let (connection, _) = serve(ConnectionHandler::new).await;
+    let client = TestServiceClient::new(connection);
+
+    assert_eq!(client.echo("hello".to_string()).await.unwrap(), "hello");
+    match client.fail().await {
+        Err(RpcError::Remote(e)) => {
+            assert_eq!(e.code, ErrorCode::NOT_FOUND);
+            assert_eq!(e.message, "nothing here");
+        }
+        result => panic!("expected a remote error, got {result:?}"),
+    }
+
+    // the calls run concurrently on their own streams
+    let (first, second) = tokio::join!(
+        client.echo("first".to_string()),
+        client.echo("second".to_string())
+    );
+    assert_eq!(first.unwrap(), "first");
+    assert_eq!(second.unwrap(), "second");
+}
+
+#[tokio::test]
+async fn unknown_methods_reset_the_stream() {
+    let (connection, _) = serve(ConnectionHandler::new).await;
+
+    let (mut send, mut recv) = connection.open_bi().await.unwrap();
+    200_u8.write_to_send_stream(&mut send).await.unwrap();
+    send.finish().await.unwrap();
+
+    match recv.read_to_end(1024).await {
+        Err(ReadToEndError::Read(ReadError::Reset(code))) => {
+            assert_eq!(code, ResetCodes::default().failed);
+        }
+        result => panic!("expected a reset stream, got {result:?}"),
+    }
+}
+
+#[tokio::test]
+async fn calls_over_the_cap_are_rate_limited() {
+    let (connection, mut events) =
+        serve(|server| ConnectionHandler::new(server).with_max_concurrent_calls(1)).await;
+    let client = TestServiceClient::new(connection);
+
+    let waiting = tokio::spawn({
+        let client = client.clone();
+
+        async move { client.wait().await }
+    });
+    events.started.recv().await.unwrap();
+
+    assert!(matches!(
+        client.echo("refused".to_string()).await,
+        Err(RpcError::RateLimited)
+    ));
+
+    // dropping the call frees its slot
+    waiting.abort();
+    let _ = waiting.await;
+    loop {
+        match client.echo("accepted".to_string()).await {
+            Ok(output) => break assert_eq!(output, "accepted"),
+            // the server may not have seen the call being dropped yet
+            Err(RpcError::RateLimited) => tokio::task::yield_now().await,
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+}
+
+/// How long the tests wait for the server to abandon a call.
+const ABANDON_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[tokio::test]
+async fn slow_calls_exceed_the_client_deadline() {
+    let (connection, mut events) = serve(ConnectionHandler::new).await;
+    let deadlines =
+        Deadlines::new().with_command::<test_service_method::Wait>(Duration::from_millis(50));
+    let client = TestServiceClient::new(connection).with_deadlines(deadlines);
+
+    assert!(matches!(
+        client.wait().await,
+        Err(RpcError::DeadlineExceeded)
+    ));
+    // the commands without a deadline aren't bounded
+    assert_eq!(client.echo("hello".to_string()).await.unwrap(), "hello");
+
+    // the client reset the stream, so the server abandons the call
+    events.started.recv().await.unwrap();
+    timeout(ABANDON_TIMEOUT, events.dropped.recv())
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn slow_calls_exceed_the_server_deadline() {
+    let (connection, mut events) = serve(|server| {
+        ConnectionHandler::new(server)
+            .with_deadlines(Deadlines::new().with_default(Duration::from_millis(50)))
+    })
+    .await;
+    let client = TestServiceClient::new(connection);
+
+    match client.wait().await {
+        Err(RpcError::Payload(e)) => assert!(matches!(
+            e.kind(),
+            PayloadError::Read(ReadError::Reset(code))
+                if *code == ResetCodes::default().deadline_exceeded
+        )),
+        result => panic!("expected a reset stream, got {result:?}"),
+    }
+    events.started.recv().await.unwrap();
+    timeout(ABANDON_TIMEOUT, events.dropped.recv())
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn dropped_calls_are_abandoned() {
+    let (connection, mut events) = serve(ConnectionHandler::new).await;
+    let client = TestServiceClient::new(connection);
+
+    // the connection stays open, only the call is dropped
+    let waiting = tokio::spawn({
+        let client = client.clone();
+
+        async move { client.wait().await }
+    });
+    events.started.recv().await.unwrap();
+    assert!(events.dropped.try_recv().is_err());
+
+    waiting.abort();
+    let _ = waiting.await;
+    timeout(ABANDON_TIMEOUT, events.dropped.recv())
+        .await
+        .unwrap()
+        .unwrap();
+}
@@ -0,0 +1,325 @@
+//! The serde data format of `example_core::serde` against the derived `Payload` impls.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use example_core::serde::{decode_with, encode};
+use example_core::{
+    AsyncRead, Bytes, BytesMut, DecodeContext, Encode, Payload, PayloadError, Serde,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::io::ReadBuf;
+
+/// Checks that serializing `value` gives the bytes of its `Payload` impl, and that both decode
+/// the bytes of the other.
+fn assert_same_encoding<T>(value: &T)
+where
+    T: Payload + Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let payload = value.to_bytes().unwrap();
+    let mut serialized = BytesMut::new();
+    encode(value, &mut serialized).unwrap();
+    assert_eq!(serialized, payload);
+    assert_eq!(Serde(value).to_bytes().unwrap(), payload);
+    assert_eq!(Serde(value).encoded_len(), payload.len());
+
+    let mut buf = payload.clone();
+    let deserialized: T = decode_with(&mut buf, &mut DecodeContext::default()).unwrap();
+    assert_eq!(&deserialized, value);
+    assert!(buf.is_empty());
+
+    let mut buf = serialized.freeze();
+    assert_eq!(&T::decode(&mut buf).unwrap(), value);
+}
+
+#[derive(lib::Payload, Serialize, Deserialize, Debug, PartialEq)]
+struct Unit;
+
+#[derive(lib::Payload, Serialize, Deserialize, Debug, PartialEq)]
+struct Tuple(u16, String);
+
+#[derive(lib::Payload, Serialize, Deserialize, Debug, PartialEq)]
+struct Everything {
+    byte: u8,
+    signed: i32,
+    wide: u128,
+    flag: bool,
+    letter: char,
+    ratio: f64,
+    text: String,
+    missing: Option<u16>,
+    present: Option<String>,
+    list: Vec<String>,
+    map: BTreeMap<String, u32>,
+    tuple: Tuple,
+    pair: (i8, u64),
+    unit: Unit,
+    status: Status,
+    shapes: Vec<Shape>,
+}
+
+#[derive(lib::Payload, Serialize, Deserialize, Debug, PartialEq)]
+enum Status {
+    Online,
+    Away,
+    Offline,
+}
+
+#[derive(lib::Payload, Serialize, Deserialize, Debug, PartialEq)]
+enum Shape {
+    Point,
+    Circle(f32),
+    Rectangle { width: u32, height: u32 },
+    Labelled(String, Box<Tuple>),
+}
+
+fn everything() -> Everything {
+    Everything {
+        byte: 7,
+        signed: -123_456,
+        wide: u128::MAX - 1,
+        flag: true,
+        letter: 'é',
+        ratio: 0.25,
+        text: "hello".to_string(),
+        missing: None,
+        present: Some("there".to_string()),
+        list: vec!["a".to_string(), String::new(), "c".repeat(100)],
+        map: BTreeMap::from([("one".to_string(), 1), ("two".to_string(), 2)]),
+        tuple: Tuple(3, "tuple".to_string()),
+        pair: (-1, u64::MAX),
+        unit: Unit,
+        status: Status::Away,
+        shapes: vec![
+            Shape::Point,
+            Shape::Circle(1.5),
+            Shape::Rectangle {
+                width: 2,
+                height: 3,
+            },
+            Shape::Labelled("boxed".to_string(), Box::new(Tuple(9, "nine".to_string()))),
+        ],
+    }
+}
+
+#[test]
+fn structs() {
+    assert_same_encoding(&Unit);
+    assert_same_encoding(&Tuple(1, "one".to_string()));
+    assert_same_encoding(&everything());
+}
+
+#[test]
+fn enums() {
+    assert_same_encoding(&Status::Offline);
+    assert_same_encoding(&Shape::Circle(-2.0));
+    assert_same_encoding(&Shape::Rectangle {
+        width: 4,
+        height: 5,
+    });
+}
+
+#[test]
+fn collections() {
+    assert_same_encoding(&Some(vec![1u32, 2, 3]));
+    assert_same_encoding(&Option::<Vec<u32>>::None);
+    assert_same_encoding(&vec![vec![1u8], vec![], vec![2, 3]]);
+    assert_same_encoding(&BTreeMap::from([
+        (1u8, "a".to_string()),
+        (2, "b".to_string()),
+    ]));
+    // long enough for a 2 byte length
+    assert_same_encoding(&vec![0u16; 300]);
+}
+
+#[derive(lib::Payload, Serialize, Deserialize, Debug, PartialEq)]
+struct VarInts {
+    #[payload(varint)]
+    #[serde(with = "example_core::serde::varint")]
+    small: u32,
+    #[payload(varint)]
+    #[serde(with = "example_core::serde::varint")]
+    large: u64,
+    #[payload(varint)]
+    #[serde(with = "example_core::serde::varint")]
+    negative: i64,
+    fixed: u64,
+}
+
+#[test]
+fn varint_fields() {
+    let value = VarInts {
+        small: 5,
+        large: 1 << 40,
+        negative: -300,
+        fixed: 1 << 40,
+    };
+    assert_same_encoding(&value);
+    assert_eq!(value.encoded_len(), 1 + 8 + 2 + 8);
+}
+
+/// Explicit discriminants, which serde doesn't see.
+#[derive(lib::Payload, Serialize, Deserialize, Debug, PartialEq)]
+enum Explicit {
+    First = 1,
+    Second = 4,
+}
+
+#[test]
+fn explicit_discriminants_differ() {
+    let mut serialized = BytesMut::new();
+    encode(&Explicit::Second, &mut serialized).unwrap();
+
+    assert_eq!(Explicit::Second.to_bytes().unwrap(), [4][..]);
+    assert_eq!(serialized, [1][..]);
+}
+
+#[test]
+fn serde_wrapper_matches_the_derive() {
+    let value = everything();
+    assert_eq!(Serde(&value).to_bytes().unwrap(), value.to_bytes().unwrap());
+
+    let shape = Shape::Labelled("label".to_string(), Box::new(Tuple(1, "one".to_string())));
+    assert_eq!(Serde(&shape).to_bytes().unwrap(), shape.to_bytes().unwrap());
+
+    let option = Some(Status::Online);
+    assert_eq!(
+        Serde(&option).to_bytes().unwrap(),
+        option.to_bytes().unwrap()
+    );
+
+    let list = vec![Tuple(1, "a".to_string()), Tuple(2, "b".to_string())];
+    assert_eq!(Serde(&list).to_bytes().unwrap(), list.to_bytes().unwrap());
+
+    let map = BTreeMap::from([("a".to_string(), vec![1u64]), ("b".to_string(), vec![])]);
+    assert_eq!(Serde(&map).to_bytes().unwrap(), map.to_bytes().unwrap());
+}
+
+/// Reads `data`, counting the calls to `poll_read` and, when `chunk` is set, returning
+/// `Poll::Pending` before each chunk of that many bytes.
+struct TestStream {
+    data: Vec<u8>,
+    position: usize,
+    chunk: Option<usize>,
+    chunk_left: usize,
+    polls: usize,
+}
+
+impl TestStream {
+    fn new(data: &[u8], chunk: Option<usize>) -> TestStream {
+        TestStream {
+            data: data.to_vec(),
+            position: 0,
+            chunk,
+            chunk_left: 0,
+            polls: 0,
+        }
+    }
+
+    fn remaining(&self) -> &[u8] {
+        &self.data[self.position..]
+    }
+}
+
+impl AsyncRead for TestStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.polls += 1;
+        if let Some(chunk) = self.chunk {
+            if self.chunk_left == 0 {
+                self.chunk_left = chunk;
+                cx.waker().wake_by_ref();
+
+                return Poll::Pending;
+            }
+        }
+
+        let len = buf
+            .remaining()
+            .min(self.remaining().len())
+            .min(self.chunk.map_or(usize::MAX, |_| self.chunk_left));
+        buf.put_slice(&self.remaining()[..len]);
+        self.position += len;
+        self.chunk_left -= len.min(self.chunk_left);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn serde_values_are_read_without_what_follows() {
+    let value = everything();
+    let mut stream = value.to_bytes().unwrap().to_vec();
+    stream.push(42);
+
+    for chunk in [None, Some(1), Some(7)] {
+        let mut recv = TestStream::new(&stream, chunk);
+        let read = Serde::<Everything>::read_from_recv_stream(&mut recv)
+            .await
+            .unwrap();
+        assert_eq!(read.0, value);
+        assert_eq!(recv.remaining(), [42]);
+    }
+}
+
+#[tokio::test]
+async fn large_serde_values_read_each_element_once() {
+    let value = vec![7u8; 1024 * 1024];
+    let encoded = value.to_bytes().unwrap();
+
+    let mut recv = TestStream::new(&encoded, None);
+    let read = Serde::<Vec<u8>>::read_from_recv_stream(&mut recv)
+        .await
+        .unwrap();
+    assert_eq!(read.0, value);
+
+    // two reads for the length, whose first byte tells its size, and one for each element, none
+    // decoding the value again
+    assert_eq!(recv.polls, 2 + value.len());
+}
+
+#[tokio::test]
+async fn truncated_serde_values_fail() {
+    let encoded = everything().to_bytes().unwrap();
+    let truncated = encoded.slice(..encoded.len() - 1);
+
+    let error = Serde::<Everything>::decode(&mut truncated.clone()).unwrap_err();
+    assert!(error.is_unexpected_end());
+
+    let mut recv = TestStream::new(&truncated, Some(5));
+    let error = Serde::<Everything>::read_from_recv_stream(&mut recv)
+        .await
+        .unwrap_err();
+    assert!(error.is_unexpected_end());
+
+    let mut recv: &[u8] = &Bytes::new();
+    let error = Serde::<u8>::read_from_recv_stream(&mut recv)
+        .await
+        .unwrap_err();
+    assert!(error.is_unexpected_end());
+}
+
+#[test]
+fn unknown_option_tags_fail() {
+    let error = decode_with::<Option<u8>>(
+        &mut Bytes::from_static(&[2, 7]),
+        &mut DecodeContext::default(),
+    )
+    .unwrap_err();
+    assert!(
+        matches!(
+            error,
+            PayloadError::UnknownDiscriminant {
+                ty: "Option",
+                value: 2
+            }
+        ),
+        "{error}"
+    );
+}